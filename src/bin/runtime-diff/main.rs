@@ -0,0 +1,1086 @@
+use regex::Regex;
+use runtime_diff::harness::{
+    self, Log, Outcome, RunOptions, TestCommand, TestGroup,
+    baseline::{self, Baseline},
+    breakpoint::Breakpoint,
+    compare,
+    coverage::{self, Coverage},
+    debuglog::DebugLog,
+    decisions::{self, DecisionLog},
+    events::EventLog,
+    export,
+    history::History,
+    init,
+    jitter::Jitter,
+    load_test_file,
+    matrix::{self, Seeds},
+    monitor::Monitor,
+    parse_duration, reduce,
+    report::{self, GroupReport, Report},
+    resume::ResumeFile,
+    rules::{self, Rules, SharedRules},
+    run_group,
+    sandbox::{self, Sandbox},
+    stats,
+    trace::{self, Query, Trace},
+    truncate, unstable,
+    wrapper::{self, Wrapper},
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex},
+    time::Duration,
+};
+use structopt::StructOpt;
+
+#[cfg(feature = "tui")]
+mod tui;
+
+#[derive(StructOpt)]
+#[structopt(setting = structopt::clap::AppSettings::ArgsNegateSubcommands)]
+struct Args {
+    testfile: Option<String>,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+    #[structopt(short = "b", long = "max-breadcumbs", default_value = "32")]
+    max_breadcumbs: usize,
+    /// Checks all the commands agreed on shown with a mismatch, as the last point the
+    /// implementations agreed; they count toward --max-buffer-mem
+    #[structopt(long = "last-agreements", value_name = "n", default_value = "5")]
+    last_agreements: usize,
+    /// Directory where instrumented programs write their binary snapshots
+    #[structopt(long = "snapshot-dir", parse(from_os_str))]
+    snapshot_dir: Option<PathBuf>,
+    /// Number of comparison groups to run concurrently
+    #[structopt(short = "j", long = "jobs", default_value = "1")]
+    jobs: usize,
+    /// Skip comparing the execution context of the commands before running them
+    #[structopt(long = "no-fingerprint")]
+    no_fingerprint: bool,
+    /// Run the named test command twice concurrently to check that its streams are deterministic
+    #[structopt(long = "self-check", value_name = "name")]
+    self_check: Option<String>,
+    /// Run every group once per seed, substituted for ${SEED} and exported as SEED
+    #[structopt(long = "seeds", value_name = "list")]
+    seeds: Option<Seeds>,
+    /// Run every group this many times, bound to ${REPEAT} and exported as REPEAT, and
+    /// report the check sites changing between repeats of the same command
+    #[structopt(long = "repeat", value_name = "n")]
+    repeat: Option<usize>,
+    /// Only run the groups with the given name; ignored with --self-check, which runs a
+    /// group of its own
+    #[structopt(long = "group", value_name = "name")]
+    group: Option<String>,
+    /// Minimize the inputs on which the comparison diverged
+    #[structopt(long = "reduce")]
+    reduce: bool,
+    /// Command minimizing a diverging input in place, given as ${INPUT}, using the
+    /// interestingness test script given as ${TEST}, both substituted already quoted;
+    /// implies --reduce
+    #[structopt(long = "reducer", value_name = "command")]
+    reducer: Option<String>,
+    /// Run the comparison on the given input only, succeeding if it diverges
+    #[structopt(long = "interestingness-test", value_name = "input", hidden = true)]
+    interestingness_test: Option<String>,
+    /// Command rendering the payloads of a diverging check, given the files holding them
+    #[structopt(long = "diff-tool", value_name = "command")]
+    diff_tool: Option<String>,
+    /// Largest difference between two elements of matching arrays or images
+    #[structopt(long = "tolerance", default_value = "0")]
+    tolerance: f64,
+    /// Directory where the raw output of every command is logged, the report of a mismatch
+    /// showing the lines printed around it
+    #[structopt(long = "log-dir", parse(from_os_str))]
+    log_dir: Option<PathBuf>,
+    /// Run diverging groups again with RUNTIME_DIFF_LEVEL=debug, up to the diverging check
+    #[structopt(long = "debug-rerun")]
+    debug_rerun: bool,
+    /// Stop every command at the check with this number or name, to attach debuggers
+    #[structopt(long = "break-at-check", value_name = "check")]
+    break_at: Option<Breakpoint>,
+    /// Command run with the process ids of the commands still running on a mismatch, which
+    /// stay stopped until it exits (e.g. a script attaching gdb to each of them). The
+    /// commands run in lockstep with the comparison, unless --max-lead allows them ahead, so
+    /// that they are stopped within a check of the divergence, unless they emit checks
+    /// faster than the harness reads them
+    #[structopt(long = "on-divergence", value_name = "command")]
+    on_divergence: Option<String>,
+    /// Run every command under a deterministic execution tool: rr, rr-chaos, single-core
+    /// or any command prefix
+    #[structopt(long = "wrap", value_name = "tool")]
+    wrap: Option<Wrapper>,
+    /// Fail groups with a suspicious configuration, such as commands running the same
+    /// command line, instead of warning about it
+    #[structopt(long = "strict-config")]
+    strict_config: bool,
+    /// Bytes the harness may hold for the messages of a group (with an optional K, M or G
+    /// suffix), beyond which it keeps fewer breadcrumbs and then checks as digests only
+    #[structopt(long = "max-buffer-mem", value_name = "bytes", parse(try_from_str = parse_bytes))]
+    max_buffer_mem: Option<usize>,
+    /// Compare how long the commands take between checks instead of their values, against
+    /// the thresholds of the test file
+    #[structopt(long = "bench")]
+    bench: bool,
+    /// Warn when a command goes this long without emitting a check (with an optional ms, s
+    /// or m suffix), to catch hangs and livelocks
+    #[structopt(long = "check-deadline", value_name = "duration", parse(try_from_str = parse_duration))]
+    check_deadline: Option<Duration>,
+    /// Fail the group when a command goes past the check deadline
+    #[structopt(long = "fail-on-deadline", requires = "check-deadline")]
+    fail_on_deadline: bool,
+    /// Write lifecycle events of the run as JSON lines to the given file, or to an inherited
+    /// file descriptor given as fd:N
+    #[structopt(long = "events", value_name = "target")]
+    events: Option<String>,
+    /// File keeping the outcomes of the latest runs of every group, updated by this run
+    #[structopt(long = "history", value_name = "file", parse(from_os_str))]
+    history: Option<PathBuf>,
+    /// Quarantine the groups that failed this many times in the history while passing in
+    /// other runs
+    #[structopt(long = "auto-quarantine", value_name = "n", requires = "history")]
+    auto_quarantine: Option<usize>,
+    /// Bytes of a message shown in reports, longer ones being truncated to this length with
+    /// a digest of their full text and the file holding it; 0 for no limit
+    #[structopt(
+        long = "max-payload-bytes",
+        value_name = "bytes",
+        default_value = truncate::DEFAULT_MAX_PAYLOAD_BYTES
+    )]
+    max_payload_bytes: usize,
+    /// Log the decisions of the harness itself to the given file: which command it waits
+    /// on, evicted breadcrumbs, backpressure and why lines were passed through
+    #[structopt(long = "debug-harness", value_name = "file", parse(from_os_str))]
+    debug_harness: Option<PathBuf>,
+    /// Record every comparison decision of the run to the given file, to render its report
+    /// again with replay-decisions
+    #[structopt(long = "decision-log", value_name = "file", parse(from_os_str))]
+    decision_log: Option<PathBuf>,
+    /// Break down the checks of the run by channel and check name in the summary, with
+    /// their bytes and mismatches
+    #[structopt(long = "stats")]
+    stats: bool,
+    /// Save the progress of every group to the given file periodically, to resume from
+    /// after a crash
+    #[structopt(long = "checkpoint", value_name = "file", parse(from_os_str))]
+    checkpoint: Option<PathBuf>,
+    /// Restart the groups saved in the checkpoint file from their last agreed check, the
+    /// instrumented programs skipping the checks before it
+    #[structopt(long = "resume", requires = "checkpoint")]
+    resume: bool,
+    /// Run diverging groups again up to the diverging check, reporting the divergences
+    /// that do not reproduce as flaky without failing the run
+    #[structopt(long = "confirm")]
+    confirm: bool,
+    /// Print the output of every command in one block once it ends instead of line by
+    /// line, keeping the output of commands running concurrently apart
+    #[structopt(long = "group-output")]
+    group_output: bool,
+    /// Delay the start of every command by a random duration up to this one (with an
+    /// optional ms, s or m suffix), shuffling their start order in every group run
+    #[structopt(long = "jitter", value_name = "duration", parse(try_from_str = parse_duration))]
+    jitter: Option<Duration>,
+    /// Seed of the start delays drawn by --jitter, to start the commands as in an earlier
+    /// run
+    #[structopt(long = "jitter-seed", value_name = "seed", requires = "jitter")]
+    jitter_seed: Option<u64>,
+    /// Stop reading the output of a command once it is this many checks ahead of the
+    /// slowest one, bounding the memory held for it
+    #[structopt(long = "max-lead")]
+    max_lead: Option<usize>,
+    /// Compare every command against its trace in a baseline, so that groups may run a
+    /// single command
+    #[structopt(long = "baseline", value_name = "name")]
+    baseline: Option<String>,
+    /// Directory holding the baselines
+    #[structopt(
+        long = "baseline-dir",
+        parse(from_os_str),
+        default_value = baseline::DEFAULT_BASELINE_DIR
+    )]
+    baseline_dir: PathBuf,
+    /// Write the outcome of every group to the given file as a JSON report
+    #[structopt(long = "report", value_name = "file", parse(from_os_str))]
+    report: Option<PathBuf>,
+    /// Record the messages of every command as trace files in the given directory
+    #[structopt(long = "record", value_name = "dir", parse(from_os_str))]
+    record: Option<PathBuf>,
+    /// Show a live dashboard of the running commands
+    #[cfg(feature = "tui")]
+    #[structopt(long = "tui")]
+    tui: bool,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// Check a test file for common mistakes
+    Lint {
+        testfile: String,
+        /// Print diagnostics as JSON lines
+        #[structopt(long = "json")]
+        json: bool,
+    },
+    /// Search a recorded trace, printing the matching regions
+    Query {
+        #[structopt(parse(from_os_str))]
+        trace: PathBuf,
+        /// Only print the entries matching the regular expression
+        #[structopt(long = "grep", value_name = "pattern")]
+        grep: Option<Regex>,
+        /// Only print the entries around the check with the given number, counting from 1
+        #[structopt(long = "around-check", value_name = "n")]
+        around_check: Option<usize>,
+        /// Number of entries printed before and after every selected one
+        #[structopt(short = "C", long = "context", default_value = "3")]
+        context: usize,
+    },
+    /// Compare two recorded traces without running anything
+    Compare {
+        #[structopt(parse(from_os_str))]
+        first: PathBuf,
+        #[structopt(parse(from_os_str))]
+        second: PathBuf,
+        /// How checks are matched: lockstep, lcs or keyed
+        #[structopt(long = "mode", default_value = "lockstep")]
+        mode: compare::Mode,
+        /// Number of entries shown around every difference
+        #[structopt(short = "C", long = "context", default_value = "5")]
+        context: usize,
+    },
+    /// Work with the JSON reports written by --report
+    Report(ReportCommand),
+    /// Manage baselines, recorded runs later runs are validated against
+    Baseline {
+        /// Directory holding the baselines
+        #[structopt(
+            long = "baseline-dir",
+            parse(from_os_str),
+            default_value = baseline::DEFAULT_BASELINE_DIR
+        )]
+        baseline_dir: PathBuf,
+        #[structopt(subcommand)]
+        command: BaselineCommand,
+    },
+    /// Convert a recorded trace for analysis with other tools
+    Export {
+        #[structopt(parse(from_os_str))]
+        trace: PathBuf,
+        /// Output format: jsonl or csv
+        #[structopt(long = "format", default_value = "jsonl")]
+        format: export::Format,
+    },
+    /// Run the commands of a harness on another machine, for test commands declaring the
+    /// address of this agent as their host. Requests must present the secret given in
+    /// RUNTIME_DIFF_AGENT_TOKEN or the token file, which the harness reads from
+    /// RUNTIME_DIFF_AGENT_TOKEN. The agent runs any command it is sent: keep it on the
+    /// loopback address and reach it through an SSH tunnel, e.g.
+    /// `ssh -L 7070:127.0.0.1:7070 host`
+    Agent {
+        /// Address to listen on
+        #[structopt(long = "listen", value_name = "address", default_value = harness::agent::DEFAULT_LISTEN)]
+        listen: String,
+        /// File holding the shared secret, instead of RUNTIME_DIFF_AGENT_TOKEN
+        #[structopt(long = "token-file", value_name = "file", parse(from_os_str))]
+        token_file: Option<PathBuf>,
+    },
+    /// Generate a starter test file diffing binaries of the cargo workspace in the current
+    /// directory
+    Init {
+        /// Binary to compare, given at least twice
+        #[structopt(long = "bin", value_name = "name")]
+        bins: Vec<String>,
+        /// File to write the test file to
+        #[structopt(
+            short = "o",
+            long = "output",
+            parse(from_os_str),
+            default_value = init::DEFAULT_TEST_FILE
+        )]
+        output: PathBuf,
+        /// Replace the output file if it exists
+        #[structopt(long = "force")]
+        force: bool,
+    },
+    /// Render the report of a run again from its --decision-log, without running anything
+    ReplayDecisions {
+        #[structopt(parse(from_os_str))]
+        log: PathBuf,
+        /// Report format: text or json
+        #[structopt(long = "format", default_value = "text")]
+        format: decisions::Format,
+        /// List every comparison decision, not only the divergences
+        #[structopt(short = "v", long = "verbose")]
+        verbose: bool,
+    },
+    /// Restrict this process to a sandbox profile, then execute the command; the prefix
+    /// of sandboxed test commands
+    #[structopt(name = wrapper::SANDBOX_EXEC, setting = structopt::clap::AppSettings::Hidden)]
+    SandboxExec {
+        #[structopt(long = "profile")]
+        profile: Sandbox,
+        #[structopt(required = true)]
+        command: Vec<String>,
+    },
+}
+
+#[derive(StructOpt)]
+enum ReportCommand {
+    /// Combine several reports into one, failing unless every group passed in all of them
+    Merge {
+        #[structopt(parse(from_os_str), required = true)]
+        reports: Vec<PathBuf>,
+        /// Write the merged report to the given file
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(StructOpt)]
+enum BaselineCommand {
+    /// Promote the traces recorded with --record in a directory to a new baseline
+    Save {
+        name: String,
+        #[structopt(parse(from_os_str))]
+        recording: PathBuf,
+    },
+    /// Replace the traces of an existing baseline with a new recording
+    Update {
+        name: String,
+        #[structopt(parse(from_os_str))]
+        recording: PathBuf,
+    },
+    /// List the baselines, marking the ones recorded with a different build
+    List,
+    /// Compare the traces recorded in a directory against a baseline
+    Diff {
+        name: String,
+        #[structopt(parse(from_os_str))]
+        recording: PathBuf,
+        /// How checks are matched: lockstep, lcs or keyed
+        #[structopt(long = "mode", default_value = "lockstep")]
+        mode: compare::Mode,
+        /// Number of entries shown around every difference
+        #[structopt(short = "C", long = "context", default_value = "5")]
+        context: usize,
+    },
+}
+
+fn exit_on_error<T>(result: Result<T, harness::Error>) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    })
+}
+
+/// Runs a baseline management command.
+fn manage_baselines(baselines: &Path, command: &BaselineCommand) {
+    let current = baseline::BuildInfo::current();
+    match command {
+        BaselineCommand::Save { name, recording } | BaselineCommand::Update { name, recording } => {
+            let replace = matches!(command, BaselineCommand::Update { .. });
+            let saved = exit_on_error(Baseline::save(baselines, name, recording, replace));
+            println!(
+                "\x1b[1;32mSaved baseline '{}'\x1b[0m in {}",
+                saved.name,
+                saved.dir.display()
+            );
+        }
+        BaselineCommand::List => {
+            for listed in exit_on_error(Baseline::list(baselines)) {
+                let stale = !listed.build.differences(&current).is_empty();
+                println!(
+                    "{}  {}  {}{}",
+                    listed.name,
+                    listed.created,
+                    listed
+                        .build
+                        .git_rev
+                        .as_deref()
+                        .unwrap_or("unknown revision"),
+                    if stale {
+                        "  \x1b[1;33m(stale)\x1b[0m"
+                    } else {
+                        ""
+                    }
+                );
+            }
+        }
+        BaselineCommand::Diff {
+            name,
+            recording,
+            mode,
+            context,
+        } => {
+            let loaded = exit_on_error(Baseline::load(baselines, name));
+            for difference in loaded.build.differences(&current) {
+                println!(
+                    "\x1b[1;41m WARNING \x1b[0m \x1b[1;31mstale baseline, {}\x1b[0m",
+                    difference
+                );
+            }
+            let mut report = String::new();
+            let matched = exit_on_error(loaded.write_diff(&mut report, recording, *mode, *context));
+            print!("{}", report);
+            if !matched {
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// Writes a starter test file for the `bins` of the cargo workspace in the current
+/// directory.
+fn init_test_file(bins: &[String], output: &Path, force: bool) {
+    if output.exists() && !force {
+        eprintln!(
+            "{} already exists, pass --force to replace it",
+            output.display()
+        );
+        std::process::exit(1);
+    }
+    let (binaries, target_dir) = exit_on_error(init::workspace_binaries(Path::new(".")));
+    let content = exit_on_error(init::starter_test_file(&binaries, &target_dir, bins));
+    if let Err(e) = std::fs::write(output, content) {
+        eprintln!("Cannot write {}: {}", output.display(), e);
+        std::process::exit(1);
+    }
+    println!(
+        "Wrote {}, run it with: runtime-diff {}",
+        output.display(),
+        output.display()
+    );
+}
+
+/// Restricts the harness process to `profile`, then replaces it with `command`.
+fn sandbox_exec(profile: &Sandbox, command: &[String]) {
+    use std::os::unix::process::CommandExt;
+
+    if let Err(e) = sandbox::restrict_self(profile) {
+        eprintln!("Cannot sandbox '{}': {}", command.join(" "), e);
+        std::process::exit(1);
+    }
+    let e = std::process::Command::new(&command[0])
+        .args(&command[1..])
+        .exec();
+    eprintln!("Cannot execute '{}': {}", command[0], e);
+    std::process::exit(1);
+}
+
+/// Merges JSON reports, printing the outcome of every group across them.
+fn merge_reports(paths: &[PathBuf], output: Option<&Path>) {
+    let mut reports = Vec::new();
+    for path in paths {
+        match Report::load(path) {
+            Ok(report) => reports.push(report),
+            Err(e) => {
+                eprintln!("Error loading report {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let merged = Report::merge(&reports);
+    let mut summary = String::new();
+    let _ = report::write_summary(&mut summary, &merged, true);
+    print!("{}", summary);
+
+    if let Some(output) = output
+        && let Err(e) = merged.save(output)
+    {
+        eprintln!("Cannot write report {}: {}", output.display(), e);
+        std::process::exit(1);
+    }
+    if !merged.passed() {
+        std::process::exit(1);
+    }
+}
+
+/// Prints the diagnostics for a test file, failing if any of them is an error.
+fn lint(testfile: &str, json: bool) {
+    let test_file = match load_test_file(testfile) {
+        Ok(test_file) => test_file,
+        Err(e) => {
+            eprintln!("Error loading test file: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let dir = Path::new(testfile).parent().unwrap_or(Path::new(""));
+    let diagnostics = harness::lint::lint(&test_file, dir);
+    for diagnostic in &diagnostics {
+        if json {
+            println!("{}", diagnostic.to_json(testfile));
+        } else {
+            println!("{}", diagnostic.to_text(testfile));
+        }
+    }
+
+    if diagnostics
+        .iter()
+        .any(|diagnostic| diagnostic.level == harness::lint::Level::Error)
+    {
+        std::process::exit(1);
+    }
+}
+
+/// Prints the regions of a recorded trace selected by `query`.
+fn query(path: &Path, query: &Query) {
+    let trace = load_trace(path);
+    let regions = match query.regions(&trace) {
+        Ok(regions) => regions,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if regions.is_empty() {
+        std::process::exit(1);
+    }
+
+    let mut out = String::new();
+    let _ = trace::write_regions(&mut out, &trace, query, &regions);
+    print!("{}", out);
+}
+
+fn load_trace(path: &Path) -> Trace {
+    match Trace::load(path) {
+        Ok(trace) => trace,
+        Err(e) => {
+            eprintln!("Error loading trace {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Compares two recorded traces, failing if they differ.
+fn compare(paths: [&Path; 2], mode: compare::Mode, context: usize) {
+    let [first, second] = paths.map(load_trace);
+    let mut report = String::new();
+    let matched =
+        compare::compare_traces(&mut report, [&first, &second], mode, context).unwrap_or(false);
+    let _ = coverage::write_coverage(
+        &mut report,
+        &[&first.name, &second.name],
+        &Coverage::of_traces(&[&first, &second]),
+    );
+    print!("{}", report);
+    if !matched {
+        std::process::exit(1);
+    }
+    println!("\x1b[1;32mThe traces match\x1b[0m");
+}
+
+fn parse_bytes(s: &str) -> Result<usize, String> {
+    let (number, multiplier) = match s.trim().char_indices().last() {
+        Some((i, 'K' | 'k')) => (&s[..i], 1 << 10),
+        Some((i, 'M' | 'm')) => (&s[..i], 1 << 20),
+        Some((i, 'G' | 'g')) => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+    number
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid byte count '{}'", s))
+}
+
+/// Builds a group running two instances of `command`, to compare it against itself.
+fn self_check_group(command: &TestCommand) -> TestGroup {
+    TestGroup::new(
+        &format!("self-check {}", command.name),
+        vec![TestCommand {
+            instances: 2,
+            ..command.clone()
+        }],
+    )
+}
+
+/// Output of a group, prefixed by its label if `prefix_output` and shown on the dashboard
+/// if there is one.
+fn group_log(group: &TestGroup, prefix_output: bool, options: &RunOptions) -> Log {
+    let label = group.label();
+    let log = Log::new(prefix_output.then_some(label.as_str()));
+    match &options.monitor {
+        Some(monitor) if monitor.is_dashboard() => log.captured(monitor.clone()),
+        _ => log,
+    }
+}
+
+/// Runs every group, up to `jobs` at a time, returning the report of each of them.
+///
+/// A group starts once the groups it depends on have passed, and is skipped if any of them
+/// did not.
+fn run_groups(groups: &[TestGroup], options: &RunOptions, jobs: usize) -> Vec<GroupReport> {
+    let prefix_output = groups.len() > 1;
+    let dependencies: Vec<Vec<usize>> = groups
+        .iter()
+        .map(|group| {
+            (0..groups.len())
+                .filter(|other| group.depends_on_group(&groups[*other]))
+                .collect()
+        })
+        .collect();
+    let results: Mutex<Vec<Option<GroupReport>>> = Mutex::new(vec![None; groups.len()]);
+    let started = Mutex::new(vec![false; groups.len()]);
+    let finished = Condvar::new();
+
+    // Picks the next group whose dependencies are done, skipping the ones they failed
+    let next_group = || {
+        let mut results = results.lock().unwrap();
+        loop {
+            let mut started = started.lock().unwrap();
+            let mut waiting = false;
+            for index in 0..groups.len() {
+                if started[index] {
+                    continue;
+                }
+                let outcomes: Option<Vec<_>> = dependencies[index]
+                    .iter()
+                    .map(|dependency| results[*dependency].as_ref().map(|report| report.outcome))
+                    .collect();
+                let Some(outcomes) = outcomes else {
+                    waiting = true;
+                    continue;
+                };
+
+                started[index] = true;
+                match dependencies[index]
+                    .iter()
+                    .zip(outcomes)
+                    .find(|(_, outcome)| !outcome.passed())
+                {
+                    Some((dependency, _)) => {
+                        let reason = format!(
+                            "depends on '{}', which did not pass",
+                            groups[*dependency].label()
+                        );
+                        let log = group_log(&groups[index], prefix_output, options);
+                        log.println(format_args!("\x1b[1;33mSkipped: {}\x1b[0m", reason));
+                        results[index] = Some(GroupReport::skipped(&groups[index], reason));
+                        finished.notify_all();
+                    }
+                    None => return Some(index),
+                }
+            }
+            if !waiting {
+                return None;
+            }
+            drop(started);
+            results = finished.wait(results).unwrap();
+        }
+    };
+
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, groups.len().max(1)) {
+            scope.spawn(|| {
+                while let Some(index) = next_group() {
+                    let group = &groups[index];
+                    let log = group_log(group, prefix_output, options);
+                    let report = GroupReport::new(group, &run_group(group, options, &log));
+                    results.lock().unwrap()[index] = Some(report);
+                    finished.notify_all();
+                }
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .zip(groups)
+        .map(|(report, group)| {
+            report.unwrap_or_else(|| GroupReport::skipped(group, "never started".to_string()))
+        })
+        .collect()
+}
+
+/// Minimizes the input of every group whose checks diverged on it.
+fn reduce_inputs(
+    args: &Args,
+    testfile: &str,
+    groups: &[TestGroup],
+    results: &[Outcome],
+    options: &RunOptions,
+) {
+    let work_dir =
+        std::env::temp_dir().join(format!("runtime-diff-reduced-{}", std::process::id()));
+    if let Err(e) = std::fs::create_dir_all(&work_dir) {
+        eprintln!("Cannot create {}: {}", work_dir.display(), e);
+        return;
+    }
+
+    for (group, outcome) in groups.iter().zip(results) {
+        let Some(input) = group.variable(matrix::INPUT_VARIABLE) else {
+            continue;
+        };
+        if *outcome != Outcome::Mismatch {
+            continue;
+        }
+
+        println!("\x1b[1;33mMinimizing input of {}...\x1b[0m", group.label());
+        let reduction = reduce::Reduction::new(group, options, &work_dir);
+        let minimized = match &args.reducer {
+            Some(reducer) => reduction.run_external(reducer, testfile),
+            None => reduction.run_builtin(),
+        };
+        match minimized {
+            Ok(minimized) => print!("{}", reduce::describe(input, &minimized)),
+            Err(e) => eprintln!("Cannot minimize {}: {}", input, e),
+        }
+    }
+}
+
+pub fn main() {
+    let args = Args::from_args();
+
+    let testfile = match (&args.command, &args.testfile) {
+        (Some(Command::Lint { testfile, json }), _) => return lint(testfile, *json),
+        (
+            Some(Command::Query {
+                trace,
+                grep,
+                around_check,
+                context,
+            }),
+            _,
+        ) => {
+            return query(
+                trace,
+                &Query {
+                    grep: grep.clone(),
+                    around_check: *around_check,
+                    context: *context,
+                },
+            );
+        }
+        (
+            Some(Command::Compare {
+                first,
+                second,
+                mode,
+                context,
+            }),
+            _,
+        ) => return compare([first, second], *mode, *context),
+        (Some(Command::Export { trace, format }), _) => {
+            let mut out = String::new();
+            let _ = export::write_export(&mut out, &load_trace(trace), *format);
+            return print!("{}", out);
+        }
+        (Some(Command::Report(ReportCommand::Merge { reports, output })), _) => {
+            return merge_reports(reports, output.as_deref());
+        }
+        (
+            Some(Command::Baseline {
+                baseline_dir,
+                command,
+            }),
+            _,
+        ) => return manage_baselines(baseline_dir, command),
+        (Some(Command::Agent { listen, token_file }), _) => {
+            let served = harness::agent::read_token(token_file.as_deref())
+                .and_then(|token| harness::agent::serve(listen, &token, &Log::new(None)));
+            if let Err(e) = served {
+                eprintln!("Agent failed: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        (
+            Some(Command::Init {
+                bins,
+                output,
+                force,
+            }),
+            _,
+        ) => return init_test_file(bins, output, *force),
+        (
+            Some(Command::ReplayDecisions {
+                log,
+                format,
+                verbose,
+            }),
+            _,
+        ) => {
+            return print!(
+                "{}",
+                exit_on_error(decisions::replay(log, *format, *verbose))
+            );
+        }
+        (Some(Command::SandboxExec { profile, command }), _) => {
+            return sandbox_exec(profile, command);
+        }
+        (None, Some(testfile)) => testfile,
+        (None, None) => {
+            eprintln!("No test file given");
+            std::process::exit(1);
+        }
+    };
+
+    let mut test_file = match load_test_file(testfile) {
+        Ok(test_file) => test_file,
+        Err(e) => {
+            eprintln!("Error loading test file: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Execute build commands, already done by the run invoking an interestingness test;
+    // they may generate the inputs
+    if args.interestingness_test.is_none()
+        && let Err(e) = test_file.build()
+    {
+        eprintln!("Build commands failed: {}", e);
+        std::process::exit(1);
+    }
+
+    let inputs = match &args.interestingness_test {
+        Some(input) => vec![input.clone()],
+        None => match matrix::collect_inputs(&test_file.inputs) {
+            Ok(inputs) => inputs,
+            Err(e) => {
+                eprintln!("Error collecting inputs: {}", e);
+                std::process::exit(1);
+            }
+        },
+    };
+
+    let groups = match &args.self_check {
+        Some(name) => match test_file.find_command(name) {
+            Some(command) => vec![self_check_group(command)],
+            None => {
+                eprintln!("No test command named '{}' in the test file", name);
+                std::process::exit(1);
+            }
+        },
+        None => std::mem::take(&mut test_file.groups),
+    };
+
+    let groups: Vec<_> = match &args.group {
+        Some(name) if args.self_check.is_none() => groups
+            .into_iter()
+            .filter(|group| &group.name == name)
+            .collect(),
+        _ => groups,
+    };
+
+    let groups = if inputs.is_empty() {
+        groups
+    } else {
+        matrix::expand(groups, matrix::INPUT_VARIABLE, &inputs)
+    };
+
+    let groups = match &args.seeds {
+        Some(Seeds(seeds)) => {
+            let seeds: Vec<_> = seeds.iter().map(|seed| seed.to_string()).collect();
+            matrix::expand(groups, matrix::SEED_VARIABLE, &seeds)
+        }
+        None => groups,
+    };
+
+    let groups = match args.repeat {
+        Some(repeat) if repeat > 1 => {
+            let repeats: Vec<_> = (1..=repeat).map(|repeat| repeat.to_string()).collect();
+            matrix::expand(groups, matrix::REPEAT_VARIABLE, &repeats)
+        }
+        _ => groups,
+    };
+
+    let mut history = args
+        .history
+        .as_deref()
+        .map(|path| exit_on_error(History::load(path)));
+    let mut groups = groups;
+    if let (Some(history), Some(threshold)) = (&history, args.auto_quarantine) {
+        for group in groups.iter_mut().filter(|group| !group.quarantine) {
+            let flaky_runs = history.flaky_runs(&group.label());
+            if flaky_runs >= threshold {
+                println!(
+                    "\x1b[1;33mQuarantining {}, which failed {} of its recent runs\x1b[0m",
+                    group.label(),
+                    flaky_runs
+                );
+                group.quarantine = true;
+            }
+        }
+    }
+
+    // Checks of different channels interleave, so their count locates no agreed point
+    if args.checkpoint.is_some() && !test_file.routes.is_empty() {
+        eprintln!("--checkpoint cannot be used with routed channels");
+        std::process::exit(1);
+    }
+
+    #[cfg(feature = "tui")]
+    let dashboard = args.tui;
+    #[cfg(not(feature = "tui"))]
+    let dashboard = false;
+    let events = args
+        .events
+        .as_deref()
+        .map(|target| exit_on_error(EventLog::open(target)));
+    let options = RunOptions {
+        max_breadcumbs: args.max_breadcumbs,
+        last_agreements: args.last_agreements,
+        snapshot_dir: args.snapshot_dir.clone().unwrap_or_else(|| {
+            std::env::temp_dir().join(format!("runtime-diff-{}", std::process::id()))
+        }),
+        // Both runs of a self-check execute the same binary by design
+        fingerprint: !args.no_fingerprint && args.self_check.is_none(),
+        monitor: (dashboard || events.is_some()).then(|| Arc::new(Monitor::new(dashboard, events))),
+        record_dir: args.record.clone(),
+        diff_tool: args.diff_tool.clone(),
+        diff_tools: test_file.diff_tools.clone(),
+        analyzers: test_file.analyzers.clone(),
+        tolerance: args.tolerance,
+        rules: Arc::new(SharedRules::new(Rules::of(&test_file))),
+        routes: test_file.routes.clone(),
+        log_dir: args.log_dir.clone(),
+        debug_rerun: args.debug_rerun,
+        break_at: args.break_at.clone(),
+        divergence_hook: args.on_divergence.clone(),
+        wrapper: args.wrap.clone(),
+        confirm: args.confirm,
+        group_output: args.group_output,
+        jitter: args.jitter.map(|max| Jitter::new(max, args.jitter_seed)),
+        strict_config: args.strict_config,
+        max_buffer_mem: args.max_buffer_mem,
+        bench: args.bench,
+        check_deadline: args.check_deadline,
+        fail_on_deadline: args.fail_on_deadline,
+        max_payload_bytes: args.max_payload_bytes,
+        stats: args.stats.then(Default::default),
+        unstable_sites: args
+            .repeat
+            .is_some_and(|repeat| repeat > 1)
+            .then(Default::default),
+        resume: args
+            .checkpoint
+            .as_deref()
+            .map(|path| Arc::new(exit_on_error(ResumeFile::new(path, args.resume)))),
+        decision_log: args
+            .decision_log
+            .as_deref()
+            .map(|path| Arc::new(exit_on_error(DecisionLog::create(path)))),
+        debug_log: args
+            .debug_harness
+            .as_deref()
+            .map(|path| Arc::new(exit_on_error(DebugLog::create(path)))),
+        thresholds: test_file.thresholds.clone(),
+        max_lead: args.max_lead,
+        in_process: Vec::new(),
+        reference_dir: args.baseline.as_ref().map(|name| {
+            let loaded = exit_on_error(Baseline::load(&args.baseline_dir, name));
+            for difference in loaded.build.differences(&baseline::BuildInfo::current()) {
+                println!(
+                    "\x1b[1;41m WARNING \x1b[0m \x1b[1;31mstale baseline, {}\x1b[0m",
+                    difference
+                );
+            }
+            loaded.dir
+        }),
+    };
+
+    if let Err(e) = rules::reload_on_sighup(testfile.clone(), options.rules.clone()) {
+        eprintln!("Cannot reload the test file on SIGHUP: {}", e);
+    }
+
+    // Run test commands and get results
+    let reports = match &options.monitor {
+        #[cfg(feature = "tui")]
+        Some(monitor) if monitor.is_dashboard() => {
+            tui::run(monitor, || run_groups(&groups, &options, args.jobs))
+        }
+        _ => run_groups(&groups, &options, args.jobs),
+    };
+    let results: Vec<Outcome> = reports.iter().map(|report| report.outcome).collect();
+    let report = Report::new(reports);
+
+    // Exiting skips destructors, the temporary snapshots are removed before any exit
+    let remove_snapshots = || {
+        if args.snapshot_dir.is_none() {
+            let _ = std::fs::remove_dir_all(&options.snapshot_dir);
+        }
+    };
+
+    if args.interestingness_test.is_some() {
+        let diverged = results.contains(&Outcome::Mismatch);
+        remove_snapshots();
+        std::process::exit(if diverged { 0 } else { 1 });
+    }
+
+    if let Some(name) = &args.self_check {
+        if results.iter().all(|outcome| outcome.passed()) {
+            println!(
+                "\x1b[1;32m'{}' produced matching streams in two concurrent runs\x1b[0m",
+                name
+            );
+        } else {
+            println!(
+                "\x1b[1;31m'{}' is not deterministic enough to be diffed: its runs diverged\x1b[0m",
+                name
+            );
+        }
+    }
+
+    let mut summary = String::new();
+    let _ = if groups.len() > 1 {
+        report::write_summary(&mut summary, &report, false)
+    } else {
+        report::write_quarantined(&mut summary, &report)
+    };
+    if let Some(stats) = &options.stats {
+        let _ = stats::write_stats(&mut summary, stats);
+    }
+    if let Some(sites) = &options.unstable_sites {
+        let _ = unstable::write_unstable(&mut summary, sites);
+    }
+    print!("{}", summary);
+
+    if let (Some(history), Some(path)) = (&mut history, &args.history) {
+        history.record(&report);
+        if let Err(e) = history.save(path) {
+            eprintln!("Cannot write history {}: {}", path.display(), e);
+        }
+    }
+
+    if let Some(path) = &args.report
+        && let Err(e) = report.save(path)
+    {
+        eprintln!("Cannot write report {}: {}", path.display(), e);
+    }
+
+    if args.seeds.is_some() {
+        let diverging = matrix::diverging_values(&groups, &results, matrix::SEED_VARIABLE);
+        if diverging.is_empty() {
+            println!("\x1b[1;32mNo seed diverged\x1b[0m");
+        } else {
+            println!("\x1b[1;31mDiverging seeds:\x1b[0m {}", diverging.join(", "));
+            if let Some(smallest) = diverging.iter().min_by_key(|seed| seed.parse::<u64>().ok()) {
+                println!("\x1b[1;31mSmallest diverging seed:\x1b[0m {}", smallest);
+            }
+        }
+    }
+
+    if !inputs.is_empty() {
+        let diverging = matrix::diverging_values(&groups, &results, matrix::INPUT_VARIABLE);
+        println!(
+            "\x1b[1;34m{} of {} inputs diverged\x1b[0m",
+            diverging.len(),
+            inputs.len()
+        );
+        for input in diverging {
+            println!("  \x1b[1;31m{}\x1b[0m", input);
+        }
+    }
+
+    if args.reduce || args.reducer.is_some() {
+        reduce_inputs(&args, testfile, &groups, &results, &options);
+    }
+
+    remove_snapshots();
+    if !report.passed() {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,171 @@
+use ratatui::{
+    Frame,
+    crossterm::event::{self, Event, KeyCode},
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style},
+    widgets::{Block, Cell, Paragraph, Row, Table},
+};
+use runtime_diff::harness::{
+    Outcome,
+    monitor::{GroupStatus, Monitor, Usage},
+};
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+/// Interval between two refreshes of the dashboard.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Shows a live dashboard of `monitor` while `run` executes, until it completes or the
+/// user quits; the captured output is printed afterwards.
+pub fn run<T: Send>(monitor: &Monitor, run: impl FnOnce() -> T + Send) -> T {
+    let done = AtomicBool::new(false);
+    let result = std::thread::scope(|scope| {
+        let handle = scope.spawn(|| {
+            let result = run();
+            done.store(true, Ordering::Release);
+            result
+        });
+
+        let mut terminal = ratatui::init();
+        while !done.load(Ordering::Acquire) {
+            if let Err(e) = terminal.draw(|frame| draw(frame, monitor)) {
+                ratatui::restore();
+                eprintln!("Cannot draw the dashboard: {}", e);
+                break;
+            }
+            if event::poll(REFRESH_INTERVAL).unwrap_or(false)
+                && let Ok(Event::Key(key)) = event::read()
+                && matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+            {
+                break;
+            }
+        }
+        ratatui::restore();
+
+        handle.join().unwrap()
+    });
+
+    for line in monitor.log_lines() {
+        println!("{}", line);
+    }
+    result
+}
+
+fn draw(frame: &mut Frame, monitor: &Monitor) {
+    let mut groups = monitor.groups_status();
+    // Running groups first, then the most recently finished ones
+    groups.reverse();
+    groups.sort_by_key(|group| group.outcome.is_some());
+
+    let [table_area, breadcumbs_area, log_area] = Layout::vertical([
+        Constraint::Percentage(40),
+        Constraint::Percentage(35),
+        Constraint::Percentage(25),
+    ])
+    .areas(frame.area());
+
+    frame.render_widget(commands_table(&groups), table_area);
+
+    let breadcumbs: Vec<String> = groups
+        .iter()
+        .filter(|group| group.outcome.is_none())
+        .flat_map(|group| &group.commands)
+        .flat_map(|command| {
+            command
+                .breadcumbs
+                .iter()
+                .map(move |breadcumb| format!("{}: {}", command.name, breadcumb))
+        })
+        .collect();
+    frame.render_widget(
+        Paragraph::new(tail(&breadcumbs, breadcumbs_area.height))
+            .block(Block::bordered().title(" Recent breadcumbs (q to leave the dashboard) ")),
+        breadcumbs_area,
+    );
+
+    let log: Vec<String> = monitor
+        .log_lines()
+        .iter()
+        .map(|line| strip_ansi(line))
+        .collect();
+    frame.render_widget(
+        Paragraph::new(tail(&log, log_area.height)).block(Block::bordered().title(" Output ")),
+        log_area,
+    );
+}
+
+fn commands_table(groups: &[GroupStatus]) -> Table<'static> {
+    let mut rows = Vec::new();
+    for group in groups {
+        let (state, color) = match group.outcome {
+            None => ("running", Color::Yellow),
+            Some(Outcome::Passed) => ("passed", Color::Green),
+            Some(Outcome::Skipped) => ("skipped", Color::Yellow),
+            Some(Outcome::Sandbox) => ("sandbox", Color::Red),
+            Some(Outcome::Flaky) => ("flaky", Color::Yellow),
+            Some(_) => ("failed", Color::Red),
+        };
+        for (index, command) in group.commands.iter().enumerate() {
+            let usage = command.pid.filter(|_| command.running).and_then(Usage::of);
+            rows.push(Row::new(vec![
+                Cell::from(group.label.clone()),
+                Cell::from(command.name.clone()),
+                Cell::from(state).style(Style::default().fg(color)),
+                Cell::from(command.checks.to_string()),
+                Cell::from(group.lag(index).to_string()),
+                Cell::from(
+                    usage
+                        .map(|usage| format!("{:.1}s", usage.cpu_seconds))
+                        .unwrap_or_default(),
+                ),
+                Cell::from(
+                    usage
+                        .map(|usage| format!("{} MiB", usage.rss_bytes / (1024 * 1024)))
+                        .unwrap_or_default(),
+                ),
+            ]));
+        }
+    }
+
+    Table::new(
+        rows,
+        [
+            Constraint::Fill(2),
+            Constraint::Fill(1),
+            Constraint::Length(8),
+            Constraint::Length(10),
+            Constraint::Length(8),
+            Constraint::Length(9),
+            Constraint::Length(10),
+        ],
+    )
+    .header(
+        Row::new(vec![
+            "Group", "Command", "State", "Checks", "Lag", "CPU", "Memory",
+        ])
+        .style(Style::default().add_modifier(Modifier::BOLD)),
+    )
+    .block(Block::bordered().title(" Commands "))
+}
+
+/// Last lines of `lines` fitting in a bordered area `height` rows tall.
+fn tail(lines: &[String], height: u16) -> String {
+    let visible = (height as usize).saturating_sub(2);
+    lines[lines.len().saturating_sub(visible)..].join("\n")
+}
+
+/// Removes the color escape sequences of the harness output.
+fn strip_ansi(line: &str) -> String {
+    let mut stripped = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            chars.by_ref().find(|c| c.is_ascii_alphabetic());
+        } else {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
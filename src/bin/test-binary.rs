@@ -1,6 +1,20 @@
 pub fn main() {
     println!("This is a placeholder for the test-binary executable.");
 
+    runtime_diff::breadcumb!(
+        "Running as {:?} ({:?} of {:?})",
+        runtime_diff::role(),
+        runtime_diff::peer_index(),
+        runtime_diff::peer_count()
+    );
     runtime_diff::breadcumb!("This is a Breadcumb message.");
+    let _ = runtime_diff::transaction(|| {
+        runtime_diff::runtime_check!("Speculative check, rolled back.");
+        Err::<(), _>("rollback")
+    });
+    let weights: Vec<f32> = (0..200).map(|i| i as f32 / 10.0).collect();
+    runtime_diff::debug_breadcumb!("Weights sum to {}", weights.iter().sum::<f32>());
+    runtime_diff::array("weights", &weights);
+    runtime_diff::snapshot("state", b"The quick brown fox jumps over the lazy dog");
     runtime_diff::runtime_check!("ASDF");
 }
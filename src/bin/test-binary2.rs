@@ -1,6 +1,13 @@
 pub fn main() {
+    runtime_diff::init_from_env();
+    let _batching = runtime_diff::batching(4096, std::time::Duration::from_millis(10));
     println!("This is a placeholder for the test-binary2 executable.");
 
     runtime_diff::breadcumb!("This is a Breadcumb2 message.");
+    let mut weights: Vec<f32> = (0..200).map(|i| i as f32 / 10.0).collect();
+    weights[150] += 0.001;
+    runtime_diff::debug_breadcumb!("Weights sum to {}", weights.iter().sum::<f32>());
+    runtime_diff::array("weights", &weights);
+    runtime_diff::snapshot("state", b"The quick brown fox jumps over the lazy cat");
     runtime_diff::runtime_check!("ASDF2");
 }
@@ -0,0 +1,530 @@
+use std::{
+    cell::RefCell,
+    sync::{
+        Mutex, MutexGuard, Once, OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "zstd")]
+use crate::ZSTD_PREFIX;
+use crate::{
+    BATCH_PREFIX, BATCH_SEPARATOR, BREADCUMB_PREFIX, CAPABILITIES, CHECK_PREFIX, COMPRESSION_ENV,
+    DISABLE_PREFIX, ENABLE_PREFIX, HELLO_PREFIX, PROTOCOL_ENV, PROTOCOL_VERSION, RESUME_CHECK,
+    RESUME_ENV, ZSTD_CAPABILITY,
+    sink::{Sink, StdoutSink},
+};
+
+type Message = (&'static str, String);
+
+/// Message buffered by a transaction, or the work producing it once the transaction
+/// commits.
+enum Pending {
+    Message(Message),
+    Deferred(&'static str, Box<dyn FnOnce() -> String>),
+}
+
+impl Pending {
+    fn into_message(self) -> Message {
+        match self {
+            Pending::Message(message) => message,
+            Pending::Deferred(prefix, payload) => (prefix, payload()),
+        }
+    }
+}
+
+thread_local! {
+    static TRANSACTIONS: RefCell<Vec<Vec<Pending>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Protocol version agreed with the harness; older harnesses only parse plain prefixed
+/// lines, so framing is only used when the harness asks for it.
+fn protocol_version() -> u32 {
+    static VERSION: OnceLock<u32> = OnceLock::new();
+    *VERSION.get_or_init(|| {
+        std::env::var(PROTOCOL_ENV)
+            .ok()
+            .and_then(|version| version.parse::<u32>().ok())
+            .map_or(1, |version| version.min(PROTOCOL_VERSION))
+    })
+}
+
+/// Returns true if the harness accepts compressed messages, which needs the zstd feature.
+fn compressing() -> bool {
+    static COMPRESSING: OnceLock<bool> = OnceLock::new();
+    cfg!(feature = "zstd")
+        && *COMPRESSING.get_or_init(|| {
+            protocol_version() >= 2
+                && std::env::var(COMPRESSION_ENV).is_ok_and(|value| value == ZSTD_CAPABILITY)
+        })
+}
+
+/// Compresses `text` as the continuation of the zstd stream of the process, into a line
+/// holding the chunk flushed; once compression fails, text is kept as is.
+#[cfg(feature = "zstd")]
+fn compress(text: String) -> String {
+    use base64::Engine;
+    use std::io::Write;
+
+    type Encoder = zstd::stream::write::Encoder<'static, Vec<u8>>;
+    static ENCODER: OnceLock<Mutex<Option<Encoder>>> = OnceLock::new();
+
+    let mut encoder = ENCODER
+        .get_or_init(|| Mutex::new(Encoder::new(Vec::new(), 0).ok()))
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    let Some(current) = &mut *encoder else {
+        return text;
+    };
+    // The stream can't be resumed after an error, the harness reads plain lines after it
+    if current
+        .write_all(text.as_bytes())
+        .and_then(|()| current.flush())
+        .is_err()
+    {
+        *encoder = None;
+        return text;
+    }
+    let chunk = std::mem::take(current.get_mut());
+    format!(
+        "{} {}\n",
+        ZSTD_PREFIX,
+        base64::engine::general_purpose::STANDARD.encode(chunk)
+    )
+}
+
+/// Messages of the process not written yet, sent as a single line once large or old enough.
+struct Batch {
+    /// Framed messages, separated by [`BATCH_SEPARATOR`]
+    messages: String,
+    /// When the first message of the batch was added
+    since: Option<Instant>,
+    max_bytes: usize,
+    max_delay: Duration,
+}
+
+impl Batch {
+    fn push(&mut self, message: &str) {
+        if self.since.is_none() {
+            self.since = Some(Instant::now());
+        } else {
+            self.messages.push(BATCH_SEPARATOR);
+        }
+        self.messages.push_str(message);
+    }
+
+    fn is_due(&self) -> bool {
+        self.messages.len() >= self.max_bytes
+            || self
+                .since
+                .is_some_and(|since| since.elapsed() >= self.max_delay)
+    }
+
+    /// Takes the pending messages as a batch line, if any.
+    fn take(&mut self) -> Option<String> {
+        self.since.take()?;
+        let line = format!("{} {}\n", BATCH_PREFIX, self.messages);
+        self.messages.clear();
+        Some(line)
+    }
+}
+
+/// Destination of the messages; its lock orders the messages of all threads.
+struct Output {
+    /// Transport the messages go to, stdout if `None`
+    sink: Option<Box<dyn Sink>>,
+    batch: Option<Batch>,
+}
+
+impl Output {
+    /// Writes `text` holding messages, compressed if the harness accepts it.
+    fn emit(&mut self, text: String) {
+        #[cfg(feature = "zstd")]
+        let text = if compressing() { compress(text) } else { text };
+        self.write(&text);
+    }
+
+    fn write(&mut self, text: &str) {
+        if let Some(sink) = &mut self.sink
+            && let Err(e) = sink.write(text)
+        {
+            eprintln!("runtime-diff: sink broke, writing to stdout: {}", e);
+            self.sink = None;
+        }
+        if self.sink.is_none() {
+            let _ = StdoutSink.write(text);
+        }
+    }
+
+    fn flush_batch(&mut self) {
+        if let Some(line) = self.batch.as_mut().and_then(Batch::take) {
+            self.emit(line);
+        }
+    }
+}
+
+static OUTPUT: Mutex<Output> = Mutex::new(Output {
+    sink: None,
+    batch: None,
+});
+
+fn output() -> MutexGuard<'static, Output> {
+    OUTPUT.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Routes every check and breadcrumb to the named pipe given by the harness in the
+/// `RUNTIME_DIFF_SINK` environment variable, if any, leaving stdout to the program.
+///
+/// Must be called before anything is emitted. If the pipe cannot be opened, or breaks
+/// for good, messages go to stdout as usual, as they do on targets without named pipes
+/// such as `wasm32-wasip1`.
+pub fn init_from_env() {
+    #[cfg(unix)]
+    {
+        let Some(path) = std::env::var_os(crate::SINK_ENV) else {
+            return;
+        };
+        let path = std::path::PathBuf::from(path);
+        match crate::sink::FifoSink::open(&path) {
+            Ok(sink) => set_sink(sink),
+            Err(e) => eprintln!("runtime-diff: cannot open sink {}: {}", path.display(), e),
+        }
+    }
+}
+
+/// Routes every check and breadcrumb to `sink`, for transports other than stdout and the
+/// named pipe of the harness, e.g. in sandboxed processes or tests.
+///
+/// Must be called before anything is emitted.
+pub fn set_sink(sink: impl Sink + 'static) {
+    output().sink = Some(Box::new(sink));
+}
+
+/// Batching of the messages, enabled by [`batching`] until dropped.
+pub struct Batching(());
+
+impl Drop for Batching {
+    fn drop(&mut self) {
+        let mut output = output();
+        output.flush_batch();
+        output.batch = None;
+    }
+}
+
+/// Sends the messages of all threads in batches of up to `max_bytes`, or held no longer
+/// than `max_delay`, rather than one line at a time, until the returned guard is dropped.
+///
+/// Cuts the per-message cost of fine-grained instrumentation; the guard must be dropped,
+/// or [`flush`] called, before the process exits for the last messages to reach the
+/// harness. Does nothing unless the harness understands batches. Where threads are
+/// unavailable, as on wasm32-wasip1, batches are only sent as messages are emitted.
+#[must_use = "batching stops when the guard is dropped"]
+pub fn batching(max_bytes: usize, max_delay: Duration) -> Batching {
+    if protocol_version() >= 3 {
+        output().batch = Some(Batch {
+            messages: String::new(),
+            since: None,
+            max_bytes,
+            max_delay,
+        });
+        // Sends the batches nothing else is emitted after, until batching stops
+        let _ = std::thread::Builder::new().spawn(move || {
+            loop {
+                std::thread::sleep(max_delay);
+                let mut output = output();
+                match &output.batch {
+                    Some(batch) if batch.is_due() => output.flush_batch(),
+                    Some(_) => {}
+                    None => return,
+                }
+            }
+        });
+    }
+    Batching(())
+}
+
+/// Checks left to skip when the harness resumed the process from a checkpoint.
+struct FastForward {
+    checks: u64,
+    remaining: u64,
+    digest: u64,
+    disabled: usize,
+}
+
+impl FastForward {
+    /// Keeps the messages past the checkpoint, the region markers, and in place of the
+    /// last skipped check one carrying the digest of the skipped checks.
+    fn filter(&mut self, (prefix, payload): Message) -> Option<Message> {
+        if self.remaining == 0 {
+            return Some((prefix, payload));
+        }
+        match prefix {
+            DISABLE_PREFIX => self.disabled += 1,
+            ENABLE_PREFIX => self.disabled = self.disabled.saturating_sub(1),
+            // Checks in disabled regions are breadcrumbs to the harness
+            CHECK_PREFIX if self.disabled == 0 => {
+                self.digest = crate::rolling_digest(self.digest, &payload);
+                self.remaining -= 1;
+                if self.remaining == 0 {
+                    let resumed = format!(
+                        "{} at={} digest={:016x}",
+                        RESUME_CHECK, self.checks, self.digest
+                    );
+                    return Some((CHECK_PREFIX, resumed));
+                }
+                return None;
+            }
+            CHECK_PREFIX | BREADCUMB_PREFIX => return None,
+            _ => {}
+        }
+        Some((prefix, payload))
+    }
+}
+
+fn fast_forward() -> &'static Mutex<FastForward> {
+    static FAST_FORWARD: OnceLock<Mutex<FastForward>> = OnceLock::new();
+    FAST_FORWARD.get_or_init(|| {
+        let checks = std::env::var(RESUME_ENV)
+            .ok()
+            .and_then(|checks| checks.parse().ok())
+            .unwrap_or(0);
+        Mutex::new(FastForward {
+            checks,
+            remaining: checks,
+            digest: 0,
+            disabled: 0,
+        })
+    })
+}
+
+/// Writes the pending batch of messages, if any.
+pub fn flush() {
+    output().flush_batch();
+}
+
+/// Writes messages to the sink or stdout, numbering them when framing is enabled.
+fn write_messages(messages: impl IntoIterator<Item = Message>) {
+    static HELLO: Once = Once::new();
+    static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+    let mut output = output();
+    let messages: Vec<_> = {
+        let mut fast_forward = fast_forward().lock().unwrap_or_else(|e| e.into_inner());
+        messages
+            .into_iter()
+            .filter_map(|message| fast_forward.filter(message))
+            .collect()
+    };
+    if messages.is_empty() {
+        return;
+    }
+    if protocol_version() < 2 {
+        let mut text = String::new();
+        for (prefix, payload) in messages {
+            text.push_str(&format!("{} {}\n", prefix, payload));
+        }
+        output.write(&text);
+        return;
+    }
+
+    HELLO.call_once(|| {
+        let capabilities: Vec<_> = CAPABILITIES
+            .iter()
+            .copied()
+            .chain(compressing().then_some(ZSTD_CAPABILITY))
+            .collect();
+        output.write(&format!(
+            "{} {} {}\n",
+            HELLO_PREFIX,
+            protocol_version(),
+            capabilities.join(" ")
+        ));
+    });
+    // Forked children inherit the sequence, the harness numbers every writer on its own
+    let pid = (protocol_version() >= 4).then(std::process::id);
+    // The output lock is held, so sequence numbers follow the output order
+    let framed = messages.into_iter().map(|(prefix, payload)| {
+        let sequence = SEQUENCE.fetch_add(1, Ordering::Relaxed);
+        match pid {
+            Some(pid) => format!("{} [{}@{}] {}", prefix, sequence, pid, payload),
+            None => format!("{} [{}] {}", prefix, sequence, payload),
+        }
+    });
+    match &mut output.batch {
+        Some(batch) => {
+            for message in framed {
+                batch.push(&message);
+            }
+            if batch.is_due() {
+                output.flush_batch();
+            }
+        }
+        None => {
+            let text: String = framed.map(|message| message + "\n").collect();
+            output.emit(text);
+        }
+    }
+}
+
+#[doc(hidden)]
+pub fn __emit(prefix: &'static str, args: std::fmt::Arguments) {
+    let message = (prefix, args.to_string());
+    let message = TRANSACTIONS.with(|stack| match stack.borrow_mut().last_mut() {
+        Some(buffer) => {
+            buffer.push(Pending::Message(message));
+            None
+        }
+        None => Some(message),
+    });
+    if let Some(message) = message {
+        write_messages([message]);
+    }
+}
+
+/// Emits the message `payload` returns, calling it only once the enclosing transaction
+/// commits, if any, so that its side effects are rolled back with the message.
+pub(crate) fn emit_deferred(prefix: &'static str, payload: impl FnOnce() -> String + 'static) {
+    let payload = TRANSACTIONS.with(|stack| match stack.borrow_mut().last_mut() {
+        Some(buffer) => {
+            buffer.push(Pending::Deferred(prefix, Box::new(payload)));
+            None
+        }
+        None => Some(payload),
+    });
+    if let Some(payload) = payload {
+        write_messages([(prefix, payload())]);
+    }
+}
+
+struct TransactionGuard {
+    committed: bool,
+}
+
+impl Drop for TransactionGuard {
+    fn drop(&mut self) {
+        let messages = TRANSACTIONS.with(|stack| {
+            let mut stack = stack.borrow_mut();
+            let messages = stack.pop().unwrap_or_default();
+            match stack.last_mut() {
+                // Nested transactions commit into their parent
+                Some(parent) if self.committed => {
+                    parent.extend(messages);
+                    Vec::new()
+                }
+                _ if self.committed => messages,
+                _ => Vec::new(),
+            }
+        });
+
+        if !messages.is_empty() {
+            write_messages(messages.into_iter().map(Pending::into_message));
+        }
+    }
+}
+
+/// Runs `f` buffering every check and breadcrumb it emits on this thread.
+///
+/// The buffered messages are written out together if `f` returns `Ok`, and dropped
+/// if it returns `Err` or panics, so speculative work that gets rolled back leaves
+/// no trace in the compared stream.
+pub fn transaction<T, E>(f: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+    TRANSACTIONS.with(|stack| stack.borrow_mut().push(Vec::new()));
+    let mut guard = TransactionGuard { committed: false };
+    let result = f();
+    guard.committed = result.is_ok();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    struct Collected(Arc<Mutex<String>>);
+
+    impl Sink for Collected {
+        fn write(&mut self, text: &str) -> std::io::Result<()> {
+            self.0.lock().unwrap().push_str(text);
+            Ok(())
+        }
+    }
+
+    fn check(payload: &str) -> Message {
+        (CHECK_PREFIX, payload.to_string())
+    }
+
+    #[test]
+    fn fast_forward_skips_to_the_checkpoint() {
+        let mut fast_forward = FastForward {
+            checks: 2,
+            remaining: 2,
+            digest: 0,
+            disabled: 0,
+        };
+        let digest = crate::rolling_digest(crate::rolling_digest(0, "a"), "c");
+        assert!(fast_forward.filter(check("a")).is_none());
+        assert!(
+            fast_forward
+                .filter((BREADCUMB_PREFIX, "b".to_string()))
+                .is_none()
+        );
+        // Checks in disabled regions do not count, but the region markers are kept
+        let disable = (DISABLE_PREFIX, String::new());
+        assert_eq!(fast_forward.filter(disable.clone()), Some(disable));
+        assert!(fast_forward.filter(check("skipped")).is_none());
+        assert!(
+            fast_forward
+                .filter((ENABLE_PREFIX, String::new()))
+                .is_some()
+        );
+        assert_eq!(
+            fast_forward.filter(check("c")),
+            Some(check(&format!(
+                "{} at=2 digest={:016x}",
+                RESUME_CHECK, digest
+            )))
+        );
+        assert_eq!(fast_forward.filter(check("d")), Some(check("d")));
+    }
+
+    #[test]
+    fn transactions_keep_the_messages_of_committed_work() {
+        let written = Arc::new(Mutex::new(String::new()));
+        set_sink(Collected(written.clone()));
+        let deferred = Arc::new(AtomicU64::new(0));
+        let defer = |payload: &'static str| {
+            let deferred = deferred.clone();
+            emit_deferred(CHECK_PREFIX, move || {
+                deferred.fetch_add(1, Ordering::Relaxed);
+                payload.to_string()
+            })
+        };
+
+        let _ = transaction(|| {
+            crate::runtime_check!("rolled back");
+            defer("rolled back snapshot");
+            Err::<(), _>(())
+        });
+        let _ = transaction(|| {
+            crate::runtime_check!("kept");
+            let _ = transaction(|| {
+                crate::runtime_check!("nested, rolled back");
+                Err::<(), _>(())
+            });
+            let _ = transaction(|| {
+                defer("nested snapshot");
+                Ok::<_, ()>(())
+            });
+            // Deferred work runs once the outermost transaction commits
+            assert_eq!(deferred.load(Ordering::Relaxed), 0);
+            Ok::<_, ()>(())
+        });
+        assert_eq!(deferred.load(Ordering::Relaxed), 1);
+        assert_eq!(
+            *written.lock().unwrap(),
+            format!("{} kept\n{} nested snapshot\n", CHECK_PREFIX, CHECK_PREFIX)
+        );
+    }
+}
@@ -0,0 +1,411 @@
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    os::unix::process::ExitStatusExt,
+    path::Path,
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{Error, Log, breakpoint};
+
+/// Environment variable holding the secret shared by the agent and the harnesses allowed
+/// to run commands through it.
+pub const TOKEN_ENV: &str = "RUNTIME_DIFF_AGENT_TOKEN";
+
+/// Address the agent listens on by default, reachable from this machine only, e.g.
+/// through an SSH tunnel.
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:7070";
+
+/// Longest request line an agent reads, before it can check the token.
+const MAX_REQUEST: u64 = 1 << 20;
+
+/// Command sent by the harness to an agent, as the first line of a connection.
+#[derive(Serialize, Deserialize)]
+struct Request {
+    /// Secret shared with the agent
+    token: String,
+    program: String,
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+}
+
+/// Line sent by an agent to the harness about the command it runs.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Message {
+    Started(u32),
+    Line(String),
+    /// Line holding invalid UTF-8, passed on bytewise
+    Bytes(Vec<u8>),
+    /// Raw wait status the command exited with
+    Exit(i32),
+    Error(String),
+}
+
+fn send(stream: &mut impl Write, message: &Message) -> io::Result<()> {
+    let mut line = serde_json::to_string(message).map_err(io::Error::other)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())
+}
+
+fn receive(reader: &mut impl BufRead) -> io::Result<Option<Message>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    serde_json::from_str(&line)
+        .map(Some)
+        .map_err(io::Error::other)
+}
+
+/// Shared secret read from `file`, or from the environment if no file is given.
+pub fn read_token(file: Option<&Path>) -> Result<String, Error> {
+    let token = match file {
+        Some(file) => std::fs::read_to_string(file)?,
+        None => std::env::var(TOKEN_ENV).unwrap_or_default(),
+    };
+    let token = token.trim();
+    if token.is_empty() {
+        return Err(Error::Config(format!(
+            "the agent needs a shared secret, given in {} or in a token file",
+            TOKEN_ENV
+        )));
+    }
+    Ok(token.to_string())
+}
+
+/// Compares tokens in a time independent of where they differ.
+fn same_token(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// Runs the commands requested by harnesses connecting to `address` and presenting
+/// `token`, streaming their output back, until the process is stopped.
+pub fn serve(address: &str, token: &str, log: &Log) -> Result<(), Error> {
+    let listener = TcpListener::bind(address)?;
+    log.println(format_args!(
+        "\x1b[1;33mAgent listening on {}\x1b[0m",
+        listener.local_addr()?
+    ));
+    for connection in listener.incoming() {
+        let connection = match connection {
+            Ok(connection) => connection,
+            Err(e) => {
+                log.eprintln(format_args!("Failed to accept a connection: {}", e));
+                continue;
+            }
+        };
+        let (log, token) = (log.clone(), token.to_string());
+        std::thread::spawn(move || {
+            let peer = connection
+                .peer_addr()
+                .map_or_else(|_| "unknown peer".to_string(), |peer| peer.to_string());
+            if let Err(e) = handle(connection, &token, &log) {
+                log.eprintln(format_args!("Request from {} failed: {}", peer, e));
+            }
+        });
+    }
+    Ok(())
+}
+
+fn handle(mut stream: TcpStream, token: &str, log: &Log) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut line = String::new();
+    reader.by_ref().take(MAX_REQUEST).read_line(&mut line)?;
+    if !line.ends_with('\n') && line.len() as u64 >= MAX_REQUEST {
+        send(&mut stream, &Message::Error("request too long".to_string()))?;
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "request too long",
+        ));
+    }
+    let request: Request = serde_json::from_str(&line).map_err(io::Error::other)?;
+    if !same_token(&request.token, token) {
+        send(&mut stream, &Message::Error("invalid token".to_string()))?;
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "invalid token",
+        ));
+    }
+    log.println(format_args!(
+        "\x1b[1;33mExecuting {} {}\x1b[0m",
+        request.program,
+        request.args.join(" ")
+    ));
+
+    let mut child = match Command::new(&request.program)
+        .args(&request.args)
+        .env_remove(TOKEN_ENV)
+        .envs(request.env)
+        .stdout(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => return send(&mut stream, &Message::Error(e.to_string())),
+    };
+    send(&mut stream, &Message::Started(child.id()))?;
+
+    // The harness closes the connection once it is done with the command. The command is
+    // reaped holding the lock, so that its PID is not reused while the watcher signals it
+    let reaped = Arc::new(Mutex::new(false));
+    {
+        let (reaped, pid) = (reaped.clone(), child.id());
+        std::thread::spawn(move || {
+            let _ = io::copy(&mut reader, &mut io::sink());
+            let reaped = reaped.lock().unwrap_or_else(|e| e.into_inner());
+            if !*reaped {
+                breakpoint::signal(pid, libc::SIGKILL);
+            }
+        });
+    }
+    let reap = |child: &mut Child| {
+        let mut reaped = reaped.lock().unwrap_or_else(|e| e.into_inner());
+        *reaped = true;
+        child.wait()
+    };
+
+    let mut stdout = BufReader::new(child.stdout.take().expect("Failed to capture stdout"));
+    loop {
+        let mut line = Vec::new();
+        let sent = match stdout.read_until(b'\n', &mut line) {
+            Ok(0) => break,
+            Ok(_) => {
+                if line.last() == Some(&b'\n') {
+                    line.pop();
+                }
+                match String::from_utf8(line) {
+                    Ok(line) => send(&mut stream, &Message::Line(line)),
+                    Err(e) => send(&mut stream, &Message::Bytes(e.into_bytes())),
+                }
+            }
+            Err(e) => {
+                send(&mut stream, &Message::Error(e.to_string()))?;
+                break;
+            }
+        };
+        if let Err(e) = sent {
+            let _ = child.kill();
+            let _ = reap(&mut child);
+            return Err(e);
+        }
+    }
+    // Left killable by the watcher until it exits
+    wait_exited(&child)?;
+    let status = reap(&mut child)?;
+    send(&mut stream, &Message::Exit(status.into_raw()))
+}
+
+/// Waits for `child` to exit without reaping it, which keeps its PID its own.
+fn wait_exited(child: &Child) -> io::Result<()> {
+    // SAFETY: an all-zero siginfo_t is valid, waitid only writes to it
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    loop {
+        // SAFETY: `info` outlives the call
+        let waited = unsafe {
+            libc::waitid(
+                libc::P_PID,
+                child.id(),
+                &mut info,
+                libc::WEXITED | libc::WNOWAIT,
+            )
+        };
+        if waited == 0 {
+            return Ok(());
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+/// Command run by the agent listening at an address, instead of locally.
+pub struct RemoteProcess {
+    stream: TcpStream,
+    reader: Option<BufReader<TcpStream>>,
+    status: Arc<Mutex<Option<ExitStatus>>>,
+}
+
+impl RemoteProcess {
+    /// Has the agent at `host` run `command`, with the environment set on it, presenting
+    /// the secret shared with the agent from the environment.
+    pub fn spawn(host: &str, command: &Command) -> io::Result<Self> {
+        let token = std::env::var(TOKEN_ENV).map_err(|_| {
+            io::Error::other(format!(
+                "{} must hold the secret of the agent at {}",
+                TOKEN_ENV, host
+            ))
+        })?;
+        let request = Request {
+            token,
+            program: command.get_program().to_string_lossy().into_owned(),
+            args: command
+                .get_args()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect(),
+            env: command
+                .get_envs()
+                .filter_map(|(name, value)| {
+                    Some((
+                        name.to_string_lossy().into_owned(),
+                        value?.to_string_lossy().into_owned(),
+                    ))
+                })
+                .collect(),
+        };
+        let mut stream = TcpStream::connect(host)?;
+        let mut line = serde_json::to_string(&request).map_err(io::Error::other)?;
+        line.push('\n');
+        stream.write_all(line.as_bytes())?;
+
+        let mut reader = BufReader::new(stream.try_clone()?);
+        match receive(&mut reader)? {
+            Some(Message::Started(_)) => Ok(Self {
+                stream,
+                reader: Some(reader),
+                status: Arc::default(),
+            }),
+            Some(Message::Error(message)) => {
+                Err(io::Error::other(format!("agent at {}: {}", host, message)))
+            }
+            _ => Err(io::Error::other(format!(
+                "agent at {} did not start the command",
+                host
+            ))),
+        }
+    }
+
+    /// Output of the command, ending when it exits.
+    pub fn take_output(&mut self) -> RemoteOutput {
+        RemoteOutput {
+            reader: self.reader.take().expect("Output already taken"),
+            status: self.status.clone(),
+            pending: Vec::new(),
+            position: 0,
+        }
+    }
+
+    /// Exit status of the command, once the agent reported it at the end of its output.
+    pub fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        Ok(*self.status.lock().unwrap())
+    }
+
+    /// Stops the command, by closing the connection to the agent.
+    pub fn kill(&mut self) -> io::Result<()> {
+        self.stream.shutdown(Shutdown::Both)
+    }
+}
+
+/// Output streamed by an agent, line by line.
+pub struct RemoteOutput {
+    reader: BufReader<TcpStream>,
+    status: Arc<Mutex<Option<ExitStatus>>>,
+    pending: Vec<u8>,
+    position: usize,
+}
+
+impl Read for RemoteOutput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.pending.len() {
+            match receive(&mut self.reader)? {
+                Some(Message::Line(line)) => {
+                    self.pending = line.into_bytes();
+                    self.pending.push(b'\n');
+                    self.position = 0;
+                }
+                Some(Message::Bytes(line)) => {
+                    self.pending = line;
+                    self.pending.push(b'\n');
+                    self.position = 0;
+                }
+                Some(Message::Exit(status)) => {
+                    *self.status.lock().unwrap() = Some(ExitStatus::from_raw(status));
+                    return Ok(0);
+                }
+                Some(Message::Error(message)) => return Err(io::Error::other(message)),
+                Some(Message::Started(_)) => return Ok(0),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "the connection to the agent closed before the command exited",
+                    ));
+                }
+            }
+        }
+        let read = buf.len().min(self.pending.len() - self.position);
+        buf[..read].copy_from_slice(&self.pending[self.position..][..read]);
+        self.position += read;
+        Ok(read)
+    }
+}
+
+/// Test command running locally or through an agent.
+pub enum Process {
+    Local(Child),
+    Remote(RemoteProcess),
+}
+
+impl Process {
+    /// Id of the process, if it runs locally and can be signaled.
+    pub fn local_id(&self) -> Option<u32> {
+        match self {
+            Process::Local(child) => Some(child.id()),
+            Process::Remote(_) => None,
+        }
+    }
+
+    pub fn take_output(&mut self) -> Box<dyn Read + Send> {
+        match self {
+            Process::Local(child) => {
+                Box::new(child.stdout.take().expect("Failed to capture stdout"))
+            }
+            Process::Remote(remote) => Box::new(remote.take_output()),
+        }
+    }
+
+    pub fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        match self {
+            Process::Local(child) => child.try_wait(),
+            Process::Remote(remote) => remote.try_wait(),
+        }
+    }
+
+    /// Stops the command and waits for it.
+    pub fn kill(&mut self) {
+        match self {
+            Process::Local(child) => {
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+            Process::Remote(remote) => {
+                let _ = remote.kill();
+            }
+        }
+    }
+
+    /// Closure stopping the command from another thread.
+    pub fn killer(&self) -> io::Result<Box<dyn Fn() + Send>> {
+        Ok(match self {
+            Process::Local(child) => {
+                let pid = child.id();
+                Box::new(move || {
+                    breakpoint::signal(pid, libc::SIGKILL);
+                })
+            }
+            Process::Remote(remote) => {
+                let stream = remote.stream.try_clone()?;
+                Box::new(move || {
+                    let _ = stream.shutdown(Shutdown::Both);
+                })
+            }
+        })
+    }
+}
@@ -0,0 +1,222 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    process::{Child, ChildStdin, Stdio},
+    sync::mpsc::{self, Receiver},
+    thread::JoinHandle,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{Error, log::Log, testfile::TestGroup};
+
+/// What an analyzer receives on stdin, one JSON object per line.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    /// The checks numbered `check` of every command matched
+    Check {
+        group: &'a str,
+        check: usize,
+        channel: &'a str,
+        commands: &'a [&'a str],
+        checks: Vec<Option<&'a str>>,
+    },
+    /// The checks numbered `check` diverged, ending the comparison
+    Mismatch {
+        group: &'a str,
+        check: usize,
+        channel: &'a str,
+        commands: &'a [&'a str],
+        checks: Vec<Option<&'a str>>,
+    },
+    /// The comparison is over, the analyzer should print its last verdicts and exit
+    End { group: &'a str, passed: bool },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Level {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// What an analyzer prints on stdout, one JSON object per line, e.g.
+/// `{"verdict": "fail", "message": "balance went negative"}`.
+#[derive(Debug, Deserialize)]
+struct Verdict {
+    verdict: Level,
+    #[serde(default)]
+    message: String,
+}
+
+struct Running {
+    name: String,
+    child: Child,
+    /// Closed once the analyzer stops reading
+    stdin: Option<ChildStdin>,
+    verdicts: Receiver<Result<Verdict, String>>,
+    reader: Option<JoinHandle<()>>,
+}
+
+/// Analyzer subprocesses of a group run, given the matched checks and divergences to
+/// apply validation logic the harness does not have.
+#[derive(Default)]
+pub struct Analyzers {
+    group: String,
+    running: Vec<Running>,
+    failure: Option<Error>,
+}
+
+impl Analyzers {
+    /// Starts the `analyzers` of the `analyzers` section, by name, for a run of `group`.
+    pub fn start(analyzers: &[(String, String)], group: &TestGroup) -> Result<Self, Error> {
+        let mut running = Vec::new();
+        for (name, command) in analyzers {
+            let command = group.substitute(command);
+            let mut child = std::process::Command::new("sh")
+                .arg("-c")
+                .arg(&command)
+                .envs(group.variables.iter().map(|(name, value)| (name, value)))
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()
+                .map_err(|source| Error::Spawn {
+                    command: command.clone(),
+                    source,
+                })?;
+            let stdout = child.stdout.take().unwrap();
+            let (sender, verdicts) = mpsc::channel();
+            let reader = std::thread::spawn(move || {
+                for line in BufReader::new(stdout).lines() {
+                    let Ok(line) = line else {
+                        break;
+                    };
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let verdict =
+                        serde_json::from_str(&line).map_err(|e| format!("'{}': {}", line, e));
+                    if sender.send(verdict).is_err() {
+                        break;
+                    }
+                }
+            });
+            running.push(Running {
+                name: name.clone(),
+                stdin: child.stdin.take(),
+                child,
+                verdicts,
+                reader: Some(reader),
+            });
+        }
+        Ok(Self {
+            group: group.label(),
+            running,
+            failure: None,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.running.is_empty()
+    }
+
+    /// Sends `event` to every analyzer still reading its input.
+    pub fn send(&mut self, event: &Event, log: &Log) {
+        if self.running.is_empty() {
+            return;
+        }
+        let line = match serde_json::to_string(event) {
+            Ok(json) => json + "\n",
+            Err(e) => {
+                log.eprintln(format_args!("Cannot serialize analyzer event: {}", e));
+                return;
+            }
+        };
+        for analyzer in &mut self.running {
+            if let Some(stdin) = &mut analyzer.stdin
+                && stdin.write_all(line.as_bytes()).is_err()
+            {
+                log.println(format_args!(
+                    "\x1b[1;41m WARNING \x1b[0m \x1b[1;31mAnalyzer '{}' stopped reading its input\x1b[0m",
+                    analyzer.name
+                ));
+                analyzer.stdin = None;
+            }
+        }
+    }
+
+    /// Reports the verdicts printed so far, returning the first failure, if any.
+    pub fn failure(&mut self, log: &Log) -> Option<Error> {
+        for i in 0..self.running.len() {
+            while let Ok(verdict) = self.running[i].verdicts.try_recv() {
+                self.report(i, verdict, log);
+            }
+        }
+        self.failure.take()
+    }
+
+    fn report(&mut self, i: usize, verdict: Result<Verdict, String>, log: &Log) {
+        let name = &self.running[i].name;
+        match verdict {
+            Ok(Verdict {
+                verdict: Level::Pass,
+                ..
+            }) => {}
+            Ok(Verdict {
+                verdict: Level::Warn,
+                message,
+            }) => log.println(format_args!(
+                "\x1b[1;41m WARNING \x1b[0m \x1b[1;31mAnalyzer '{}': {}\x1b[0m",
+                name, message
+            )),
+            Ok(Verdict {
+                verdict: Level::Fail,
+                message,
+            }) => {
+                let error = Error::Analyzer {
+                    analyzer: name.clone(),
+                    group: self.group.clone(),
+                    message,
+                };
+                log.eprintln(&error);
+                self.failure.get_or_insert(error);
+            }
+            Err(e) => log.println(format_args!(
+                "\x1b[1;41m WARNING \x1b[0m \x1b[1;31mAnalyzer '{}' printed an invalid verdict {}\x1b[0m",
+                name, e
+            )),
+        }
+    }
+
+    /// Tells the analyzers the comparison is over and waits for them to exit, returning
+    /// the first failure, including an analyzer exiting unsuccessfully.
+    pub fn finish(mut self, passed: bool, log: &Log) -> Result<(), Error> {
+        let group = self.group.clone();
+        self.send(
+            &Event::End {
+                group: &group,
+                passed,
+            },
+            log,
+        );
+        let mut exit_failure = None;
+        for analyzer in &mut self.running {
+            analyzer.stdin = None;
+            let status = analyzer.child.wait()?;
+            if let Some(reader) = analyzer.reader.take() {
+                let _ = reader.join();
+            }
+            if !status.success() {
+                exit_failure.get_or_insert(Error::CommandFailed {
+                    command: analyzer.name.clone(),
+                    status,
+                });
+            }
+        }
+        match self.failure(log).or(exit_failure) {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,247 @@
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    Error,
+    compare::{self, Mode},
+    trace::Trace,
+};
+
+/// Directory holding the baselines when none is given.
+pub const DEFAULT_BASELINE_DIR: &str = ".runtime-diff/baselines";
+/// File of a baseline directory holding its metadata.
+const METADATA_FILE: &str = "baseline.json";
+/// Extension of the trace files recorded with `--record`.
+const TRACE_EXTENSION: &str = "trace";
+
+/// Build the traces of a baseline were recorded with; a baseline recorded with a
+/// different build than the current one is stale.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildInfo {
+    /// Commit checked out in the working directory, suffixed with `-dirty` if modified
+    pub git_rev: Option<String>,
+    pub rustc: Option<String>,
+    pub rustflags: Option<String>,
+}
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+impl BuildInfo {
+    pub fn current() -> Self {
+        let git_rev =
+            command_output("git", &["rev-parse", "HEAD"]).map(|rev| {
+                match command_output("git", &["status", "--porcelain", "--untracked-files=no"]) {
+                    Some(status) if !status.is_empty() => format!("{}-dirty", rev),
+                    _ => rev,
+                }
+            });
+        Self {
+            git_rev,
+            rustc: command_output("rustc", &["--version"]),
+            rustflags: std::env::var("RUSTFLAGS").ok(),
+        }
+    }
+
+    /// Describes how `other` differs from this build.
+    pub fn differences(&self, other: &BuildInfo) -> Vec<String> {
+        let fields = [
+            ("git revision", &self.git_rev, &other.git_rev),
+            ("rustc", &self.rustc, &other.rustc),
+            ("RUSTFLAGS", &self.rustflags, &other.rustflags),
+        ];
+        fields
+            .into_iter()
+            .filter(|(_, own, other)| own != other)
+            .map(|(field, own, other)| {
+                format!(
+                    "{}: {} recorded, {} now",
+                    field,
+                    own.as_deref().unwrap_or("none"),
+                    other.as_deref().unwrap_or("none")
+                )
+            })
+            .collect()
+    }
+}
+
+/// A recorded run promoted as the reference later runs are validated against.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Baseline {
+    pub name: String,
+    /// Seconds since the Unix epoch at which the baseline was saved
+    pub created: u64,
+    pub build: BuildInfo,
+    #[serde(skip)]
+    pub dir: PathBuf,
+}
+
+/// Relative paths of the trace files below `root`, sorted.
+pub fn trace_files(root: &Path) -> Result<Vec<PathBuf>, Error> {
+    fn visit(root: &Path, dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), Error> {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                visit(root, &path, files)?;
+            } else if path.extension().is_some_and(|ext| ext == TRACE_EXTENSION) {
+                files.push(path.strip_prefix(root).unwrap_or(&path).to_path_buf());
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = Vec::new();
+    visit(root, root, &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+fn check_name(name: &str) -> Result<(), Error> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(Error::Config(format!("invalid baseline name '{}'", name)));
+    }
+    Ok(())
+}
+
+impl Baseline {
+    pub fn load(baselines: &Path, name: &str) -> Result<Self, Error> {
+        check_name(name)?;
+        let dir = baselines.join(name);
+        let metadata = std::fs::read_to_string(dir.join(METADATA_FILE))
+            .map_err(|e| Error::Config(format!("cannot read baseline '{}': {}", name, e)))?;
+        let mut baseline: Baseline = serde_json::from_str(&metadata)
+            .map_err(|e| Error::Config(format!("invalid baseline '{}': {}", name, e)))?;
+        baseline.dir = dir;
+        Ok(baseline)
+    }
+
+    /// Every baseline in `baselines`, sorted by name.
+    pub fn list(baselines: &Path) -> Result<Vec<Self>, Error> {
+        if !baselines.exists() {
+            return Ok(Vec::new());
+        }
+        let mut list = Vec::new();
+        for entry in std::fs::read_dir(baselines)? {
+            let entry = entry?;
+            if entry.path().join(METADATA_FILE).exists() {
+                list.push(Self::load(baselines, &entry.file_name().to_string_lossy())?);
+            }
+        }
+        list.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(list)
+    }
+
+    /// Copies the traces recorded in `recording` into the baseline `name`, replacing the
+    /// traces of an existing baseline only if `replace`.
+    pub fn save(
+        baselines: &Path,
+        name: &str,
+        recording: &Path,
+        replace: bool,
+    ) -> Result<Self, Error> {
+        check_name(name)?;
+        let traces = trace_files(recording)?;
+        if traces.is_empty() {
+            return Err(Error::Config(format!(
+                "no traces recorded in {}",
+                recording.display()
+            )));
+        }
+
+        let dir = baselines.join(name);
+        match (dir.exists(), replace) {
+            (true, false) => {
+                return Err(Error::Config(format!(
+                    "baseline '{}' already exists, update it instead",
+                    name
+                )));
+            }
+            (false, true) => {
+                return Err(Error::Config(format!("no baseline named '{}'", name)));
+            }
+            (true, true) => std::fs::remove_dir_all(&dir)?,
+            (false, false) => {}
+        }
+
+        for trace in &traces {
+            let target = dir.join(trace);
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::copy(recording.join(trace), target)?;
+        }
+
+        let baseline = Baseline {
+            name: name.to_string(),
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+            build: BuildInfo::current(),
+            dir,
+        };
+        let metadata = serde_json::to_string_pretty(&baseline)
+            .map_err(|e| Error::Config(format!("cannot serialize baseline: {}", e)))?;
+        std::fs::write(baseline.dir.join(METADATA_FILE), metadata + "\n")?;
+        Ok(baseline)
+    }
+
+    /// Compares the traces recorded in `recording` against the baseline, writing the
+    /// differences; returns true if every trace matched.
+    pub fn write_diff(
+        &self,
+        out: &mut impl Write,
+        recording: &Path,
+        mode: Mode,
+        context: usize,
+    ) -> Result<bool, Error> {
+        let expected = trace_files(&self.dir)?;
+        let actual = trace_files(recording)?;
+
+        let mut matched = true;
+        for trace in &expected {
+            if !actual.contains(trace) {
+                matched = false;
+                writeln!(out, "\x1b[1;31mMissing trace:\x1b[0m {}", trace.display())?;
+                continue;
+            }
+
+            let mut baseline_trace = Trace::load(&self.dir.join(trace))?;
+            baseline_trace.name = format!("baseline {}", self.name);
+            let mut recorded_trace = Trace::load(&recording.join(trace))?;
+            recorded_trace.name = recording.display().to_string();
+            let mut report = String::new();
+            if compare::compare_traces(
+                &mut report,
+                [&baseline_trace, &recorded_trace],
+                mode,
+                context,
+            )? {
+                writeln!(out, "\x1b[1;32mmatches\x1b[0m {}", trace.display())?;
+            } else {
+                matched = false;
+                writeln!(out, "\x1b[1;31mdiffers\x1b[0m {}", trace.display())?;
+                out.write_str(&report)?;
+            }
+        }
+        for trace in actual.iter().filter(|trace| !expected.contains(trace)) {
+            matched = false;
+            writeln!(
+                out,
+                "\x1b[1;31mTrace missing from the baseline:\x1b[0m {}",
+                trace.display()
+            )?;
+        }
+        Ok(matched)
+    }
+}
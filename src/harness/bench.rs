@@ -0,0 +1,87 @@
+use std::{fmt::Write, time::Duration};
+
+/// Key of the threshold applying to the checkpoints without one of their own.
+pub const DEFAULT_THRESHOLD_KEY: &str = "*";
+
+/// Time spent before every checkpoint, since the previous one, summed by checkpoint name
+/// in order of first appearance; `times` holds the name of every check and the time it
+/// was emitted at since the start of the command.
+fn checkpoint_durations(times: &[(String, Duration)]) -> Vec<(&str, Duration)> {
+    let mut durations: Vec<(&str, Duration)> = Vec::new();
+    let mut previous = Duration::ZERO;
+    for (name, time) in times {
+        let elapsed = time.saturating_sub(previous);
+        previous = *time;
+        match durations
+            .iter_mut()
+            .find(|(checkpoint, _)| checkpoint == name)
+        {
+            Some((_, total)) => *total += elapsed,
+            None => durations.push((name, elapsed)),
+        }
+    }
+    durations
+}
+
+fn threshold_for(name: &str, thresholds: &[(String, f64)]) -> Option<f64> {
+    let find = |key: &str| {
+        thresholds
+            .iter()
+            .find(|(checkpoint, _)| checkpoint == key)
+            .map(|(_, threshold)| *threshold)
+    };
+    find(name).or_else(|| find(DEFAULT_THRESHOLD_KEY))
+}
+
+/// Writes the time every command spent before each checkpoint and its ratio to the first
+/// command, returning the checkpoints some command exceeded its threshold at.
+pub fn write_timings(
+    out: &mut impl Write,
+    names: &[&str],
+    times: &[Vec<(String, Duration)>],
+    thresholds: &[(String, f64)],
+) -> Result<Vec<String>, std::fmt::Error> {
+    let durations: Vec<_> = times
+        .iter()
+        .map(|times| checkpoint_durations(times))
+        .collect();
+    let Some(reference) = durations.first() else {
+        return Ok(Vec::new());
+    };
+
+    writeln!(
+        out,
+        "\x1b[1;34mCheckpoint timings, relative to \x1b[1;37m{}\x1b[1;34m:\x1b[0m",
+        names[0]
+    )?;
+    let mut exceeded = Vec::new();
+    for (checkpoint, reference_duration) in reference {
+        let threshold = threshold_for(checkpoint, thresholds);
+        write!(out, "  {:<24} {:>12.3?}", checkpoint, reference_duration)?;
+        let mut slow = false;
+        for (name, durations) in names.iter().zip(&durations).skip(1) {
+            let Some((_, duration)) = durations.iter().find(|(other, _)| other == checkpoint)
+            else {
+                write!(out, "  {}: missing", name)?;
+                continue;
+            };
+            let ratio = duration.as_secs_f64() / reference_duration.as_secs_f64().max(1e-9);
+            let over = threshold.is_some_and(|threshold| ratio > threshold);
+            slow |= over;
+            let color = if over { "\x1b[1;31m" } else { "" };
+            write!(
+                out,
+                "  {}{}: {:.3?} ({:.2}x)\x1b[0m",
+                color, name, duration, ratio
+            )?;
+        }
+        match threshold {
+            Some(threshold) if slow => writeln!(out, "  \x1b[1;31mabove {}x\x1b[0m", threshold)?,
+            _ => writeln!(out)?,
+        }
+        if slow {
+            exceeded.push(checkpoint.to_string());
+        }
+    }
+    Ok(exceeded)
+}
@@ -0,0 +1,249 @@
+use std::{
+    collections::BTreeSet,
+    io::BufRead,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use super::{Error, log::Log, trace};
+
+/// Point of the comparison where every command is paused for debuggers to be attached.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// The check with the given number, counting from 1
+    Check(usize),
+    /// The first check with the given name
+    Name(String),
+}
+
+impl FromStr for Breakpoint {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse() {
+            Ok(0) => Err(Error::Config("checks are numbered from 1".to_string())),
+            Ok(number) => Ok(Breakpoint::Check(number)),
+            Err(_) if s.trim().is_empty() => Err(Error::Config("empty check name".to_string())),
+            Err(_) => Ok(Breakpoint::Name(s.trim().to_string())),
+        }
+    }
+}
+
+impl Breakpoint {
+    /// Returns true if the comparison reached the breakpoint at the checks numbered `number`.
+    pub fn is_hit(&self, number: usize, checks: &[Option<String>]) -> bool {
+        checks
+            .iter()
+            .flatten()
+            .any(|check| self.matches(number, check))
+    }
+
+    /// Returns true if `check`, numbered `number` among the checks of a command, is the
+    /// breakpoint.
+    fn matches(&self, number: usize, check: &str) -> bool {
+        match self {
+            Breakpoint::Check(check) => *check == number,
+            Breakpoint::Name(name) => {
+                let payload = check.strip_prefix(crate::CHECK_PREFIX).unwrap_or(check);
+                trace::check_name(payload) == name
+            }
+        }
+    }
+}
+
+/// Sends `signal` to the process `pid`; returns true if it was delivered.
+pub fn signal(pid: u32, signal: libc::c_int) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: kill only takes plain integers
+    unsafe { libc::kill(pid, signal) == 0 }
+}
+
+/// Processes stopped by the readers of a comparison, so that they do not run past the
+/// checks it compares.
+#[derive(Default)]
+pub struct Stopped(Mutex<BTreeSet<u32>>);
+
+impl Stopped {
+    /// Stops `pid`; returns true if it was running.
+    pub fn stop(&self, pid: u32) -> bool {
+        let mut stopped = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        !stopped.contains(&pid) && signal(pid, libc::SIGSTOP) && stopped.insert(pid)
+    }
+
+    pub fn is_stopped(&self, pid: u32) -> bool {
+        let stopped = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        stopped.contains(&pid)
+    }
+
+    /// Resumes `pid`, if it was stopped.
+    pub fn resume(&self, pid: u32) {
+        let mut stopped = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        if stopped.remove(&pid) {
+            signal(pid, libc::SIGCONT);
+        }
+    }
+
+    pub fn resume_all(&self) {
+        let mut stopped = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        for pid in std::mem::take(&mut *stopped) {
+            signal(pid, libc::SIGCONT);
+        }
+    }
+}
+
+/// Processes stopped by the readers of a comparison, resumed when it returns, however it
+/// does.
+pub struct StoppedProcesses(pub Arc<Stopped>);
+
+impl Drop for StoppedProcesses {
+    fn drop(&mut self) {
+        self.0.resume_all();
+    }
+}
+
+/// Stops the process of a command from its reader once it emitted the breakpoint, so that
+/// it waits there for the comparison to reach it instead of running on.
+pub struct BreakpointStop {
+    breakpoint: Breakpoint,
+    /// Checks emitted by the command so far, including those skipped by resuming it
+    checks: usize,
+    reached: bool,
+}
+
+impl BreakpointStop {
+    /// Starts counting the checks of a command resumed past `skipped` checks, whose first
+    /// check stands for the last skipped one.
+    pub fn new(breakpoint: Breakpoint, skipped: usize) -> Self {
+        Self {
+            breakpoint,
+            checks: skipped.saturating_sub(1),
+            reached: false,
+        }
+    }
+
+    /// Counts a `check` of the command `pid`, stopping it if it is the breakpoint.
+    pub fn check(&mut self, check: &str, pid: u32, stopped: &Stopped) {
+        self.checks += 1;
+        if !self.reached && self.breakpoint.matches(self.checks, check) {
+            self.reached = true;
+            stopped.stop(pid);
+        }
+    }
+}
+
+/// Stops the commands, prints how to attach a debugger to each of them and resumes them
+/// once the user presses enter.
+pub fn pause(names: &[&str], pids: &[Option<u32>], number: usize, stopped: &Stopped, log: &Log) {
+    let mut instructions = format!(
+        "\x1b[1;33mBreakpoint reached at check {}, commands stopped:\x1b[0m\n",
+        number
+    );
+    for (name, pid) in names.iter().zip(pids) {
+        match pid {
+            // Usually stopped by its reader as soon as it emitted the check
+            Some(pid) if stopped.stop(*pid) || stopped.is_stopped(*pid) => {
+                instructions.push_str(&format!("  {}: gdb -p {}\n", name, pid));
+            }
+            Some(pid) => instructions.push_str(&format!("  {}: process {} exited\n", name, pid)),
+            None => instructions.push_str(&format!("  {}: not a process\n", name)),
+        }
+    }
+    instructions.push_str("Press enter to resume them");
+    log.println(instructions);
+
+    let _ = std::io::stdin().lock().read_line(&mut String::new());
+    stopped.resume_all();
+}
+
+/// Stops the commands that are still running and runs `hook` with their process ids as
+/// arguments, in the order of the commands, resuming them once it exits.
+pub fn run_hook(
+    hook: &str,
+    names: &[&str],
+    pids: &[Option<u32>],
+    processes: &Stopped,
+    log: &Log,
+) -> Result<(), Error> {
+    // Usually held by their reader within a check of the divergence
+    let stopped: Vec<_> = names
+        .iter()
+        .zip(pids)
+        .filter_map(|(name, pid)| Some((*name, (*pid)?)))
+        .filter(|(_, pid)| processes.stop(*pid) || processes.is_stopped(*pid))
+        .collect();
+    if stopped.is_empty() {
+        log.println("\x1b[1;33mNo diverging command is still running, not running the hook\x1b[0m");
+        return Ok(());
+    }
+
+    let description: Vec<_> = stopped
+        .iter()
+        .map(|(name, pid)| format!("{} ({})", name, pid))
+        .collect();
+    log.println(format_args!(
+        "\x1b[1;33mStopped {}, running: {}\x1b[0m",
+        description.join(", "),
+        hook
+    ));
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", hook))
+        .arg("sh")
+        .args(stopped.iter().map(|(_, pid)| pid.to_string()))
+        .status();
+    processes.resume_all();
+
+    let status = status.map_err(|source| Error::Spawn {
+        command: hook.to_string(),
+        source,
+    })?;
+    if !status.success() {
+        return Err(Error::CommandFailed {
+            command: hook.to_string(),
+            status,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::BufReader,
+        process::{Command, Stdio},
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[test]
+    fn commands_stop_once_they_emit_the_breakpoint() {
+        let progress =
+            std::env::temp_dir().join(format!("runtime-diff-breakpoint-{}", std::process::id()));
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg("for i in 1 2 3 4 5; do echo $i > \"$0\"; echo \"RUNTIME CHECK: x $i\"; sleep 0.1; done")
+            .arg(&progress)
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stopped = Stopped::default();
+        let mut stop = BreakpointStop::new(Breakpoint::Check(3), 0);
+        let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+        for line in lines.by_ref().take(3) {
+            stop.check(&line.unwrap(), child.id(), &stopped);
+        }
+        assert!(stopped.is_stopped(child.id()));
+
+        // Running, it would reach the last check meanwhile
+        std::thread::sleep(Duration::from_millis(400));
+        assert_eq!(std::fs::read_to_string(&progress).unwrap().trim(), "3");
+        stopped.resume_all();
+        assert_eq!(lines.next().unwrap().unwrap(), "RUNTIME CHECK: x 4");
+        assert_eq!(lines.count(), 1);
+        assert!(child.wait().unwrap().success());
+        std::fs::remove_file(&progress).unwrap();
+    }
+}
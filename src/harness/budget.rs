@@ -0,0 +1,79 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+/// Marker of the messages retained as a digest of their text.
+const DIGEST_MARKER: &str = "digest~";
+
+/// Returns true if `message` was retained as a digest.
+pub fn is_digest(message: &str) -> bool {
+    message.contains(DIGEST_MARKER)
+}
+
+/// Digest form of a message, keeping its prefix and first word so that checks keep their
+/// name.
+pub fn digest_message(prefix: &str, message: &str) -> String {
+    if is_digest(message) {
+        return message.to_string();
+    }
+    let payload = message.strip_prefix(prefix).unwrap_or(message);
+    let name = payload.split_whitespace().next().unwrap_or("");
+    format!(
+        "{} {} {}{:016x}",
+        prefix,
+        name,
+        DIGEST_MARKER,
+        crate::digest(message.as_bytes())
+    )
+}
+
+/// Cap on the bytes the harness holds for the messages of a group, shared with the
+/// threads reading the commands.
+#[derive(Clone)]
+pub struct BufferBudget {
+    limit: usize,
+    /// Bytes sent by the readers and not yet received by the comparison
+    in_flight: Arc<AtomicUsize>,
+    /// Whether messages are only retained as digests
+    digest_only: Arc<AtomicBool>,
+}
+
+impl BufferBudget {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            in_flight: Default::default(),
+            digest_only: Default::default(),
+        }
+    }
+
+    /// Called by a reader before sending a message, returns the message to send.
+    pub fn send(&self, prefix: &str, message: String) -> String {
+        let message = if self.is_digest_only() {
+            digest_message(prefix, &message)
+        } else {
+            message
+        };
+        self.in_flight.fetch_add(message.len(), Ordering::Relaxed);
+        message
+    }
+
+    /// Called by the comparison after receiving a message.
+    pub fn received(&self, message: &str) {
+        self.in_flight.fetch_sub(message.len(), Ordering::Relaxed);
+    }
+
+    /// Returns true if the messages in flight and the `retained` bytes exceed the limit.
+    pub fn is_exceeded(&self, retained: usize) -> bool {
+        self.in_flight.load(Ordering::Relaxed) + retained > self.limit
+    }
+
+    pub fn is_digest_only(&self) -> bool {
+        self.digest_only.load(Ordering::Relaxed)
+    }
+
+    pub fn set_digest_only(&self) {
+        self.digest_only.store(true, Ordering::Relaxed);
+    }
+}
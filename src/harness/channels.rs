@@ -0,0 +1,228 @@
+use std::collections::VecDeque;
+
+use regex::Regex;
+
+use super::{budget, protocol::WriterId};
+
+/// Channel of the checks no routing rule matches.
+pub const DEFAULT_CHANNEL: &str = "";
+
+/// Checks of every command split into virtual channels by routing rules, and by writer
+/// when forked processes share the stdout of a command, each channel compared in lockstep
+/// independently of the others.
+pub struct Channels<'a> {
+    routes: &'a [(String, Regex)],
+    commands: usize,
+    /// Checks received but not compared yet, per channel and command
+    queues: Vec<(String, Vec<VecDeque<String>>)>,
+    /// Bytes of the queued checks
+    bytes: usize,
+}
+
+impl<'a> Channels<'a> {
+    pub fn new(routes: &'a [(String, Regex)], commands: usize) -> Self {
+        Self {
+            routes,
+            commands,
+            queues: Vec::new(),
+            bytes: 0,
+        }
+    }
+
+    /// Channel of the first rule matching the payload of `check`.
+    pub fn route(&self, check: &str) -> &'a str {
+        let payload = check.strip_prefix(crate::CHECK_PREFIX).unwrap_or(check);
+        self.routes
+            .iter()
+            .find(|(_, pattern)| pattern.is_match(payload.trim_start()))
+            .map_or(DEFAULT_CHANNEL, |(channel, _)| channel.as_str())
+    }
+
+    /// Queues a check `writer` wrote to the stream of `command`.
+    pub fn push(&mut self, command: usize, check: String, writer: WriterId) {
+        let route = self.route(&check);
+        let channel = if writer.is_first() {
+            route.to_string()
+        } else {
+            format!("{}[{}]", route, writer)
+        };
+        let index = match self.queues.iter().position(|(name, _)| *name == channel) {
+            Some(index) => index,
+            None => {
+                self.queues
+                    .push((channel, vec![VecDeque::new(); self.commands]));
+                self.queues.len() - 1
+            }
+        };
+        self.bytes += check.len();
+        self.queues[index].1[command].push_back(check);
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Replaces the queued checks by their digest, to compare them against the digests of
+    /// the checks still to come.
+    pub fn digest_all(&mut self) {
+        let queued = self
+            .queues
+            .iter_mut()
+            .flat_map(|(_, queues)| queues)
+            .flatten();
+        for check in queued {
+            self.bytes -= check.len();
+            *check = budget::digest_message(crate::CHECK_PREFIX, check);
+            self.bytes += check.len();
+        }
+    }
+
+    /// Takes the next check of every command from a channel every command still running
+    /// has reached, along with the channel; commands that ended have none.
+    pub fn next_aligned(&mut self, ended: &[bool]) -> Option<(String, Vec<Option<String>>)> {
+        let (channel, queues) = self.queues.iter_mut().find(|(_, queues)| {
+            queues.iter().any(|queue| !queue.is_empty())
+                && queues
+                    .iter()
+                    .zip(ended)
+                    .all(|(queue, ended)| *ended || !queue.is_empty())
+        })?;
+        let aligned: Vec<_> = queues.iter_mut().map(VecDeque::pop_front).collect();
+        self.bytes -= aligned.iter().flatten().map(String::len).sum::<usize>();
+        Some((channel.clone(), aligned))
+    }
+}
+
+/// Most recent checks all the commands agreed on, per channel, reported with a mismatch as
+/// the last point the commands were known to agree.
+pub struct Agreements {
+    limit: usize,
+    /// Number and text of the check of the first command, per channel
+    channels: Vec<(String, VecDeque<(usize, String)>)>,
+    bytes: usize,
+}
+
+impl Agreements {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            channels: Vec::new(),
+            bytes: 0,
+        }
+    }
+
+    /// Records that the checks numbered `check` matched on `channel`, as `agreed`.
+    pub fn record(&mut self, channel: &str, check: usize, agreed: &str) {
+        if self.limit == 0 {
+            return;
+        }
+        let index = match self.channels.iter().position(|(name, _)| name == channel) {
+            Some(index) => index,
+            None => {
+                self.channels.push((channel.to_string(), VecDeque::new()));
+                self.channels.len() - 1
+            }
+        };
+        let kept = &mut self.channels[index].1;
+        kept.push_back((check, agreed.to_string()));
+        self.bytes += agreed.len();
+        while kept.len() > self.limit {
+            if let Some((_, evicted)) = kept.pop_front() {
+                self.bytes -= evicted.len();
+            }
+        }
+    }
+
+    /// Agreements of `channel`, oldest first.
+    pub fn last(&self, channel: &str) -> impl Iterator<Item = &(usize, String)> {
+        self.channels
+            .iter()
+            .filter(move |(name, _)| name == channel)
+            .flat_map(|(_, kept)| kept)
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Keeps at most `limit` agreements per channel from now on.
+    pub fn shrink(&mut self, limit: usize) {
+        self.limit = self.limit.min(limit);
+        for (_, kept) in &mut self.channels {
+            while kept.len() > self.limit {
+                if let Some((_, evicted)) = kept.pop_front() {
+                    self.bytes -= evicted.len();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(payload: &str) -> String {
+        format!("{} {}", crate::CHECK_PREFIX, payload)
+    }
+
+    #[test]
+    fn checks_are_routed_by_the_first_matching_rule() {
+        let routes = [
+            ("net".to_string(), Regex::new("^net").unwrap()),
+            ("io".to_string(), Regex::new("^(io|net)").unwrap()),
+        ];
+        let channels = Channels::new(&routes, 2);
+        assert_eq!(channels.route(&check("net send")), "net");
+        assert_eq!(channels.route(&check("io read")), "io");
+        assert_eq!(channels.route(&check("other")), DEFAULT_CHANNEL);
+    }
+
+    #[test]
+    fn channels_align_independently() {
+        let routes = [("net".to_string(), Regex::new("^net").unwrap())];
+        let mut channels = Channels::new(&routes, 2);
+        let first = WriterId::default();
+        channels.push(0, check("net a"), first);
+        channels.push(0, check("b"), first);
+        assert!(channels.next_aligned(&[false, false]).is_none());
+
+        // The second command reaches the default channel first
+        channels.push(1, check("b"), first);
+        let (channel, aligned) = channels.next_aligned(&[false, false]).unwrap();
+        assert_eq!(channel, DEFAULT_CHANNEL);
+        assert_eq!(aligned, [Some(check("b")), Some(check("b"))]);
+        assert!(channels.next_aligned(&[false, false]).is_none());
+
+        channels.push(1, check("net a"), first);
+        let (channel, aligned) = channels.next_aligned(&[false, false]).unwrap();
+        assert_eq!(channel, "net");
+        assert_eq!(aligned, [Some(check("net a")), Some(check("net a"))]);
+        assert_eq!(channels.bytes(), 0);
+    }
+
+    #[test]
+    fn ended_commands_have_no_check() {
+        let mut channels = Channels::new(&[], 2);
+        channels.push(0, check("a"), WriterId::default());
+        assert!(channels.next_aligned(&[false, false]).is_none());
+        let (_, aligned) = channels.next_aligned(&[false, true]).unwrap();
+        assert_eq!(aligned, [Some(check("a")), None]);
+        assert!(channels.next_aligned(&[true, true]).is_none());
+    }
+
+    #[test]
+    fn forked_writers_have_channels_of_their_own() {
+        let mut channels = Channels::new(&[], 2);
+        let forked = WriterId {
+            forked_at: 3,
+            rank: 0,
+        };
+        channels.push(0, check("a"), forked);
+        channels.push(1, check("a"), WriterId::default());
+        assert!(channels.next_aligned(&[false, false]).is_none());
+        channels.push(1, check("a"), forked);
+        let (channel, _) = channels.next_aligned(&[false, false]).unwrap();
+        assert_eq!(channel, "[writer forked at message 3]");
+    }
+}
@@ -0,0 +1,290 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Write,
+    str::FromStr,
+};
+
+use super::{
+    Error,
+    trace::{Entry, Trace},
+};
+
+/// How the checks of two recorded traces are matched against each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Checks are compared in order, stopping at the first divergence like a live run
+    Lockstep,
+    /// Checks are aligned by their longest common subsequence, reporting every difference
+    Lcs,
+    /// Checks are matched by name, the first word of their payload, and occurrence
+    Keyed,
+}
+
+impl FromStr for Mode {
+    type Err = Error;
+
+    fn from_str(mode: &str) -> Result<Self, Error> {
+        match mode {
+            "lockstep" => Ok(Mode::Lockstep),
+            "lcs" => Ok(Mode::Lcs),
+            "keyed" => Ok(Mode::Keyed),
+            _ => Err(Error::Config(format!(
+                "unknown comparison mode '{}', expected lockstep, lcs or keyed",
+                mode
+            ))),
+        }
+    }
+}
+
+/// Compares two traces, writing a report of their differences; returns true if they match.
+///
+/// `context` is the number of entries shown around every difference.
+pub fn compare_traces(
+    out: &mut impl Write,
+    traces: [&Trace; 2],
+    mode: Mode,
+    context: usize,
+) -> Result<bool, std::fmt::Error> {
+    match mode {
+        Mode::Lockstep => compare_lockstep(out, traces, context),
+        Mode::Lcs => compare_lcs(out, traces, context),
+        Mode::Keyed => compare_keyed(out, traces),
+    }
+}
+
+fn checks(trace: &Trace) -> Vec<&Entry> {
+    trace
+        .entries
+        .iter()
+        .filter(|entry| entry.is_check())
+        .collect()
+}
+
+fn compare_lockstep(
+    out: &mut impl Write,
+    traces: [&Trace; 2],
+    context: usize,
+) -> Result<bool, std::fmt::Error> {
+    let mut positions = [0, 0];
+    let mut recent = [VecDeque::new(), VecDeque::new()];
+    let mut check_number = 0;
+
+    loop {
+        check_number += 1;
+        let mut last_checks = [None, None];
+        for (i, trace) in traces.iter().enumerate() {
+            while let Some(entry) = trace.entries.get(positions[i]) {
+                positions[i] += 1;
+                if recent[i].len() > context {
+                    recent[i].pop_front();
+                }
+                recent[i].push_back(entry.text());
+                if entry.is_check() {
+                    last_checks[i] = Some(entry.text());
+                    break;
+                }
+            }
+        }
+
+        match last_checks {
+            [None, None] => return Ok(true),
+            [a, b] if a == b => continue,
+            _ => {}
+        }
+
+        writeln!(
+            out,
+            "\x1b[1;31mTraces diverge at check #{}\x1b[0m",
+            check_number
+        )?;
+        for (i, trace) in traces.iter().enumerate() {
+            writeln!(
+                out,
+                "\x1b[1;34mTrace \x1b[1;37m{}\x1b[1;34m breadcumbs:\x1b[0m",
+                trace.name
+            )?;
+            for entry in &recent[i] {
+                writeln!(out, "{}", entry)?;
+            }
+            if last_checks[i].is_none() {
+                writeln!(out, "\x1b[1;31m(end of trace)\x1b[0m")?;
+            }
+        }
+        return Ok(false);
+    }
+}
+
+/// Edit script turning `a` into `b`, computed with Myers' algorithm: `(in_a, in_b)` pairs
+/// where a missing side marks a removed or inserted element.
+fn diff(a: &[&str], b: &[&str]) -> Vec<(Option<usize>, Option<usize>)> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let offset = n + m + 1;
+    let mut v = vec![0isize; 2 * offset as usize + 1];
+    // Furthest reaching x of every diagonal before each step, only the diagonals
+    // -d - 1..=d + 1 that the step can read, so that memory grows with the differences only
+    let mut history: Vec<Vec<isize>> = Vec::new();
+    let goes_down =
+        |v: &dyn Fn(isize) -> isize, d: isize, k: isize| k == -d || (k != d && v(k - 1) < v(k + 1));
+
+    'search: for d in 0..=(n + m) {
+        history.push(v[(offset - d - 1) as usize..=(offset + d + 1) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let furthest = |k: isize| v[(k + offset) as usize];
+            let mut x = if goes_down(&furthest, d, k) {
+                furthest(k + 1)
+            } else {
+                furthest(k - 1) + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[(k + offset) as usize] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    // Walk the recorded frontiers back from the end
+    let mut script = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, frontier) in history.iter().enumerate().rev() {
+        let d = d as isize;
+        let furthest = |k: isize| frontier[(k + d + 1) as usize];
+        let k = x - y;
+        let previous_k = if goes_down(&furthest, d, k) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let previous_x = if d == 0 { 0 } else { furthest(previous_k) };
+        let previous_y = if d == 0 { 0 } else { previous_x - previous_k };
+        while x > previous_x && y > previous_y {
+            x -= 1;
+            y -= 1;
+            script.push((Some(x as usize), Some(y as usize)));
+        }
+        if d > 0 {
+            if x == previous_x {
+                script.push((None, Some(previous_y as usize)));
+            } else {
+                script.push((Some(previous_x as usize), None));
+            }
+        }
+        (x, y) = (previous_x, previous_y);
+    }
+    script.reverse();
+    script
+}
+
+fn compare_lcs(
+    out: &mut impl Write,
+    traces: [&Trace; 2],
+    context: usize,
+) -> Result<bool, std::fmt::Error> {
+    let [a, b] = traces.map(|trace| {
+        checks(trace)
+            .into_iter()
+            .map(Entry::text)
+            .collect::<Vec<_>>()
+    });
+    let script = diff(&a, &b);
+    let changed: Vec<usize> = script
+        .iter()
+        .enumerate()
+        .filter(|(_, (in_a, in_b))| in_a.is_none() || in_b.is_none())
+        .map(|(position, _)| position)
+        .collect();
+    if changed.is_empty() {
+        return Ok(true);
+    }
+
+    writeln!(
+        out,
+        "\x1b[1;31m--- {}\x1b[0m\n\x1b[1;32m+++ {}\x1b[0m",
+        traces[0].name, traces[1].name
+    )?;
+    let mut hunk_start = 0;
+    while hunk_start < changed.len() {
+        // Group the changes whose context overlaps into one hunk
+        let mut hunk_end = hunk_start;
+        while hunk_end + 1 < changed.len()
+            && changed[hunk_end + 1] - changed[hunk_end] <= 2 * context
+        {
+            hunk_end += 1;
+        }
+        let first = changed[hunk_start].saturating_sub(context);
+        let last = (changed[hunk_end] + context + 1).min(script.len());
+
+        let check_in = |trace: usize| {
+            script[first..]
+                .iter()
+                .find_map(|pair| if trace == 0 { pair.0 } else { pair.1 })
+                .map_or(0, |position| position + 1)
+        };
+        writeln!(
+            out,
+            "\x1b[1;34m@@ check #{} / #{} @@\x1b[0m",
+            check_in(0),
+            check_in(1)
+        )?;
+        for pair in &script[first..last] {
+            match *pair {
+                (Some(in_a), Some(_)) => writeln!(out, "  {}", a[in_a])?,
+                (Some(in_a), None) => writeln!(out, "\x1b[1;31m- {}\x1b[0m", a[in_a])?,
+                (None, Some(in_b)) => writeln!(out, "\x1b[1;32m+ {}\x1b[0m", b[in_b])?,
+                (None, None) => {}
+            }
+        }
+        hunk_start = hunk_end + 1;
+    }
+    Ok(false)
+}
+
+fn compare_keyed(out: &mut impl Write, traces: [&Trace; 2]) -> Result<bool, std::fmt::Error> {
+    // Checks of every name in order of occurrence, names in order of first occurrence
+    let mut names = Vec::new();
+    let mut by_name: [HashMap<&str, Vec<&str>>; 2] = [HashMap::new(), HashMap::new()];
+    for (i, trace) in traces.iter().enumerate() {
+        for check in checks(trace) {
+            let name = check.name().unwrap_or_default();
+            if !by_name[0].contains_key(name) && !by_name[1].contains_key(name) {
+                names.push(name);
+            }
+            by_name[i].entry(name).or_default().push(check.text());
+        }
+    }
+
+    let mut matched = true;
+    for name in names {
+        let [a, b] = [0, 1].map(|i| by_name[i].get(name).map_or(&[][..], Vec::as_slice));
+        for (occurrence, (check_a, check_b)) in a.iter().zip(b).enumerate() {
+            if check_a != check_b {
+                matched = false;
+                writeln!(
+                    out,
+                    "\x1b[1;31m{}#{}\x1b[0m\n\x1b[1;31m- {}\x1b[0m\n\x1b[1;32m+ {}\x1b[0m",
+                    name,
+                    occurrence + 1,
+                    check_a,
+                    check_b
+                )?;
+            }
+        }
+        if a.len() != b.len() {
+            matched = false;
+            writeln!(
+                out,
+                "\x1b[1;31m{}\x1b[0m: {} checks in {}, {} in {}",
+                name,
+                a.len(),
+                traces[0].name,
+                b.len(),
+                traces[1].name
+            )?;
+        }
+    }
+    Ok(matched)
+}
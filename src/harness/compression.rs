@@ -0,0 +1,81 @@
+use std::{collections::VecDeque, io::Write};
+
+use base64::Engine;
+
+type Decoder = zstd::stream::write::Decoder<'static, Vec<u8>>;
+
+/// Lines of a command, the chunks of its zstd stream expanded into the lines they hold
+/// once its hello line announced compression.
+pub struct Decompressed<I> {
+    lines: I,
+    decoder: Option<Decoder>,
+    /// Decompressed lines not returned yet
+    pending: VecDeque<String>,
+    /// Decompressed text after the last complete line
+    partial: String,
+}
+
+pub fn decompressed<I>(lines: I) -> Decompressed<I> {
+    Decompressed {
+        lines,
+        decoder: None,
+        pending: VecDeque::new(),
+        partial: String::new(),
+    }
+}
+
+impl<I> Decompressed<I> {
+    fn expand(&mut self, chunk: &str) -> std::io::Result<()> {
+        let Some(decoder) = &mut self.decoder else {
+            return Ok(());
+        };
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(chunk.trim())
+            .map_err(std::io::Error::other)?;
+        decoder.write_all(&bytes)?;
+        decoder.flush()?;
+        let text = std::mem::take(decoder.get_mut());
+        self.partial.push_str(&String::from_utf8_lossy(&text));
+        while let Some(end) = self.partial.find('\n') {
+            let line: String = self.partial.drain(..=end).collect();
+            self.pending.push_back(line.trim_end().to_string());
+        }
+        Ok(())
+    }
+}
+
+impl<I: Iterator<Item = std::io::Result<String>>> Iterator for Decompressed<I> {
+    type Item = std::io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(line) = self.pending.pop_front() {
+                return Some(Ok(line));
+            }
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e)),
+            };
+
+            if let Some(hello) = line.trim().strip_prefix(crate::HELLO_PREFIX)
+                && hello
+                    .split_whitespace()
+                    .skip(1)
+                    .any(|capability| capability == crate::ZSTD_CAPABILITY)
+            {
+                match Decoder::new(Vec::new()) {
+                    Ok(decoder) => self.decoder = Some(decoder),
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+            // Without compression announced, such lines are plain program output
+            let chunk = match line.trim().strip_prefix(crate::ZSTD_PREFIX) {
+                Some(chunk) if self.decoder.is_some() => chunk.to_string(),
+                _ => return Some(Ok(line)),
+            };
+            if let Err(e) = self.expand(&chunk) {
+                return Some(Err(e));
+            }
+        }
+    }
+}
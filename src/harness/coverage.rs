@@ -0,0 +1,96 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use super::trace::{self, Trace};
+
+/// Distinct check names emitted by every command of a comparison, with their count.
+#[derive(Debug, Clone)]
+pub struct Coverage {
+    names: Vec<BTreeMap<String, usize>>,
+}
+
+impl Coverage {
+    pub fn new(commands: usize) -> Self {
+        Self {
+            names: vec![BTreeMap::new(); commands],
+        }
+    }
+
+    pub fn of_traces(traces: &[&Trace]) -> Self {
+        let mut coverage = Self::new(traces.len());
+        for (i, trace) in traces.iter().enumerate() {
+            for name in trace.entries.iter().filter_map(|entry| entry.name()) {
+                coverage.record_name(i, name);
+            }
+        }
+        coverage
+    }
+
+    /// Records a check, as the canonical line, emitted by `command`.
+    pub fn record(&mut self, command: usize, check: &str) {
+        let payload = check.strip_prefix(crate::CHECK_PREFIX).unwrap_or(check);
+        self.record_name(command, trace::check_name(payload));
+    }
+
+    /// Returns true if `command` emitted a check named `name`.
+    pub fn emitted(&self, command: usize, name: &str) -> bool {
+        self.names[command].contains_key(name)
+    }
+
+    fn record_name(&mut self, command: usize, name: &str) {
+        *self.names[command].entry(name.to_string()).or_default() += 1;
+    }
+
+    /// Check names some commands emitted and others never did, with the number of times
+    /// every command emitted them.
+    pub fn gaps(&self) -> Vec<(&str, Vec<usize>)> {
+        let mut all: Vec<&str> = self
+            .names
+            .iter()
+            .flat_map(|names| names.keys().map(String::as_str))
+            .collect();
+        all.sort_unstable();
+        all.dedup();
+        all.into_iter()
+            .map(|name| {
+                let counts = self
+                    .names
+                    .iter()
+                    .map(|names| names.get(name).copied().unwrap_or(0))
+                    .collect::<Vec<_>>();
+                (name, counts)
+            })
+            .filter(|(_, counts)| counts.contains(&0))
+            .collect()
+    }
+}
+
+/// Warns about the check names only some of the commands `names` emitted, which hints at
+/// code paths one implementation skips.
+pub fn write_coverage(
+    out: &mut impl Write,
+    names: &[&str],
+    coverage: &Coverage,
+) -> std::fmt::Result {
+    for (check, counts) in coverage.gaps() {
+        let emitted: Vec<_> = names
+            .iter()
+            .zip(&counts)
+            .filter(|(_, count)| **count > 0)
+            .map(|(name, count)| format!("{} ({}x)", name, count))
+            .collect();
+        let missing: Vec<_> = names
+            .iter()
+            .zip(&counts)
+            .filter(|(_, count)| **count == 0)
+            .map(|(name, _)| *name)
+            .collect();
+        writeln!(
+            out,
+            "\x1b[1;41m WARNING \x1b[0m \x1b[1;31mCheck '{}' emitted by {} but never by {}\x1b[0m",
+            check,
+            emitted.join(", "),
+            missing.join(", ")
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,50 @@
+use std::{
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
+
+/// Longest time a command may go without emitting a check, watched from a thread of its
+/// own to catch commands that hang or livelock.
+pub struct CheckDeadline {
+    deadline: Duration,
+    last: Mutex<Instant>,
+}
+
+impl CheckDeadline {
+    pub fn new(deadline: Duration) -> Arc<Self> {
+        Arc::new(Self {
+            deadline,
+            last: Mutex::new(Instant::now()),
+        })
+    }
+
+    /// Called whenever the command emits a check.
+    pub fn checked(&self) {
+        *self.last.lock().unwrap() = Instant::now();
+    }
+
+    /// Calls `exceeded` with the time since the last check whenever the command goes past
+    /// the deadline without one, once per gap, until the deadline is dropped or `exceeded`
+    /// returns false.
+    pub fn watch(self: &Arc<Self>, mut exceeded: impl FnMut(Duration) -> bool + Send + 'static) {
+        let deadline: Weak<Self> = Arc::downgrade(self);
+        let period = self.deadline / 4;
+        std::thread::spawn(move || {
+            let mut reported = None;
+            loop {
+                std::thread::sleep(period);
+                let Some(deadline) = deadline.upgrade() else {
+                    return;
+                };
+                let last = *deadline.last.lock().unwrap();
+                let elapsed = last.elapsed();
+                if elapsed > deadline.deadline && reported != Some(last) {
+                    reported = Some(last);
+                    if !exceeded(elapsed) {
+                        return;
+                    }
+                }
+            }
+        });
+    }
+}
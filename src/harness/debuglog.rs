@@ -0,0 +1,46 @@
+use std::{fmt, fs::File, io::Write, path::Path, sync::Mutex, time::Instant};
+
+use super::Error;
+
+/// Longest part of a message quoted in the debug log.
+const QUOTED_BYTES: usize = 200;
+
+/// File logging the decisions of the harness itself, to diagnose protocol issues between
+/// instrumented programs and the harness.
+pub struct DebugLog {
+    file: Mutex<File>,
+    start: Instant,
+}
+
+impl DebugLog {
+    pub fn create(path: &Path) -> Result<Self, Error> {
+        Ok(Self {
+            file: Mutex::new(File::create(path)?),
+            start: Instant::now(),
+        })
+    }
+
+    /// Writes a line with the time since the start of the run and the current thread,
+    /// which is named after the command it reads.
+    pub fn write(&self, message: fmt::Arguments) {
+        let elapsed = self.start.elapsed();
+        let thread = std::thread::current();
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        let _ = writeln!(
+            file,
+            "[{:>10.6}s {}] {}",
+            elapsed.as_secs_f64(),
+            thread.name().unwrap_or("-"),
+            message
+        );
+    }
+}
+
+/// `message` cut short enough to be quoted in the debug log.
+pub fn quoted(message: &str) -> &str {
+    let mut end = message.len().min(QUOTED_BYTES);
+    while !message.is_char_boundary(end) {
+        end -= 1;
+    }
+    &message[..end]
+}
@@ -0,0 +1,251 @@
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    str::FromStr,
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    Error, channels,
+    group::Outcome,
+    report::{self, GroupReport, Report},
+};
+
+/// Comparison decision of the harness, one JSON object per line of the decision log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "decision", rename_all = "snake_case")]
+pub enum Decision {
+    /// A comparison of the commands of a group started, up to the check numbered `stop_at`
+    /// if given
+    Start {
+        group: String,
+        commands: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        stop_at: Option<usize>,
+    },
+    /// The checks numbered `check` of a channel were compared
+    Compare {
+        group: String,
+        check: usize,
+        channel: String,
+        /// Digest of the check of every command, none for commands that ended before it
+        digests: Vec<Option<String>>,
+        /// Rules that changed the checks before they were compared
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        normalized: Vec<String>,
+        ignored: bool,
+        diverged: bool,
+        /// Text of the checks, kept for divergences only
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        checks: Vec<Option<String>>,
+    },
+    /// The group ended with `outcome`
+    End {
+        group: String,
+        outcome: Outcome,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
+
+/// Append-only file recording every comparison decision of a run, to render its report
+/// again with `replay-decisions` without running anything.
+pub struct DecisionLog(Mutex<BufWriter<File>>);
+
+impl DecisionLog {
+    pub fn create(path: &Path) -> Result<Self, Error> {
+        Ok(Self(Mutex::new(BufWriter::new(File::create(path)?))))
+    }
+
+    /// Appends `decision`, flushing the file once a group ends.
+    pub fn record(&self, decision: &Decision) {
+        let Ok(json) = serde_json::to_string(decision) else {
+            return;
+        };
+        let mut file = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let _ = writeln!(file, "{}", json);
+        if matches!(decision, Decision::End { .. }) {
+            let _ = file.flush();
+        }
+    }
+}
+
+/// Digest of a check as recorded in the decision log.
+pub fn check_digest(check: &str) -> String {
+    format!("{:016x}", crate::digest(check.as_bytes()))
+}
+
+/// Reads the decisions recorded at `path`.
+pub fn load(path: &Path) -> Result<Vec<Decision>, Error> {
+    let mut decisions = Vec::new();
+    for (line_number, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let decision = serde_json::from_str(&line).map_err(|e| Error::Parse {
+            line: line_number + 1,
+            message: format!("invalid decision: {}", e),
+        })?;
+        decisions.push(decision);
+    }
+    Ok(decisions)
+}
+
+/// How `replay-decisions` renders the report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Divergences and the summary of the groups, as printed by a run
+    Text,
+    /// The JSON report written by --report
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(format: &str) -> Result<Self, Error> {
+        match format {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(Error::Config(format!(
+                "unknown report format '{}', expected text or json",
+                format
+            ))),
+        }
+    }
+}
+
+/// Outcome of every group that ended in the recorded run.
+pub fn replayed_report(decisions: &[Decision]) -> Report {
+    let groups = decisions
+        .iter()
+        .filter_map(|decision| match decision {
+            Decision::End {
+                group,
+                outcome,
+                error,
+            } => Some(GroupReport {
+                label: group.clone(),
+                outcome: *outcome,
+                error: error.clone(),
+                runs: Vec::new(),
+                quarantined: false,
+            }),
+            _ => None,
+        })
+        .collect();
+    Report::new(groups)
+}
+
+/// Writes the report of the recorded run: its divergences with the checks of every
+/// command, then the summary of the groups. Every decision is listed when `verbose`.
+pub fn write_replay(
+    out: &mut impl std::fmt::Write,
+    decisions: &[Decision],
+    verbose: bool,
+) -> std::fmt::Result {
+    let mut commands: Vec<(&str, &[String])> = Vec::new();
+    for decision in decisions {
+        match decision {
+            Decision::Start {
+                group,
+                commands: names,
+                stop_at,
+            } => {
+                commands.retain(|(label, _)| label != group);
+                commands.push((group, names));
+                if verbose {
+                    write!(out, "\x1b[1;34mComparing '{}'\x1b[0m", group)?;
+                    if let Some(stop_at) = stop_at {
+                        write!(out, " up to check {}", stop_at)?;
+                    }
+                    writeln!(out, ": {}", names.join(", "))?;
+                }
+            }
+            Decision::Compare {
+                group,
+                check,
+                channel,
+                digests,
+                normalized,
+                ignored,
+                diverged,
+                checks,
+            } => {
+                // Checks of the default channel are shown without it
+                let channel = if channel == channels::DEFAULT_CHANNEL {
+                    String::new()
+                } else {
+                    format!(" [{}]", channel)
+                };
+                if *diverged {
+                    writeln!(
+                        out,
+                        "\x1b[1;31mMismatch in '{}' at check {}{}:\x1b[0m",
+                        group, check, channel
+                    )?;
+                    let names = commands
+                        .iter()
+                        .find(|(label, _)| label == group)
+                        .map_or(&[][..], |(_, names)| names);
+                    for (i, check) in checks.iter().enumerate() {
+                        let name = names.get(i).map_or("?", String::as_str);
+                        writeln!(
+                            out,
+                            "  \x1b[1;37m{}\x1b[0m {}",
+                            name,
+                            check.as_deref().unwrap_or("<ended>")
+                        )?;
+                    }
+                } else if verbose {
+                    let status = if *ignored { "ignored" } else { "matched" };
+                    let digests: Vec<_> = digests
+                        .iter()
+                        .map(|digest| digest.as_deref().unwrap_or("-"))
+                        .collect();
+                    write!(
+                        out,
+                        "  check {}{} {} {}",
+                        check,
+                        channel,
+                        status,
+                        digests.join(" ")
+                    )?;
+                    if !normalized.is_empty() {
+                        write!(out, " \x1b[1;33m({})\x1b[0m", normalized.join(", "))?;
+                    }
+                    writeln!(out)?;
+                }
+                if *diverged && !normalized.is_empty() {
+                    writeln!(out, "  normalized by {}", normalized.join(", "))?;
+                }
+            }
+            Decision::End { group, outcome, .. } => {
+                if verbose {
+                    writeln!(out, "\x1b[1;34m'{}' ended: {:?}\x1b[0m", group, outcome)?;
+                }
+            }
+        }
+    }
+    report::write_summary(out, &replayed_report(decisions), true)
+}
+
+/// Renders the report of the decision log at `path` in `format`.
+pub fn replay(path: &Path, format: Format, verbose: bool) -> Result<String, Error> {
+    let decisions = load(path)?;
+    let mut out = String::new();
+    match format {
+        Format::Text => write_replay(&mut out, &decisions, verbose)?,
+        Format::Json => {
+            let json = serde_json::to_string_pretty(&replayed_report(&decisions))
+                .map_err(|e| Error::Config(format!("cannot serialize report: {}", e)))?;
+            writeln!(out, "{}", json)?;
+        }
+    }
+    Ok(out)
+}
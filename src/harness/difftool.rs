@@ -0,0 +1,93 @@
+use std::path::{Path, PathBuf};
+
+use super::{Error, group::RunOptions, log::Log, trace};
+
+/// Writes the payload of a diverging check next to the snapshots of its command.
+fn write_payload(dir: &Path, name: &str, payload: &str) -> Result<PathBuf, Error> {
+    let path = crate::snapshot_path(dir, &format!("check-{}", name)).with_extension("txt");
+    std::fs::write(&path, format!("{}\n", payload))?;
+    Ok(path)
+}
+
+/// Runs the diff tool configured for the check name of the payloads, if any, with the
+/// files holding them as its last two arguments.
+fn run_tool(
+    options: &RunOptions,
+    dirs: [&Path; 2],
+    payloads: [&str; 2],
+    log: &Log,
+) -> Result<(), Error> {
+    let name = trace::check_name(payloads[0]);
+    let Some(tool) = options
+        .diff_tools
+        .iter()
+        .find(|(check, _)| check == name)
+        .map(|(_, tool)| tool)
+        .or(options.diff_tool.as_ref())
+    else {
+        return Ok(());
+    };
+
+    let files = [
+        write_payload(dirs[0], name, payloads[0])?,
+        write_payload(dirs[1], name, payloads[1])?,
+    ];
+    log.println(format_args!(
+        "\x1b[1;34mRendering check \x1b[1;37m{}\x1b[1;34m with: {}\x1b[0m",
+        name, tool
+    ));
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", tool))
+        .arg("sh")
+        .args(&files)
+        .status()
+        .map_err(|source| Error::Spawn {
+            command: tool.clone(),
+            source,
+        })?;
+    // Diff tools exit unsuccessfully when the inputs differ, which they do by construction
+    if status.code().is_none() {
+        return Err(Error::CommandFailed {
+            command: tool.clone(),
+            status,
+        });
+    }
+    Ok(())
+}
+
+/// Payload of a check, without its prefix.
+pub fn payload(check: &Option<String>) -> Option<&str> {
+    let check = check.as_deref()?;
+    Some(
+        check
+            .strip_prefix(crate::CHECK_PREFIX)
+            .unwrap_or(check)
+            .trim_start(),
+    )
+}
+
+/// Renders the last check of every command diverging from the first command with the
+/// external diff tool configured for it.
+pub fn run_diff_tools(
+    options: &RunOptions,
+    dirs: &[PathBuf],
+    checks: &[Option<String>],
+    log: &Log,
+) {
+    let Some(reference) = payload(&checks[0]) else {
+        return;
+    };
+
+    for (i, check) in checks.iter().enumerate().skip(1) {
+        let Some(other) = payload(check) else {
+            continue;
+        };
+        if other == reference {
+            continue;
+        }
+        if let Err(e) = run_tool(options, [&dirs[0], &dirs[i]], [reference, other], log) {
+            log.eprintln(format_args!("Cannot run the diff tool: {}", e));
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use std::{fmt, process::ExitStatus, time::Duration};
+
+/// Failure of a harness operation.
+#[derive(Debug)]
+pub enum Error {
+    /// The test file is malformed at the given line, counting from 1
+    Parse {
+        line: usize,
+        message: String,
+    },
+    /// The harness was given inconsistent or invalid settings
+    Config(String),
+    Io(std::io::Error),
+    /// A command could not be started
+    Spawn {
+        command: String,
+        source: std::io::Error,
+    },
+    /// A command exited unsuccessfully
+    CommandFailed {
+        command: String,
+        status: ExitStatus,
+    },
+    /// A sandboxed command made a syscall its profile denies, and was killed
+    SandboxViolation {
+        command: String,
+        status: ExitStatus,
+    },
+    /// The output of a command broke the instrumentation protocol
+    Protocol {
+        command: String,
+        message: String,
+    },
+    /// A closure run in place of a command panicked
+    Panicked {
+        command: String,
+        message: String,
+    },
+    /// A command exited without emitting any check; holds the first lines it printed
+    NoInstrumentation {
+        command: String,
+        output: Vec<String>,
+    },
+    /// A check pinned by an `expect` line had another value, or was never emitted
+    Expectation {
+        command: String,
+        check: String,
+        expected: String,
+        actual: Option<String>,
+    },
+    /// A command went past the deadline between two checks
+    Deadline {
+        command: String,
+        elapsed: Duration,
+        deadline: Duration,
+    },
+    /// An analyzer of the test file gave a group a failing verdict
+    Analyzer {
+        analyzer: String,
+        group: String,
+        message: String,
+    },
+    /// The verify command of a group failed
+    Verify {
+        group: String,
+        status: ExitStatus,
+    },
+    /// The output directories of the commands differ, in the given number of files
+    FileTree {
+        group: String,
+        files: usize,
+    },
+    /// Commands took too long to reach checkpoints compared to the first command
+    Timing {
+        group: String,
+        checkpoints: Vec<String>,
+    },
+    /// The runtime checks of a group diverged; holds the number of the diverging check,
+    /// counting from 1, and the last check of every command
+    Mismatch {
+        group: String,
+        check: usize,
+        checks: Vec<(String, Option<String>)>,
+    },
+    /// The runtime checks of a group diverged at the given check, but not when the group
+    /// ran again to confirm it
+    Unconfirmed {
+        group: String,
+        check: usize,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            Error::Config(message) => write!(f, "{}", message),
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Spawn { command, source } => {
+                write!(
+                    f,
+                    "Failed to execute test command '{}': {}",
+                    command, source
+                )
+            }
+            Error::CommandFailed { command, status } => {
+                write!(
+                    f,
+                    "Test command '{}' failed with status: {}",
+                    command, status
+                )
+            }
+            Error::SandboxViolation { command, status } => write!(
+                f,
+                "Test command '{}' violated its sandbox and was killed: {}",
+                command, status
+            ),
+            Error::Protocol { command, message } => {
+                write!(f, "Protocol error in '{}': {}", command, message)
+            }
+            Error::Panicked { command, message } => {
+                write!(f, "'{}' panicked: {}", command, message)
+            }
+            Error::NoInstrumentation { command, output } => {
+                write!(f, "No instrumentation detected for '{}'", command)?;
+                if output.is_empty() {
+                    return write!(f, ", it printed nothing");
+                }
+                write!(f, ", its output starts with:")?;
+                for line in output {
+                    write!(f, "\n  {}", line)?;
+                }
+                Ok(())
+            }
+            Error::Expectation {
+                command,
+                check,
+                expected,
+                actual: Some(actual),
+            } => write!(
+                f,
+                "'{}' gave check '{}' the value '{}', expected '{}'",
+                command, check, actual, expected
+            ),
+            Error::Expectation {
+                command,
+                check,
+                actual: None,
+                ..
+            } => write!(
+                f,
+                "'{}' never emitted the expected check '{}'",
+                command, check
+            ),
+            Error::Deadline {
+                command,
+                elapsed,
+                deadline,
+            } => write!(
+                f,
+                "Test command '{}' emitted no check for {:?}, over the deadline of {:?}",
+                command, elapsed, deadline
+            ),
+            Error::Analyzer {
+                analyzer,
+                group,
+                message,
+            } => write!(f, "Analyzer '{}' failed '{}': {}", analyzer, group, message),
+            Error::Verify { group, status } => {
+                write!(
+                    f,
+                    "Verify command of '{}' failed with status: {}",
+                    group, status
+                )
+            }
+            Error::FileTree { group, files } => write!(
+                f,
+                "Output files of '{}' differ: {} added, removed or modified",
+                group, files
+            ),
+            Error::Timing { group, checkpoints } => write!(
+                f,
+                "Checkpoints of '{}' slower than their threshold: {}",
+                group,
+                checkpoints.join(", ")
+            ),
+            Error::Mismatch { group, .. } => {
+                write!(f, "Mismatch detected in runtime checks of '{}'", group)
+            }
+            Error::Unconfirmed { group, check } => write!(
+                f,
+                "Runtime checks of '{}' diverged at check {} but not when run again",
+                group, check
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) | Error::Spawn { source: e, .. } => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+/// Reports are formatted into strings, this only surfaces a failing `Display` impl.
+impl From<fmt::Error> for Error {
+    fn from(e: fmt::Error) -> Self {
+        Error::Io(std::io::Error::other(e))
+    }
+}
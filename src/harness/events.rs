@@ -0,0 +1,50 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{Value, json};
+
+use super::Error;
+
+/// Shortest time between two events reporting the checks matched by a group.
+pub const PROGRESS_PERIOD: Duration = Duration::from_millis(250);
+
+/// Destination of the lifecycle events of a run as JSON lines, for IDE plugins and
+/// scripts following it live.
+pub struct EventLog(Mutex<File>);
+
+impl EventLog {
+    /// Opens `target`, a file or `fd:N` for a descriptor inherited from the parent.
+    pub fn open(target: &str) -> Result<Self, Error> {
+        let file = match target.strip_prefix("fd:") {
+            Some(fd) => {
+                let fd: u32 = fd
+                    .parse()
+                    .map_err(|_| Error::Config(format!("invalid file descriptor '{}'", fd)))?;
+                OpenOptions::new()
+                    .write(true)
+                    .open(format!("/dev/fd/{}", fd))?
+            }
+            None => File::create(target)?,
+        };
+        Ok(Self(Mutex::new(file)))
+    }
+
+    /// Writes an event of the given kind, with the fields of the `fields` object.
+    pub fn emit(&self, kind: &str, fields: Value) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let mut event = json!({ "event": kind, "time": time });
+        if let (Value::Object(event), Value::Object(fields)) = (&mut event, fields) {
+            event.extend(fields);
+        }
+        // The consumer going away must not stop the run
+        let mut file = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let _ = writeln!(file, "{}", event).and_then(|_| file.flush());
+    }
+}
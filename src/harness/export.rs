@@ -0,0 +1,82 @@
+use std::{fmt::Write, str::FromStr};
+
+use super::{Error, json, trace::Trace};
+
+/// Columns of an exported trace, in order.
+const COLUMNS: &[&str] = &["sequence", "elapsed_us", "channel", "name", "message"];
+
+/// Format of a trace converted for external analysis tools.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// One JSON object per line
+    Jsonl,
+    /// Comma separated values with a header row
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(format: &str) -> Result<Self, Error> {
+        match format {
+            "jsonl" => Ok(Format::Jsonl),
+            "csv" => Ok(Format::Csv),
+            _ => Err(Error::Config(format!(
+                "unknown export format '{}', expected jsonl or csv",
+                format
+            ))),
+        }
+    }
+}
+
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// Writes every entry of `trace` as a record with its position in the stream, the time
+/// it was received, its channel (check or breadcrumb), the check name and the payload.
+pub fn write_export(out: &mut impl Write, trace: &Trace, format: Format) -> std::fmt::Result {
+    if format == Format::Csv {
+        writeln!(out, "{}", COLUMNS.join(","))?;
+    }
+
+    for (sequence, entry) in trace.entries.iter().enumerate() {
+        let elapsed = trace
+            .timestamps
+            .get(sequence)
+            .copied()
+            .flatten()
+            .map(|elapsed| elapsed.as_micros().to_string());
+        let channel = if entry.is_check() {
+            "check"
+        } else {
+            "breadcumb"
+        };
+
+        match format {
+            Format::Jsonl => writeln!(
+                out,
+                "{{\"sequence\":{},\"elapsed_us\":{},\"channel\":\"{}\",\"name\":{},\"message\":{}}}",
+                sequence,
+                elapsed.as_deref().unwrap_or("null"),
+                channel,
+                entry.name().map_or("null".to_string(), json::string),
+                json::string(entry.payload())
+            )?,
+            Format::Csv => writeln!(
+                out,
+                "{},{},{},{},{}",
+                sequence,
+                elapsed.unwrap_or_default(),
+                channel,
+                csv_field(entry.name().unwrap_or_default()),
+                csv_field(entry.payload())
+            )?,
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,256 @@
+use std::{borrow::Cow, str::FromStr};
+
+use regex::Regex;
+
+/// Separator of the pattern of an extraction rule from its assignments.
+const RULE_SEPARATOR: &str = "=>";
+
+/// Expression computing part of a check from the payload matched by a rule.
+#[derive(Debug, Clone)]
+enum Expr {
+    /// `regex_capture(n)`: capture group `n` of the pattern of the rule
+    Capture(usize),
+    /// `json_path($.a[0].b)`: value at the path in the first JSON object or array of the
+    /// payload
+    JsonPath(Vec<PathStep>),
+    /// `word(n)`: `n`th whitespace separated word of the payload, from 0
+    Word(usize),
+}
+
+#[derive(Debug, Clone)]
+enum PathStep {
+    Field(String),
+    Index(usize),
+}
+
+impl FromStr for Expr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (function, argument) = s
+            .trim()
+            .strip_suffix(')')
+            .and_then(|call| call.split_once('('))
+            .ok_or_else(|| format!("expected 'function(argument)', got '{}'", s.trim()))?;
+        let argument = argument.trim();
+        let index = || {
+            argument
+                .parse()
+                .map_err(|_| format!("invalid index '{}' in '{}'", argument, s.trim()))
+        };
+        match function.trim() {
+            "regex_capture" => Ok(Expr::Capture(index()?)),
+            "word" => Ok(Expr::Word(index()?)),
+            "json_path" => parse_json_path(argument).map(Expr::JsonPath),
+            function => Err(format!("unknown function '{}'", function)),
+        }
+    }
+}
+
+fn parse_json_path(path: &str) -> Result<Vec<PathStep>, String> {
+    let invalid = || format!("invalid JSON path '{}'", path);
+    let mut rest = path.strip_prefix('$').ok_or_else(invalid)?;
+    let mut steps = Vec::new();
+    while !rest.is_empty() {
+        if let Some(field) = rest.strip_prefix('.') {
+            let end = field.find(['.', '[']).unwrap_or(field.len());
+            if end == 0 {
+                return Err(invalid());
+            }
+            steps.push(PathStep::Field(field[..end].to_string()));
+            rest = &field[end..];
+        } else if let Some((index, after)) = rest
+            .strip_prefix('[')
+            .and_then(|indexed| indexed.split_once(']'))
+        {
+            steps.push(PathStep::Index(
+                index.trim().parse().map_err(|_| invalid())?,
+            ));
+            rest = after;
+        } else {
+            return Err(invalid());
+        }
+    }
+    Ok(steps)
+}
+
+impl Expr {
+    fn eval(&self, payload: &str, captures: &regex::Captures) -> Option<String> {
+        match self {
+            Expr::Capture(group) => Some(captures.get(*group)?.as_str().to_string()),
+            Expr::Word(index) => Some(payload.split_whitespace().nth(*index)?.to_string()),
+            Expr::JsonPath(steps) => {
+                let start = payload.find(['{', '['])?;
+                let mut stream = serde_json::Deserializer::from_str(&payload[start..]).into_iter();
+                let document: serde_json::Value = stream.next()?.ok()?;
+                let value = steps.iter().try_fold(&document, |value, step| match step {
+                    PathStep::Field(field) => value.get(field),
+                    PathStep::Index(index) => value.get(index),
+                })?;
+                Some(match value {
+                    serde_json::Value::String(text) => text.clone(),
+                    value => value.to_string(),
+                })
+            }
+        }
+    }
+}
+
+/// Rule of the `extract` section, giving the comparable portion of the checks whose
+/// payload matches its pattern as `pattern => key = expr; value = expr`.
+#[derive(Debug, Clone)]
+pub struct Extraction {
+    pattern: Regex,
+    /// Identifies the check, its name if not given
+    key: Option<Expr>,
+    /// Compared part of the check, the rest of its payload if not given
+    value: Option<Expr>,
+}
+
+impl FromStr for Extraction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pattern, assignments) = s.rsplit_once(RULE_SEPARATOR).ok_or_else(|| {
+            format!(
+                "expected 'pattern {} key = expr; value = expr', got '{}'",
+                RULE_SEPARATOR, s
+            )
+        })?;
+        let pattern =
+            Regex::new(pattern.trim()).map_err(|e| format!("invalid extraction pattern: {}", e))?;
+        let mut extraction = Self {
+            pattern,
+            key: None,
+            value: None,
+        };
+        for assignment in assignments.split(';').filter(|a| !a.trim().is_empty()) {
+            let (field, expr) = assignment
+                .split_once('=')
+                .ok_or_else(|| format!("expected 'field = expr', got '{}'", assignment.trim()))?;
+            let expr = Some(expr.parse()?);
+            match field.trim() {
+                "key" => extraction.key = expr,
+                "value" => extraction.value = expr,
+                field => return Err(format!("unknown field '{}', expected key or value", field)),
+            }
+        }
+        Ok(extraction)
+    }
+}
+
+impl Extraction {
+    pub fn pattern(&self) -> &str {
+        self.pattern.as_str()
+    }
+}
+
+/// Comparable form of `check`, as extracted by the first rule matching its payload.
+pub fn extract<'a>(extractions: &[Extraction], check: &'a str) -> Cow<'a, str> {
+    match extract_with_rule(extractions, check) {
+        Some((_, extracted)) => Cow::Owned(extracted),
+        None => Cow::Borrowed(check),
+    }
+}
+
+/// Index of the first rule matching the payload of `check` and the form it extracts.
+pub fn extract_with_rule(extractions: &[Extraction], check: &str) -> Option<(usize, String)> {
+    let payload = check.strip_prefix(crate::CHECK_PREFIX).map(str::trim)?;
+    let (rule, extraction, captures) =
+        extractions
+            .iter()
+            .enumerate()
+            .find_map(|(rule, extraction)| {
+                Some((rule, extraction, extraction.pattern.captures(payload)?))
+            })?;
+
+    let (name, rest) = payload
+        .split_once(char::is_whitespace)
+        .unwrap_or((payload, ""));
+    let eval = |expr: &Option<Expr>, default: &str| match expr {
+        Some(expr) => expr
+            .eval(payload, &captures)
+            .unwrap_or_else(|| "<none>".to_string()),
+        None => default.trim().to_string(),
+    };
+    let extracted = format!(
+        "{} {} {}",
+        crate::CHECK_PREFIX,
+        eval(&extraction.key, name),
+        eval(&extraction.value, rest)
+    );
+    Some((rule, extracted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extracted(rule: &str, payload: &str) -> String {
+        let extractions = [rule.parse::<Extraction>().unwrap()];
+        let check = format!("{} {}", crate::CHECK_PREFIX, payload);
+        extract(&extractions, &check).into_owned()
+    }
+
+    #[test]
+    fn json_paths_are_parsed() {
+        let error = parse_json_path("$.a[0] .b").unwrap_err();
+        assert!(error.contains("invalid JSON path"), "{}", error);
+        let steps = parse_json_path("$.a[ 2 ].b").unwrap();
+        assert!(matches!(
+            steps.as_slice(),
+            [PathStep::Field(a), PathStep::Index(2), PathStep::Field(b)] if a == "a" && b == "b"
+        ));
+        assert!(parse_json_path("$").unwrap().is_empty());
+        for invalid in ["a.b", "$.", "$..a", "$[x]", "$[1", "$a"] {
+            assert!(parse_json_path(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn expressions_call_a_known_function() {
+        assert!(matches!("regex_capture(1)".parse(), Ok(Expr::Capture(1))));
+        assert!(matches!(" word( 2 ) ".parse(), Ok(Expr::Word(2))));
+        assert!(matches!("json_path($.a)".parse(), Ok(Expr::JsonPath(_))));
+        for invalid in ["word", "word(x)", "word(-1)", "upper(1)", "json_path(a)"] {
+            assert!(invalid.parse::<Expr>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn rules_assign_key_and_value() {
+        assert!("request".parse::<Extraction>().is_err());
+        assert!("(".parse::<Extraction>().is_err());
+        assert!("request => name = word(0)".parse::<Extraction>().is_err());
+        assert!("request => key".parse::<Extraction>().is_err());
+        // The last separator splits the rule, so patterns may contain one
+        let rule: Extraction = "a=>b => key = word(0);".parse().unwrap();
+        assert_eq!(rule.pattern(), "a=>b");
+    }
+
+    #[test]
+    fn checks_are_extracted_by_their_rule() {
+        let rule = r"^req (\d+) took => key = word(0); value = regex_capture(1)";
+        assert_eq!(
+            extracted(rule, "req 12 took 3ms"),
+            format!("{} req 12", crate::CHECK_PREFIX)
+        );
+        let rule = "^reply => value = json_path($.items[1].id)";
+        assert_eq!(
+            extracted(
+                rule,
+                r#"reply {"items": [{"id": 1}, {"id": "b"}]} trailing"#
+            ),
+            format!("{} reply b", crate::CHECK_PREFIX)
+        );
+        assert_eq!(
+            extracted(rule, r#"reply {"items": []}"#),
+            format!("{} reply <none>", crate::CHECK_PREFIX)
+        );
+        // Checks no rule matches are compared whole
+        assert_eq!(
+            extracted(rule, "other 1"),
+            format!("{} other 1", crate::CHECK_PREFIX)
+        );
+    }
+}
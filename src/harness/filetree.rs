@@ -0,0 +1,122 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use super::Error;
+
+/// State of a file left by a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileState {
+    pub size: u64,
+    pub digest: u64,
+    /// Permission bits
+    pub mode: u32,
+}
+
+/// Files below a directory by relative path; a missing directory has none.
+pub type FileTree = BTreeMap<PathBuf, FileState>;
+
+/// Records every file below `root`, following no symbolic links.
+pub fn snapshot_tree(root: &Path) -> Result<FileTree, Error> {
+    fn visit(root: &Path, dir: &Path, tree: &mut FileTree) -> Result<(), Error> {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let metadata = std::fs::symlink_metadata(&path)?;
+            if metadata.is_dir() {
+                visit(root, &path, tree)?;
+                continue;
+            }
+            let contents = if metadata.is_symlink() {
+                std::fs::read_link(&path)?
+                    .as_os_str()
+                    .as_encoded_bytes()
+                    .to_vec()
+            } else {
+                std::fs::read(&path)?
+            };
+            tree.insert(
+                path.strip_prefix(root).unwrap_or(&path).to_path_buf(),
+                FileState {
+                    size: metadata.len(),
+                    digest: crate::digest(&contents),
+                    mode: metadata.permissions().mode() & 0o7777,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    let mut tree = FileTree::new();
+    if root.exists() {
+        visit(root, root, &mut tree)?;
+    }
+    Ok(tree)
+}
+
+/// Describes how `other` differs from `reference`, file by file.
+fn diff_trees(reference: &FileTree, other: &FileTree) -> Vec<String> {
+    let mut differences = Vec::new();
+    for (path, state) in reference {
+        let Some(other_state) = other.get(path) else {
+            differences.push(format!("\x1b[1;31mremoved\x1b[0m  {}", path.display()));
+            continue;
+        };
+        let mut changes = Vec::new();
+        if state.size != other_state.size {
+            changes.push(format!("size {} -> {}", state.size, other_state.size));
+        }
+        if state.mode != other_state.mode {
+            changes.push(format!("mode {:o} -> {:o}", state.mode, other_state.mode));
+        }
+        if state.digest != other_state.digest && state.size == other_state.size {
+            changes.push("content differs".to_string());
+        }
+        if !changes.is_empty() {
+            differences.push(format!(
+                "\x1b[1;33mmodified\x1b[0m {}: {}",
+                path.display(),
+                changes.join(", ")
+            ));
+        }
+    }
+    for (path, state) in other {
+        if !reference.contains_key(path) {
+            differences.push(format!(
+                "\x1b[1;32madded\x1b[0m    {} ({} bytes)",
+                path.display(),
+                state.size
+            ));
+        }
+    }
+    differences
+}
+
+/// Writes how the trees left by every command differ from the one left by the first
+/// command, returning the number of differing files.
+pub fn write_tree_diffs(
+    out: &mut impl Write,
+    names: &[&str],
+    dirs: &[&str],
+    trees: &[FileTree],
+) -> Result<usize, std::fmt::Error> {
+    let mut count = 0;
+    for i in 1..trees.len() {
+        let differences = diff_trees(&trees[0], &trees[i]);
+        if differences.is_empty() {
+            continue;
+        }
+        count += differences.len();
+        writeln!(
+            out,
+            "\x1b[1;34mFiles of \x1b[1;37m{}\x1b[1;34m ({}) differ from \x1b[1;37m{}\x1b[1;34m ({}):\x1b[0m",
+            names[i], dirs[i], names[0], dirs[0]
+        )?;
+        for difference in differences {
+            writeln!(out, "  {}", difference)?;
+        }
+    }
+    Ok(count)
+}
@@ -0,0 +1,211 @@
+use std::{
+    collections::HashMap,
+    fmt::{Result, Write},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+/// Environment variables that commonly change the behavior of a program.
+const WATCHED_ENV: &[&str] = &[
+    "PATH",
+    "LD_LIBRARY_PATH",
+    "LD_PRELOAD",
+    "LANG",
+    "LC_ALL",
+    "TZ",
+];
+
+/// Container runtimes whose `run` subcommand is recognized to fingerprint the image.
+const CONTAINER_RUNTIMES: &[&str] = &["docker", "podman"];
+
+/// Container runtime options taking their value as a separate word.
+const CONTAINER_VALUE_OPTIONS: &[&str] = &[
+    "-e",
+    "--env",
+    "-v",
+    "--volume",
+    "-w",
+    "--workdir",
+    "-u",
+    "--user",
+    "--name",
+    "--network",
+    "--entrypoint",
+    "--platform",
+    "--mount",
+];
+
+/// Description of the context a test command executes in.
+pub struct Fingerprint {
+    binary: Option<(PathBuf, u64)>,
+    image: Option<String>,
+    glibc: Option<&'static str>,
+    env: Vec<(&'static str, Option<String>)>,
+    /// Environment variables explicitly assigned on the command line
+    declared_env: Vec<String>,
+}
+
+impl Fingerprint {
+    pub fn collect(command: &str) -> Self {
+        let mut words = command.split_whitespace().peekable();
+        let mut assigned = HashMap::new();
+        while let Some((name, value)) = words.peek().and_then(|word| word.split_once('=')) {
+            assigned.insert(name.to_string(), value.to_string());
+            words.next();
+        }
+
+        let program = words.next().unwrap_or_default();
+        let image = match words.next() {
+            Some("run") if CONTAINER_RUNTIMES.contains(&program) => {
+                container_image(program, &mut words)
+            }
+            _ => None,
+        };
+
+        Self {
+            binary: resolve_program(program)
+                .and_then(|path| Some((path.clone(), crate::digest(&std::fs::read(&path).ok()?)))),
+            glibc: if image.is_none() { host_glibc() } else { None },
+            image,
+            env: WATCHED_ENV
+                .iter()
+                .map(|name| {
+                    let value = assigned
+                        .get(*name)
+                        .cloned()
+                        .or_else(|| std::env::var(name).ok());
+                    (*name, value)
+                })
+                .collect(),
+            declared_env: assigned.into_keys().collect(),
+        }
+    }
+
+    /// Identity of what is being executed: the container image, or the binary contents.
+    fn identity(&self) -> Option<String> {
+        match (&self.image, &self.binary) {
+            (Some(image), _) => Some(format!("image {}", image)),
+            (None, Some((_, digest))) => Some(format!("binary digest {:016x}", digest)),
+            (None, None) => None,
+        }
+    }
+}
+
+fn resolve_program(program: &str) -> Option<PathBuf> {
+    if program.contains('/') {
+        return Some(PathBuf::from(program)).filter(|path| path.is_file());
+    }
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|path| path.is_file())
+}
+
+fn container_image<'a>(runtime: &str, words: &mut impl Iterator<Item = &'a str>) -> Option<String> {
+    let image = loop {
+        let word = words.next()?;
+        if CONTAINER_VALUE_OPTIONS.contains(&word) {
+            words.next();
+        } else if !word.starts_with('-') {
+            break word;
+        }
+    };
+
+    let output = std::process::Command::new(runtime)
+        .args(["image", "inspect", "--format", "{{.Id}}", image])
+        .output()
+        .ok()?;
+    let digest = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Some(if output.status.success() && !digest.is_empty() {
+        digest
+    } else {
+        format!("{} (unresolved)", image)
+    })
+}
+
+fn host_glibc() -> Option<&'static str> {
+    static GLIBC: OnceLock<Option<String>> = OnceLock::new();
+    GLIBC
+        .get_or_init(|| {
+            let output = std::process::Command::new("getconf")
+                .arg("GNU_LIBC_VERSION")
+                .output()
+                .ok()?;
+            output
+                .status
+                .success()
+                .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        })
+        .as_deref()
+}
+
+fn display(path: Option<&Path>) -> String {
+    path.map(|path| path.display().to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+pub fn write_fingerprints(
+    out: &mut impl Write,
+    names: &[&str],
+    fingerprints: &[Fingerprint],
+) -> Result {
+    writeln!(out, "\x1b[1;34mExecution fingerprints:\x1b[0m")?;
+    for (name, fingerprint) in names.iter().zip(fingerprints) {
+        writeln!(out, "  \x1b[1;37m{}\x1b[0m", name)?;
+        write_field(
+            out,
+            "binary",
+            display(fingerprint.binary.as_ref().map(|(path, _)| path.as_path())),
+        )?;
+        write_field(
+            out,
+            "identity",
+            fingerprint.identity().as_deref().unwrap_or("-"),
+        )?;
+        write_field(out, "glibc", fingerprint.glibc.unwrap_or("-"))?;
+        for (var, value) in &fingerprint.env {
+            write_field(out, var, value.as_deref().unwrap_or("-"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Compares the fingerprints pairwise, returning a warning for each pair executing the
+/// same thing or running in contexts that differ in ways not declared in the test file.
+pub fn compare_fingerprints(names: &[&str], fingerprints: &[Fingerprint]) -> Vec<String> {
+    let mut warnings = Vec::new();
+    for a in 0..fingerprints.len() {
+        for b in a + 1..fingerprints.len() {
+            let (fa, fb) = (&fingerprints[a], &fingerprints[b]);
+            if let Some(identity) = fa.identity()
+                && fb.identity().as_ref() == Some(&identity)
+            {
+                warnings.push(format!(
+                    "'{}' and '{}' run the same {}: is this diffing a binary against itself?",
+                    names[a], names[b], identity
+                ));
+            }
+            if let (Some(glibc_a), Some(glibc_b)) = (fa.glibc, fb.glibc)
+                && glibc_a != glibc_b
+            {
+                warnings.push(format!(
+                    "'{}' and '{}' run with different glibc: {} vs {}",
+                    names[a], names[b], glibc_a, glibc_b
+                ));
+            }
+            for ((var, va), (_, vb)) in fa.env.iter().zip(&fb.env) {
+                let declared = |f: &Fingerprint| f.declared_env.iter().any(|name| name == var);
+                if va != vb && !declared(fa) && !declared(fb) {
+                    warnings.push(format!(
+                        "'{}' and '{}' run with undeclared different {}",
+                        names[a], names[b], var
+                    ));
+                }
+            }
+        }
+    }
+    warnings
+}
+
+fn write_field(out: &mut impl Write, name: &str, value: impl std::fmt::Display) -> Result {
+    writeln!(out, "    {:<16} {}", name, value)
+}
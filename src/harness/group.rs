@@ -0,0 +1,2024 @@
+use std::{
+    collections::VecDeque,
+    fmt::Write,
+    os::unix::process::ExitStatusExt,
+    path::{Path, PathBuf},
+    process::ExitStatus,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+        mpsc::Sender,
+    },
+    time::{Duration, Instant},
+};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "zstd")]
+use super::compression;
+use super::{
+    Error,
+    agent::{Process, RemoteProcess},
+    analyzer::{self, Analyzers},
+    bench,
+    breakpoint::{self, Breakpoint, BreakpointStop, Stopped, StoppedProcesses},
+    budget::{self, BufferBudget},
+    channels::{self, Agreements, Channels},
+    coverage::{self, Coverage},
+    deadline::CheckDeadline,
+    debuglog::{self, DebugLog},
+    decisions::{self, Decision, DecisionLog},
+    difftool, filetree, fingerprint,
+    inprocess::InProcessCommand,
+    jitter::Jitter,
+    log::{CommandOutput, Log},
+    monitor::Monitor,
+    numeric,
+    protocol::{self, Line, StreamProtocol, WriterId},
+    rate::{self, RateMonitor},
+    rawlog::{self, RawLog},
+    report::{GroupReport, Report},
+    resume::{self, ResumeFile, ResumePoint},
+    rules::{Normalizations, SharedRules},
+    sandbox,
+    sink::{self, Tail},
+    snapshot,
+    stats::Stats,
+    testfile::{TestCommand, TestGroup},
+    throttle::{Throttle, Throttles},
+    trace::{self, Entry, Trace, TraceWriter},
+    truncate,
+    unstable::{SiteRun, UnstableSites},
+    wrapper::{self, Wrapper},
+};
+
+#[derive(Clone)]
+pub struct RunOptions {
+    pub max_breadcumbs: usize,
+    /// Checks all the commands agreed on kept per channel, shown with a mismatch
+    pub last_agreements: usize,
+    pub snapshot_dir: PathBuf,
+    pub fingerprint: bool,
+    /// Live state of the run, for a dashboard
+    pub monitor: Option<Arc<Monitor>>,
+    /// Directory where the messages of every command are recorded as traces
+    pub record_dir: Option<PathBuf>,
+    /// Command rendering the payloads of a diverging check, given the files holding them
+    pub diff_tool: Option<String>,
+    /// Diff tools for specific check names, taking precedence over `diff_tool`
+    pub diff_tools: Vec<(String, String)>,
+    /// Analyzers started for every group run, by name, given the matched checks and
+    /// divergences and failing the group on a failing verdict
+    pub analyzers: Vec<(String, String)>,
+    /// Largest difference between two elements of matching arrays or images
+    pub tolerance: f64,
+    /// Tolerances for specific arrays and images, taking precedence over `tolerance`,
+    /// extraction and ignore rules and verbosity, reloaded during the run
+    pub rules: Arc<SharedRules>,
+    /// Most checks a command may get ahead of the comparison before the harness stops
+    /// reading its output
+    pub max_lead: Option<usize>,
+    /// Directory of recorded traces, replayed as the reference of the commands they
+    /// were recorded from so that a group may run a single live command
+    pub reference_dir: Option<PathBuf>,
+    /// Closures run in place of the commands with the same name
+    pub in_process: Vec<InProcessCommand>,
+    /// Rules routing the checks matching a pattern to a channel, compared in lockstep
+    /// independently of the other channels
+    pub routes: Vec<(String, Regex)>,
+    /// Directory where the raw output of every command is logged, to show the lines it
+    /// printed around a divergence
+    pub log_dir: Option<PathBuf>,
+    /// Run a group again with debug breadcrumbs enabled when it diverges, up to the check
+    /// where it did
+    pub debug_rerun: bool,
+    /// Check at which every command is stopped for debuggers to be attached
+    pub break_at: Option<Breakpoint>,
+    /// Command run with the process ids of the commands still running when they diverge,
+    /// which are stopped until it exits; the commands run in lockstep with the comparison
+    /// meanwhile, stopped while they are ahead of it
+    pub divergence_hook: Option<String>,
+    /// Tool every command runs under, to rule out nondeterministic scheduling
+    pub wrapper: Option<Wrapper>,
+    /// Run diverging groups again up to the diverging check, marking the divergences that
+    /// do not reproduce as flaky
+    pub confirm: bool,
+    /// Random delays before the commands start, shuffling their start order
+    pub jitter: Option<Jitter>,
+    /// Print the output passed through by every command in one block once it ends,
+    /// instead of line by line
+    pub group_output: bool,
+    /// Fail groups whose configuration is suspicious instead of warning about it
+    pub strict_config: bool,
+    /// Bytes the harness may hold for the messages of a group before shrinking the
+    /// breadcrumb windows and retaining checks as digests only
+    pub max_buffer_mem: Option<usize>,
+    /// Only compare how long the commands take between checks, not their values
+    pub bench: bool,
+    /// Largest ratio of the time a command takes before a checkpoint to the time the first
+    /// command takes, by check name
+    pub thresholds: Vec<(String, f64)>,
+    /// Longest time a command may go without emitting a check before it is warned about
+    pub check_deadline: Option<Duration>,
+    /// Fail the group, stopping the command, when a command goes past the check deadline
+    pub fail_on_deadline: bool,
+    /// Bytes of a message shown in reports, longer ones being truncated and saved in full
+    /// next to the snapshots, 0 for no limit
+    pub max_payload_bytes: usize,
+    /// Log of the decisions of the harness itself
+    pub debug_log: Option<Arc<DebugLog>>,
+    /// Log of every comparison decision, to render the report again later
+    pub decision_log: Option<Arc<DecisionLog>>,
+    /// Counts of the checks of every group, by channel and check name
+    pub stats: Option<Arc<Stats>>,
+    /// Check sites of the commands across the repeats of their groups
+    pub unstable_sites: Option<Arc<UnstableSites>>,
+    /// Progress of the groups, saved periodically and resumed from after a crash
+    pub resume: Option<Arc<ResumeFile>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Passed,
+    /// The runtime checks of the commands diverged
+    Mismatch,
+    /// A command could not be run or exited unsuccessfully
+    Failed,
+    /// A sandboxed command did something its profile forbids
+    Sandbox,
+    /// A group this one depends on did not pass, so it was not run
+    Skipped,
+    /// The runtime checks diverged, but not when the group ran again to confirm it
+    Flaky,
+}
+
+impl Outcome {
+    pub fn of(result: &Result<(), Error>) -> Self {
+        match result {
+            Ok(()) => Outcome::Passed,
+            Err(
+                Error::Mismatch { .. }
+                | Error::Expectation { .. }
+                | Error::Timing { .. }
+                | Error::FileTree { .. },
+            ) => Outcome::Mismatch,
+            Err(Error::SandboxViolation { .. }) => Outcome::Sandbox,
+            Err(Error::Unconfirmed { .. }) => Outcome::Flaky,
+            Err(_) => Outcome::Failed,
+        }
+    }
+
+    pub fn passed(self) -> bool {
+        self == Outcome::Passed
+    }
+}
+
+/// Recent breadcrumbs shown with a check rate collapse.
+const RATE_BREADCUMBS: usize = 8;
+/// Lines of output kept to show what a command printed instead of checks.
+const OUTPUT_SAMPLE_LINES: usize = 10;
+/// How long a command may keep running once its output ended.
+const EARLY_EOF_TIMEOUT: Duration = Duration::from_secs(10);
+
+enum CommandData {
+    /// The command was spawned at the given time, as the process with the given id if it
+    /// runs locally
+    Started(Option<u32>, Instant),
+    /// A check, with the process that wrote it and the time it was read at
+    Check(String, WriterId, Instant),
+    Breadcumb(String),
+    Failed(Error),
+}
+
+/// Runs the commands of a comparison group, comparing their runtime checks.
+pub fn run_group(group: &TestGroup, options: &RunOptions, log: &Log) -> Result<(), Error> {
+    let mut analyzers = match Analyzers::start(&options.analyzers, group) {
+        Ok(analyzers) => analyzers,
+        Err(e) => {
+            log.eprintln(&e);
+            return Err(e);
+        }
+    };
+    let mut normalizations = Normalizations::default();
+    let mut result = monitored_comparison(
+        group,
+        options,
+        log,
+        None,
+        &mut analyzers,
+        &mut normalizations,
+    );
+    if !normalizations.rules().is_empty() {
+        let mut summary = String::new();
+        let _ = write_normalizations(&mut summary, group, &normalizations, options);
+        log.println(summary.trim_end());
+    }
+    let analyzed = analyzers.finish(result.is_ok(), log);
+    if result.is_ok() {
+        result = analyzed;
+    }
+    if options.confirm
+        && let Err(Error::Mismatch { check, .. }) = &result
+    {
+        result = confirm_mismatch(group, options, log, *check, result);
+    }
+    if options.debug_rerun
+        && let Err(Error::Mismatch { check, .. }) = &result
+    {
+        log.println(format_args!(
+            "\x1b[1;33mRe-running with {}={} up to check {}\x1b[0m",
+            crate::LEVEL_ENV,
+            crate::DEBUG_LEVEL,
+            check
+        ));
+        let debug_group = group.with_variable(crate::LEVEL_ENV, crate::DEBUG_LEVEL);
+        let mut analyzers = Analyzers::default();
+        if monitored_comparison(
+            &debug_group,
+            &rerun_options(options),
+            log,
+            Some(*check),
+            &mut analyzers,
+            &mut Normalizations::default(),
+        )
+        .is_ok()
+        {
+            log.println(format_args!(
+                "\x1b[1;33mThe debug run did not diverge by check {}\x1b[0m",
+                check
+            ));
+        }
+    }
+
+    // A command that crashed leaves the progress of the group to resume from
+    if let Some(resume) = &options.resume
+        && Outcome::of(&result) != Outcome::Failed
+        && let Err(e) = resume.remove(&group.label())
+    {
+        log.eprintln(format_args!("Cannot save checkpoints: {}", e));
+    }
+
+    if let Some(verify) = &group.verify {
+        let verified = run_verify(group, verify, &result, options, log);
+        if let Err(e) = &verified {
+            log.eprintln(e);
+        }
+        if result.is_ok() {
+            result = verified;
+        }
+    }
+    if let Some(decision_log) = &options.decision_log {
+        decision_log.record(&Decision::End {
+            group: group.label(),
+            outcome: Outcome::of(&result),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        });
+    }
+    result
+}
+
+/// Options of a rerun of a group, which runs its commands as the first run did without
+/// recording anything over what the first run recorded.
+fn rerun_options(options: &RunOptions) -> RunOptions {
+    RunOptions {
+        record_dir: None,
+        decision_log: None,
+        stats: None,
+        unstable_sites: None,
+        resume: None,
+        ..options.clone()
+    }
+}
+
+/// Runs the group again up to the check its commands diverged at, returning `mismatch` if
+/// they diverge again, or else an error marking the divergence as flaky.
+fn confirm_mismatch(
+    group: &TestGroup,
+    options: &RunOptions,
+    log: &Log,
+    check: usize,
+    mismatch: Result<(), Error>,
+) -> Result<(), Error> {
+    log.println(format_args!(
+        "\x1b[1;33mRe-running '{}' up to check {} to confirm the divergence\x1b[0m",
+        group.label(),
+        check
+    ));
+    let rerun = monitored_comparison(
+        group,
+        &rerun_options(options),
+        log,
+        Some(check),
+        &mut Analyzers::default(),
+        &mut Normalizations::default(),
+    );
+    match rerun {
+        Err(Error::Mismatch { .. }) => {
+            log.println(format_args!(
+                "\x1b[1;31mThe divergence of '{}' reproduced\x1b[0m",
+                group.label()
+            ));
+            mismatch
+        }
+        Ok(()) => {
+            let unconfirmed = Error::Unconfirmed {
+                group: group.label(),
+                check,
+            };
+            log.println(format_args!(
+                "\x1b[1;33m{}, marking it flaky\x1b[0m",
+                unconfirmed
+            ));
+            Err(unconfirmed)
+        }
+        // The re-run could not tell, e.g. a command failed, so the divergence stands
+        Err(e) => {
+            log.println(format_args!(
+                "\x1b[1;33mCannot confirm the divergence of '{}': {}\x1b[0m",
+                group.label(),
+                e
+            ));
+            mismatch
+        }
+    }
+}
+
+/// Runs the verify command of a group with the path of its report as argument, once its
+/// commands exited.
+fn run_verify(
+    group: &TestGroup,
+    verify: &str,
+    result: &Result<(), Error>,
+    options: &RunOptions,
+    log: &Log,
+) -> Result<(), Error> {
+    let label = group.label();
+    let path = options
+        .log_dir
+        .as_ref()
+        .unwrap_or(&options.snapshot_dir)
+        .join(label.replace('/', "_"))
+        .join("report.json");
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    Report::new(vec![GroupReport::new(group, result)]).save(&path)?;
+
+    let verify = group.substitute(verify);
+    log.println(format_args!(
+        "\x1b[1;33mVerifying '{}': {}\x1b[0m",
+        label, verify
+    ));
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", verify))
+        .arg("sh")
+        .arg(&path)
+        .envs(group.variables.iter().map(|(name, value)| (name, value)))
+        .status()
+        .map_err(|source| Error::Spawn {
+            command: verify.clone(),
+            source,
+        })?;
+    if !status.success() {
+        return Err(Error::Verify {
+            group: label,
+            status,
+        });
+    }
+    Ok(())
+}
+
+/// Compares the commands of a group, up to the check numbered `stop_at` if given.
+fn monitored_comparison(
+    group: &TestGroup,
+    options: &RunOptions,
+    log: &Log,
+    stop_at: Option<usize>,
+    analyzers: &mut Analyzers,
+    normalizations: &mut Normalizations,
+) -> Result<(), Error> {
+    let Some(monitor) = &options.monitor else {
+        return compare_commands(
+            group,
+            options,
+            log,
+            None,
+            stop_at,
+            analyzers,
+            normalizations,
+        );
+    };
+    let commands = match group_commands(group, options) {
+        Ok(commands) => commands,
+        Err(e) => {
+            log.eprintln(&e);
+            return Err(e);
+        }
+    };
+    let names: Vec<_> = commands
+        .iter()
+        .map(|(command, _)| command.clone())
+        .collect();
+    let index = monitor.start_group(&group.label(), &command_names(&names));
+    let result = compare_commands(
+        group,
+        options,
+        log,
+        Some((monitor, index)),
+        stop_at,
+        analyzers,
+        normalizations,
+    );
+    if let Err(Error::Mismatch { check, .. }) = &result {
+        monitor.record_mismatch(index, *check);
+    }
+    monitor.finish_group(index, Outcome::of(&result));
+    result
+}
+
+fn compare_commands(
+    group: &TestGroup,
+    options: &RunOptions,
+    log: &Log,
+    monitor: Option<(&Arc<Monitor>, usize)>,
+    stop_at: Option<usize>,
+    analyzers: &mut Analyzers,
+    normalizations: &mut Normalizations,
+) -> Result<(), Error> {
+    let in_process = |command: &TestCommand| {
+        options
+            .in_process
+            .iter()
+            .any(|closure| closure.name == command.name)
+    };
+    for (first, second) in group.identical_commands() {
+        // Closures have no command line
+        if in_process(first) || in_process(second) {
+            continue;
+        }
+        let message = format!(
+            "'{}' and '{}' run the same command line, so their comparison is meaningless",
+            first.name, second.name
+        );
+        if options.strict_config {
+            let error = Error::Config(message);
+            log.eprintln(&error);
+            return Err(error);
+        }
+        log.println(format_args!(
+            "\x1b[1;41m WARNING \x1b[0m \x1b[1;31m{}\x1b[0m",
+            message
+        ));
+    }
+
+    let commands = match group_commands(group, options) {
+        Ok(commands) => commands,
+        Err(e) => {
+            log.eprintln(&e);
+            return Err(e);
+        }
+    };
+    let replays: Vec<_> = commands.iter().map(|(_, replay)| replay.clone()).collect();
+    let commands: Vec<_> = commands.into_iter().map(|(command, _)| command).collect();
+    if let Some(decision_log) = &options.decision_log {
+        decision_log.record(&Decision::Start {
+            group: group.label(),
+            commands: commands
+                .iter()
+                .map(|command| command.name.clone())
+                .collect(),
+            stop_at,
+        });
+    }
+
+    if options.fingerprint {
+        let live: Vec<_> = commands
+            .iter()
+            .zip(&replays)
+            .filter(|(command, replay)| replay.is_none() && !in_process(command))
+            .map(|(command, _)| command.clone())
+            .collect();
+        check_fingerprints(&live, log);
+    }
+
+    let site_run = options
+        .unstable_sites
+        .as_ref()
+        .map(|sites| (sites.clone(), sites.start(group)));
+
+    let resume_point = options
+        .resume
+        .as_ref()
+        .and_then(|resume| resume.resume_point(&group.label()))
+        .cloned();
+    if let Some(point) = &resume_point {
+        log.println(format_args!(
+            "\x1b[1;33mResuming '{}' after check {}\x1b[0m",
+            group.label(),
+            point.checks
+        ));
+    }
+
+    let jitter_delays = match &options.jitter {
+        Some(jitter) => {
+            let delays = jitter.delays(&group.label(), commands.len());
+            let shown: Vec<_> = commands
+                .iter()
+                .zip(&delays)
+                .map(|(command, delay)| format!("'{}' +{}ms", command.name, delay.as_millis()))
+                .collect();
+            log.println(format_args!(
+                "\x1b[1;33mStart jitter (seed {}): {}\x1b[0m",
+                jitter.seed(),
+                shown.join(", ")
+            ));
+            delays
+        }
+        None => vec![Duration::ZERO; commands.len()],
+    };
+
+    log.println("Running test commands...");
+    let mut handles = Vec::new();
+    let mut receivers = Vec::new();
+    let mut snapshot_dirs = Vec::new();
+    let mut throttles = Throttles::default();
+    let stopped = StoppedProcesses(Arc::new(Stopped::default()));
+    let budget = options.max_buffer_mem.map(BufferBudget::new);
+    let peer_count = commands.len();
+    for (peer_index, test_command) in commands.iter().enumerate() {
+        let name = test_command.name.clone();
+        let command = test_command.command.clone();
+        let script = test_command.script();
+        let user = test_command.user.clone();
+        let isolate_network = test_command.isolate_network;
+        let sandbox = test_command.sandbox.clone();
+        let host = test_command.host.clone();
+        let watch = test_command.watch.clone();
+        let start_delay = test_command.start_delay.unwrap_or_default() + jitter_delays[peer_index];
+        let debug_log = options.debug_log.clone();
+        let check_deadline = options.check_deadline;
+        let rules = options.rules.clone();
+        let fail_on_deadline = options.fail_on_deadline;
+        let variables = group.variables.clone();
+        let wrapper = options.wrapper.clone();
+        let budget = budget.clone();
+        let compare_exit_status = group.compare_exit_status;
+        let compress = test_command.compress;
+        let resume_at = resume_point.as_ref().map(|point| point.checks);
+        let mut output = CommandOutput::new(log.clone(), &name, options.group_output);
+        let log = log.clone();
+        let monitor = monitor.map(|(monitor, group_index)| (monitor.clone(), group_index));
+        let site_run = site_run.clone();
+        let mut breakpoint_stop = options
+            .break_at
+            .clone()
+            .map(|breakpoint| BreakpointStop::new(breakpoint, resume_at.unwrap_or(0)));
+        let stopped = stopped.0.clone();
+
+        let command_snapshot_dir = options
+            .snapshot_dir
+            .join(group.label().replace('/', "_"))
+            .join(&name);
+        std::fs::create_dir_all(&command_snapshot_dir)?;
+        snapshot_dirs.push(command_snapshot_dir.clone());
+        let sink_path = command_snapshot_dir.join(sink::SINK_FILE);
+
+        // Replays never block, their throttle only keeps the indices of the others aligned.
+        // With a divergence hook the commands run in lockstep with the comparison, for the
+        // hook to find them at the diverging check
+        let throttle = options
+            .max_lead
+            .or(options.divergence_hook.as_ref().map(|_| 0))
+            .map(|max_lead| Arc::new(Throttle::new(max_lead)));
+        throttles.0.extend(throttle.clone());
+        // Throttled commands are stopped by their reader until the comparison takes their
+        // checks, so that they wait at the breakpoint or the divergence instead of running
+        // on until their pipe fills
+        let hold =
+            throttle.is_some() && (options.break_at.is_some() || options.divergence_hook.is_some());
+
+        if let Some(path) = &replays[peer_index] {
+            let (sender, receiver) = std::sync::mpsc::channel::<CommandData>();
+            let path = path.clone();
+            let handle = std::thread::Builder::new()
+                .name(name)
+                .spawn(move || replay_trace(&path, &sender, monitor, peer_index))
+                .unwrap();
+            handles.push(handle);
+            receivers.push(receiver);
+            continue;
+        }
+
+        let mut trace = match &options.record_dir {
+            Some(dir) => Some(TraceWriter::create(
+                &trace::trace_path(dir, &group.label(), &name),
+                &name,
+            )?),
+            None => None,
+        };
+
+        if let Some(closure) = options
+            .in_process
+            .iter()
+            .find(|closure| closure.name == name)
+        {
+            let (sender, receiver) = std::sync::mpsc::channel::<CommandData>();
+            let (closure, rules) = (closure.clone(), options.rules.clone());
+            let handle = std::thread::Builder::new()
+                .name(name)
+                .spawn(move || {
+                    run_in_process(
+                        &closure, &sender, trace, output, &rules, monitor, peer_index,
+                    )
+                })
+                .unwrap();
+            handles.push(handle);
+            receivers.push(receiver);
+            continue;
+        }
+
+        let mut raw_log = match &options.log_dir {
+            Some(dir) => Some(RawLog::create(&rawlog::log_path(
+                dir,
+                &group.label(),
+                &name,
+            ))?),
+            None => None,
+        };
+
+        sink::create_fifo(&sink_path)?;
+        let (sender, receiver) = std::sync::mpsc::channel::<CommandData>();
+
+        let handle = std::thread::Builder::new()
+            .name(name.clone())
+            .spawn(move || {
+                if !start_delay.is_zero() {
+                    std::thread::sleep(start_delay);
+                }
+                log.println(format_args!(
+                    "\x1b[1;33mExecuting test command '{}': {}\x1b[0m",
+                    name, command
+                ));
+
+                // Use Command to execute the test and capture stdout
+                let mut process_command = match wrapper::shell_command(
+                    &script,
+                    user.as_deref(),
+                    isolate_network,
+                    sandbox.as_ref(),
+                    wrapper.as_ref(),
+                ) {
+                    Ok(process_command) => process_command,
+                    Err(e) => {
+                        let _ = sender.send(CommandData::Failed(e));
+                        return;
+                    }
+                };
+                process_command
+                    .env(crate::PROTOCOL_ENV, crate::PROTOCOL_VERSION.to_string())
+                    .env(crate::ROLE_ENV, &name)
+                    .env(crate::PEER_INDEX_ENV, peer_index.to_string())
+                    .env(crate::PEER_COUNT_ENV, peer_count.to_string())
+                    .env(crate::SNAPSHOT_DIR_ENV, &command_snapshot_dir)
+                    .envs(compress.then_some((crate::COMPRESSION_ENV, crate::ZSTD_CAPABILITY)))
+                    .envs(variables)
+                    .envs(resume_at.map(|checks| (crate::RESUME_ENV, checks.to_string())))
+                    .stdout(std::process::Stdio::piped());
+                let watched = watch.iter().map(|path| Tail::new(path.into())).collect();
+                let spawned = match &host {
+                    Some(host) => RemoteProcess::spawn(host, &process_command).map(Process::Remote),
+                    // The sink is a FIFO on this machine
+                    None => process_command
+                        .env(crate::SINK_ENV, &sink_path)
+                        .spawn()
+                        .map(Process::Local),
+                };
+                match spawned {
+                    Ok(mut child) => {
+                        if let Some((monitor, group_index)) = &monitor
+                            && let Some(pid) = child.local_id()
+                        {
+                            monitor.set_pid(*group_index, peer_index, pid);
+                        }
+                        let _ = sender.send(CommandData::Started(child.local_id(), Instant::now()));
+                        let killer = match child.killer() {
+                            Ok(killer) => killer,
+                            Err(e) => {
+                                child.kill();
+                                let _ = sender.send(CommandData::Failed(Error::Io(e)));
+                                return;
+                            }
+                        };
+                        let deadline = check_deadline.map(|check_deadline| {
+                            let deadline = CheckDeadline::new(check_deadline);
+                            let sender = sender.clone();
+                            let (name, log) = (name.clone(), log.clone());
+                            deadline.watch(move |elapsed| {
+                                let message = format!(
+                                    "'{}' emitted no check for {:?}, over the deadline of {:?}",
+                                    name, elapsed, check_deadline
+                                );
+                                log.println(format_args!(
+                                    "\x1b[1;41m WARNING \x1b[0m \x1b[1;31m{}\x1b[0m",
+                                    message
+                                ));
+                                let _ = sender.send(CommandData::Breadcumb(format!(
+                                    "{} [harness] {}",
+                                    crate::BREADCUMB_PREFIX,
+                                    message
+                                )));
+                                if !fail_on_deadline {
+                                    return true;
+                                }
+                                let _ = sender.send(CommandData::Failed(Error::Deadline {
+                                    command: name.clone(),
+                                    elapsed,
+                                    deadline: check_deadline,
+                                }));
+                                killer();
+                                false
+                            });
+                            deadline
+                        });
+                        let stdout = child.take_output();
+                        let mut protocol = StreamProtocol::default();
+                        let mut checks = 0;
+                        let mut output_sample = Vec::new();
+
+                        let invalid_utf8 = Arc::new(AtomicUsize::new(0));
+                        let mut encoding_noted = false;
+
+                        let lines = sink::merged_lines(
+                            stdout,
+                            sink_path,
+                            watched,
+                            invalid_utf8.clone(),
+                        );
+                        #[cfg(feature = "zstd")]
+                        let lines = compression::decompressed(lines);
+                        let lines = protocol::unbatched(lines);
+                        let mut lines = lines;
+                        while let Some(line) = lines.next() {
+                            let mut held = None;
+                            if !encoding_noted && invalid_utf8.load(Ordering::Relaxed) > 0 {
+                                encoding_noted = true;
+                                let message = format!(
+                                    "'{}' wrote invalid UTF-8, replaced in its output and escaped as \\xNN in its messages",
+                                    name
+                                );
+                                log.println(format_args!(
+                                    "\x1b[1;41m WARNING \x1b[0m \x1b[1;31m{}\x1b[0m",
+                                    message
+                                ));
+                                let _ = sender.send(CommandData::Breadcumb(format!(
+                                    "{} [harness] {}",
+                                    crate::BREADCUMB_PREFIX,
+                                    message
+                                )));
+                            }
+                            let mut data = match line {
+                                Ok(line) => {
+                                    let negotiated = protocol.version().is_some();
+                                    let parsed = protocol.parse(line.trim());
+                                    if let Some(raw_log) = &mut raw_log
+                                        && let Err(e) = raw_log
+                                            .write(&line, matches!(parsed, Ok(Line::Check(_))))
+                                    {
+                                        let _ = sender.send(CommandData::Failed(e));
+                                    }
+                                    if !negotiated
+                                        && let Some(downgrade) = protocol.describe_downgrade(&name)
+                                    {
+                                        log.println(format_args!("\x1b[1;33m{}\x1b[0m", downgrade));
+                                    }
+
+                                    match parsed {
+                                        Ok(Line::Check(check)) => {
+                                            checks += 1;
+                                            if let Some(stop) = &mut breakpoint_stop
+                                                && let Some(pid) = child.local_id()
+                                            {
+                                                stop.check(&check, pid, &stopped);
+                                            }
+                                            if hold {
+                                                held = child
+                                                    .local_id()
+                                                    .filter(|pid| stopped.stop(*pid));
+                                            }
+                                            if let Some((sites, run)) = &site_run {
+                                                record_site(sites, run, &rules, &name, &check);
+                                            }
+                                            CommandData::Check(
+                                                check,
+                                                protocol.writer(),
+                                                Instant::now(),
+                                            )
+                                        }
+                                        Ok(Line::Duplicate(line)) => {
+                                            if let Some(debug_log) = &debug_log {
+                                                debug_log.write(format_args!(
+                                                    "duplicate of a message already received, dropped: {}",
+                                                    debuglog::quoted(&line)
+                                                ));
+                                            }
+                                            continue;
+                                        }
+                                        Ok(Line::Breadcumb(breadcumb)) => {
+                                            CommandData::Breadcumb(breadcumb)
+                                        }
+                                        Ok(Line::Hello) => {
+                                            if let Some(debug_log) = &debug_log {
+                                                debug_log.write(format_args!(
+                                                    "hello line, negotiated protocol version {}: {}",
+                                                    protocol.version().unwrap_or(1),
+                                                    debuglog::quoted(line.trim())
+                                                ));
+                                            }
+                                            continue;
+                                        }
+                                        Ok(Line::Output(line)) => {
+                                            if let Some(debug_log) = &debug_log {
+                                                debug_log.write(format_args!(
+                                                    "passthrough, no protocol prefix at the start of the line: {:?}",
+                                                    debuglog::quoted(&line)
+                                                ));
+                                            }
+                                            if output_sample.len() < OUTPUT_SAMPLE_LINES {
+                                                output_sample.push(line.clone());
+                                            }
+                                            if rules.current().verbosity.echo_output {
+                                                output.write(&line);
+                                            }
+                                            continue;
+                                        }
+                                        Err(message) => CommandData::Failed(Error::Protocol {
+                                            command: name.clone(),
+                                            message,
+                                        }),
+                                    }
+                                }
+                                Err(e) => CommandData::Failed(Error::Io(e)),
+                            };
+
+                            if let Some(trace) = &mut trace {
+                                let written = match &data {
+                                    CommandData::Check(check, _, _) => {
+                                        trace.write(&Entry::Check(check.clone()))
+                                    }
+                                    CommandData::Breadcumb(breadcumb) => {
+                                        trace.write(&Entry::Breadcumb(breadcumb.clone()))
+                                    }
+                                    CommandData::Started(..) | CommandData::Failed(_) => Ok(()),
+                                };
+                                if let Err(e) = written {
+                                    data = CommandData::Failed(e);
+                                }
+                            }
+
+                            if let Some((monitor, group_index)) = &monitor {
+                                match &data {
+                                    CommandData::Check(..) => {
+                                        monitor.record_check(*group_index, peer_index)
+                                    }
+                                    CommandData::Breadcumb(breadcumb) => monitor.record_breadcumb(
+                                        *group_index,
+                                        peer_index,
+                                        breadcumb,
+                                    ),
+                                    CommandData::Started(..) | CommandData::Failed(_) => {}
+                                }
+                            }
+
+                            if let Some(budget) = &budget {
+                                data = match data {
+                                    CommandData::Check(check, writer, time) => {
+                                        CommandData::Check(
+                                            budget.send(crate::CHECK_PREFIX, check),
+                                            writer,
+                                            time,
+                                        )
+                                    }
+                                    CommandData::Breadcumb(breadcumb) => CommandData::Breadcumb(
+                                        budget.send(crate::BREADCUMB_PREFIX, breadcumb),
+                                    ),
+                                    data => data,
+                                };
+                            }
+
+                            let is_check = matches!(data, CommandData::Check(..));
+                            // The comparison is over, stop the command
+                            if sender.send(data).is_err() {
+                                if let Some(pid) = held {
+                                    stopped.resume(pid);
+                                }
+                                // Its unstable sites are recorded up to its end
+                                if let Some((sites, run)) = &site_run {
+                                    for line in lines.by_ref().flatten() {
+                                        if let Ok(Line::Check(check)) = protocol.parse(line.trim())
+                                        {
+                                            record_site(sites, run, &rules, &name, &check);
+                                        }
+                                    }
+                                }
+                                child.kill();
+                                if let Some((monitor, group_index)) = &monitor {
+                                    monitor.finish_command(*group_index, peer_index, None);
+                                }
+                                return;
+                            }
+                            if is_check && let Some(throttle) = &throttle {
+                                let start = Instant::now();
+                                let blocked = throttle.sent();
+                                if let Some(pid) = held {
+                                    stopped.resume(pid);
+                                }
+                                if blocked && let Some(debug_log) = &debug_log
+                                {
+                                    debug_log.write(format_args!(
+                                        "blocked {:?} by --max-lead until the comparison caught up",
+                                        start.elapsed()
+                                    ));
+                                }
+                            }
+                            if is_check && let Some(deadline) = &deadline {
+                                deadline.checked();
+                            }
+                        }
+
+                        // The output can end before the process, e.g. if it closes stdout
+                        let status = match wait_timeout(&mut child, EARLY_EOF_TIMEOUT) {
+                            Some(status) => status,
+                            None => {
+                                let message = format!(
+                                    "The output of '{}' ended but the process still ran {:?} later, stopping it and comparing the checks received",
+                                    name, EARLY_EOF_TIMEOUT
+                                );
+                                log.println(format_args!(
+                                    "\x1b[1;41m WARNING \x1b[0m \x1b[1;31m{}\x1b[0m",
+                                    message
+                                ));
+                                let _ = sender.send(CommandData::Breadcumb(format!(
+                                    "{} [harness] {}",
+                                    crate::BREADCUMB_PREFIX,
+                                    message
+                                )));
+                                child.kill();
+                                if let Some((monitor, group_index)) = &monitor {
+                                    monitor.finish_command(*group_index, peer_index, None);
+                                }
+                                return;
+                            }
+                        };
+                        if let Some((monitor, group_index)) = &monitor {
+                            monitor.finish_command(*group_index, peer_index, Some(status));
+                        }
+                        if sandbox.is_some() && sandbox::is_violation(status) {
+                            let _ = sender.send(CommandData::Failed(Error::SandboxViolation {
+                                command: name,
+                                status,
+                            }));
+                            return;
+                        }
+                        if compare_exit_status {
+                            let check = exit_status_check(status);
+                            if let Some(trace) = &mut trace
+                                && let Err(e) = trace.write(&Entry::Check(check.clone()))
+                            {
+                                let _ = sender.send(CommandData::Failed(e));
+                            }
+                            let _ = sender.send(CommandData::Check(check, WriterId::default(), Instant::now()));
+                        } else if !status.success() {
+                            let _ = sender.send(CommandData::Failed(Error::CommandFailed {
+                                command: name,
+                                status,
+                            }));
+                            return;
+                        }
+                        if checks == 0 {
+                            // Nothing to compare would make the group trivially pass
+                            let _ = sender.send(CommandData::Failed(Error::NoInstrumentation {
+                                command: name,
+                                output: output_sample,
+                            }));
+                        }
+                    }
+                    Err(source) => {
+                        let _ = sender.send(CommandData::Failed(Error::Spawn {
+                            command: name,
+                            source,
+                        }));
+                    }
+                };
+            })
+            .unwrap();
+        handles.push(handle);
+        receivers.push(receiver);
+    }
+
+    let mut breadcumbs = vec![VecDeque::new(); handles.len()];
+    let mut coverage = Coverage::new(handles.len());
+    let mut channels = Channels::new(&options.routes, handles.len());
+    let mut ended = vec![false; handles.len()];
+    let mut checks_received = vec![0; handles.len()];
+    let mut compared = resume_point.as_ref().map_or(0, |point| point.checks);
+    let mut resumed = vec![compared == 0; handles.len()];
+    let mut skipped = vec![0; handles.len()];
+    let mut digests: Vec<u64> = commands
+        .iter()
+        .map(|command| {
+            resume_point
+                .as_ref()
+                .and_then(|point| point.digests.get(&command.name).copied())
+                .unwrap_or_default()
+        })
+        .collect();
+    let mut last_saved = Instant::now();
+    let mut pids = vec![None; handles.len()];
+    let mut started = vec![None; handles.len()];
+    let mut timings = vec![Vec::new(); handles.len()];
+    let mut rates = RateMonitor::new(handles.len());
+    let mut window = options.max_breadcumbs;
+    let mut agreements = Agreements::new(options.last_agreements);
+    let mut breakpoint = options.break_at.as_ref();
+
+    // Compare results from all threads
+
+    let mut still_running = true;
+    while still_running {
+        let mut last_checks = vec![None; handles.len()];
+        let mut round_times = vec![None; handles.len()];
+        let mut failures = Vec::new();
+        still_running = false;
+
+        for (i, receiver) in receivers.iter().enumerate() {
+            if let Some(debug_log) = &options.debug_log {
+                debug_log.write(format_args!(
+                    "check {}: polling the receiver of '{}'",
+                    compared + 1,
+                    commands[i].name
+                ));
+            }
+            while let Ok(data) = receiver.recv() {
+                still_running = true;
+
+                while breadcumbs[i].len() > window {
+                    let evicted: Option<String> = breadcumbs[i].pop_front();
+                    if let Some(debug_log) = &options.debug_log
+                        && let Some(evicted) = evicted
+                    {
+                        debug_log.write(format_args!(
+                            "evicted from the breadcrumb window of '{}' ({} messages): {}",
+                            commands[i].name,
+                            window,
+                            debuglog::quoted(&evicted)
+                        ));
+                    }
+                }
+
+                if let Some(budget) = &budget
+                    && replays[i].is_none()
+                    && let CommandData::Check(msg, _, _) | CommandData::Breadcumb(msg) = &data
+                {
+                    budget.received(msg);
+                }
+
+                match data {
+                    CommandData::Check(..)
+                        if !resumed[i]
+                            && let Some(point) = &resume_point
+                            && replays[i].is_some() =>
+                    {
+                        // Recorded traces are skipped to the checkpoint by the harness
+                        skipped[i] += 1;
+                        resumed[i] = skipped[i] == point.checks;
+                    }
+                    CommandData::Check(msg, _, _)
+                        if !resumed[i]
+                            && let Some(point) = &resume_point =>
+                    {
+                        if let Err(error) = resume::check_resumed(&commands[i].name, &msg, point) {
+                            failures.push(error);
+                            break;
+                        }
+                        resumed[i] = true;
+                    }
+                    CommandData::Check(mut msg, writer, time) => {
+                        if budget.as_ref().is_some_and(BufferBudget::is_digest_only) {
+                            msg = budget::digest_message(crate::CHECK_PREFIX, &msg);
+                        }
+                        // Replays emit their checks at once, they are no rate reference
+                        if replays[i].is_none() {
+                            round_times[i] = Some(time);
+                        }
+                        if let Some(throttle) = throttles.0.get(i) {
+                            throttle.compared();
+                        }
+                        coverage.record(i, &msg);
+                        if let Some(stats) = &options.stats {
+                            stats.record(channels.route(&msg), &msg);
+                        }
+                        checks_received[i] += 1;
+                        if options.bench
+                            && let Some(started) = started[i]
+                        {
+                            let payload = msg.strip_prefix(crate::CHECK_PREFIX).unwrap_or(&msg);
+                            timings[i].push((
+                                trace::check_name(payload).to_string(),
+                                time.duration_since(started),
+                            ));
+                        }
+                        if !options.bench
+                            && !budget::is_digest(&msg)
+                            && let Err(error) = check_expectation(group, &commands[i].name, &msg)
+                        {
+                            failures.push(error);
+                        }
+                        last_checks[i] = Some((msg.clone(), writer));
+                        breadcumbs[i].push_back(msg);
+                        break;
+                    }
+                    CommandData::Breadcumb(msg) => {
+                        breadcumbs[i].push_back(msg);
+                    }
+                    CommandData::Started(pid, time) => {
+                        pids[i] = pid;
+                        started[i] = Some(time);
+                    }
+                    CommandData::Failed(error) => {
+                        failures.push(error);
+                        // The command may never send anything else, e.g. if it hangs
+                        break;
+                    }
+                }
+            }
+        }
+
+        if !failures.is_empty() {
+            for failure in &failures {
+                log.eprintln(failure);
+            }
+            return Err(failures.remove(0));
+        }
+
+        for collapse in rates.record(&round_times) {
+            let mut warning = format!(
+                "\x1b[1;41m WARNING \x1b[0m \x1b[1;31m'{}' took {:?} to emit its last {} checks, against {:?} for '{}': its check rate collapsed\x1b[0m\n",
+                commands[collapse.command].name,
+                collapse.durations.0,
+                rate::WINDOW,
+                collapse.durations.1,
+                commands[collapse.fastest].name
+            );
+            let recent = &breadcumbs[collapse.command];
+            for breadcumb in recent
+                .iter()
+                .skip(recent.len().saturating_sub(RATE_BREADCUMBS))
+            {
+                warning.push_str(&format!("{}\n", breadcumb));
+            }
+            log.println(warning.trim_end());
+        }
+
+        for (i, check) in last_checks.into_iter().enumerate() {
+            match check {
+                Some((check, writer)) => channels.push(i, check, writer),
+                None => ended[i] = true,
+            }
+        }
+
+        if let Some(budget) = &budget {
+            degrade(
+                budget,
+                &mut window,
+                &mut breadcumbs,
+                &mut channels,
+                &mut agreements,
+                log,
+            );
+        }
+
+        let rules = options.rules.current();
+        while let Some((channel, aligned)) = channels.next_aligned(&ended) {
+            let channel = channel.as_str();
+            let ignored = aligned
+                .iter()
+                .flatten()
+                .all(|check| rules.is_ignored(check));
+            let normalized: Vec<_> = if ignored {
+                for check in aligned.iter().flatten() {
+                    rules.record_ignored(check, normalizations);
+                }
+                Vec::new()
+            } else {
+                aligned
+                    .iter()
+                    .map(|check| Some(rules.normalize(check.as_deref()?, normalizations)))
+                    .collect()
+            };
+            let mut emitted = normalized
+                .iter()
+                .zip(&snapshot_dirs)
+                .filter_map(|(check, dir)| Some((check.as_deref()?, dir.as_path())));
+            let diverged = !ignored
+                && emitted.next().is_some_and(|(reference, reference_dir)| {
+                    emitted.any(|(check, dir)| {
+                        !numeric::checks_match(
+                            [reference, check],
+                            [reference_dir, dir],
+                            options.tolerance,
+                            &rules.tolerances,
+                        )
+                    })
+                });
+            compared += 1;
+            if let Some(decision_log) = &options.decision_log {
+                let mut normalized: Vec<String> = Vec::new();
+                for rule in aligned
+                    .iter()
+                    .flatten()
+                    .filter_map(|check| rules.applied_rule(check, ignored))
+                {
+                    if !normalized.contains(&rule) {
+                        normalized.push(rule);
+                    }
+                }
+                decision_log.record(&Decision::Compare {
+                    group: group.label(),
+                    check: compared,
+                    channel: channel.to_string(),
+                    digests: aligned
+                        .iter()
+                        .map(|check| check.as_deref().map(decisions::check_digest))
+                        .collect(),
+                    normalized,
+                    ignored,
+                    diverged,
+                    checks: if diverged {
+                        aligned.clone()
+                    } else {
+                        Vec::new()
+                    },
+                });
+            }
+            if !diverged && let Some((monitor, group_index)) = &monitor {
+                monitor.record_matched(*group_index, compared as u64);
+            }
+            if let Some(resume) = &options.resume
+                && !budget.as_ref().is_some_and(BufferBudget::is_digest_only)
+            {
+                for (digest, check) in digests.iter_mut().zip(&aligned) {
+                    if let Some(check) = check {
+                        let payload = check.strip_prefix(crate::CHECK_PREFIX).unwrap_or(check);
+                        *digest = crate::rolling_digest(*digest, payload);
+                    }
+                }
+                if !diverged
+                    && aligned.iter().all(Option::is_some)
+                    && last_saved.elapsed() >= resume::SAVE_PERIOD
+                {
+                    let point = ResumePoint {
+                        checks: compared,
+                        digests: commands
+                            .iter()
+                            .map(|command| command.name.clone())
+                            .zip(digests.iter().copied())
+                            .collect(),
+                    };
+                    if let Err(e) = resume.save(&group.label(), point) {
+                        log.eprintln(format_args!("Cannot save checkpoints: {}", e));
+                    }
+                    last_saved = Instant::now();
+                }
+            }
+            if options.bench {
+                continue;
+            }
+            if diverged && let Some(stats) = &options.stats {
+                stats.record_mismatch(channel, &aligned);
+            }
+            if diverged && let Some(sites) = &options.unstable_sites {
+                sites.record_divergence(group, &aligned);
+            }
+            if !diverged
+                && !ignored
+                && let Some(agreed) = aligned.iter().flatten().next()
+            {
+                agreements.record(channel, compared, agreed);
+            }
+            if !ignored && !analyzers.is_empty() {
+                let (label, names) = (group.label(), command_names(&commands));
+                let checks = aligned.iter().map(difftool::payload).collect();
+                let event = if diverged {
+                    analyzer::Event::Mismatch {
+                        group: &label,
+                        check: compared,
+                        channel,
+                        commands: &names,
+                        checks,
+                    }
+                } else {
+                    analyzer::Event::Check {
+                        group: &label,
+                        check: compared,
+                        channel,
+                        commands: &names,
+                        checks,
+                    }
+                };
+                analyzers.send(&event, log);
+                if let Some(failure) = analyzers.failure(log) {
+                    return Err(failure);
+                }
+            }
+            if breakpoint.is_some_and(|breakpoint| breakpoint.is_hit(compared, &aligned)) {
+                breakpoint::pause(&command_names(&commands), &pids, compared, &stopped.0, log);
+                breakpoint = None;
+            }
+            if !diverged {
+                if stop_at.is_some_and(|stop_at| compared >= stop_at) {
+                    log.println(format_args!("Stopped after check {}", compared));
+                    return Ok(());
+                }
+                continue;
+            }
+
+            let mut report = String::new();
+            let _ = write_mismatch_report(
+                &mut report,
+                &commands,
+                channel,
+                &breadcumbs,
+                &snapshot_dirs,
+                &aligned,
+                options,
+            );
+            let _ = write_agreements(
+                &mut report,
+                &agreements,
+                channel,
+                &snapshot_dirs[0],
+                options,
+            );
+            if let Some(dir) = &options.log_dir {
+                for ((command, check), checks) in
+                    commands.iter().zip(&aligned).zip(&checks_received)
+                {
+                    if let Some(check) = check {
+                        let path = rawlog::log_path(dir, &group.label(), &command.name);
+                        let _ = rawlog::write_log_context(
+                            &mut report,
+                            &command.name,
+                            &path,
+                            check,
+                            *checks,
+                            options.max_payload_bytes,
+                        );
+                    }
+                }
+            }
+            log.println(report);
+            // Diff tools may be interactive, only launch them when someone watches the output
+            if !log.is_quiet() {
+                difftool::run_diff_tools(options, &snapshot_dirs, &aligned, log);
+            }
+            if let Some(hook) = &options.divergence_hook
+                && let Err(e) =
+                    breakpoint::run_hook(hook, &command_names(&commands), &pids, &stopped.0, log)
+            {
+                log.eprintln(format_args!("Divergence hook failed: {}", e));
+            }
+            // The commands run to their end for their unstable sites to be recorded
+            if options.unstable_sites.is_some() {
+                drop(receivers);
+                for handle in handles {
+                    let _ = handle.join();
+                }
+            }
+            return Err(Error::Mismatch {
+                group: group.label(),
+                check: compared,
+                checks: commands
+                    .iter()
+                    .map(|command| command.name.clone())
+                    .zip(aligned)
+                    .collect(),
+            });
+        }
+    }
+
+    // Wait for all threads to finish
+    for handle in handles {
+        if let Err(e) = handle.join() {
+            log.eprintln(format_args!("Error joining thread: {:?}", e));
+        }
+    }
+
+    compare_output_dirs(group, &commands, log)?;
+
+    // The checks skipped when resuming are not seen again
+    for (check, expected) in group.expectations.iter().filter(|_| resume_point.is_none()) {
+        if let Some(command) = (0..commands.len()).find(|i| !coverage.emitted(*i, check)) {
+            let error = Error::Expectation {
+                command: commands[command].name.clone(),
+                check: check.clone(),
+                expected: expected.clone(),
+                actual: None,
+            };
+            log.eprintln(&error);
+            return Err(error);
+        }
+    }
+
+    if options.bench {
+        let mut report = String::new();
+        let exceeded = bench::write_timings(
+            &mut report,
+            &command_names(&commands),
+            &timings,
+            &options.thresholds,
+        )?;
+        log.println(report.trim_end());
+        if !exceeded.is_empty() {
+            let error = Error::Timing {
+                group: group.label(),
+                checkpoints: exceeded,
+            };
+            log.eprintln(&error);
+            return Err(error);
+        }
+    }
+
+    // Checks compare in lockstep, so a command may run code paths the others never reach
+    // once they are done, or emit checks the others skip under a tolerance
+    let mut report = String::new();
+    let _ = coverage::write_coverage(&mut report, &command_names(&commands), &coverage);
+    if !report.is_empty() {
+        log.println(report.trim_end());
+    }
+
+    log.println("All tests completed successfully");
+    Ok(())
+}
+
+/// Shrinks the breadcrumb windows, then retains checks as digests only, while the
+/// messages held for a group exceed its budget.
+fn degrade(
+    budget: &BufferBudget,
+    window: &mut usize,
+    breadcumbs: &mut [VecDeque<String>],
+    channels: &mut Channels,
+    agreements: &mut Agreements,
+    log: &Log,
+) {
+    let retained =
+        |breadcumbs: &[VecDeque<String>], channels: &Channels, agreements: &Agreements| {
+            breadcumbs.iter().flatten().map(String::len).sum::<usize>()
+                + channels.bytes()
+                + agreements.bytes()
+        };
+    let initial_window = *window;
+    while *window > 1 && budget.is_exceeded(retained(breadcumbs, channels, agreements)) {
+        *window /= 2;
+        for breadcumbs in breadcumbs.iter_mut() {
+            while breadcumbs.len() > *window {
+                breadcumbs.pop_front();
+            }
+        }
+        agreements.shrink(*window);
+    }
+    if *window < initial_window {
+        log.println(format_args!(
+            "\x1b[1;41m WARNING \x1b[0m \x1b[1;31mBuffered messages exceed the memory limit, breadcrumb windows shrunk to {}\x1b[0m",
+            window
+        ));
+    }
+
+    if !budget.is_digest_only() && budget.is_exceeded(retained(breadcumbs, channels, agreements)) {
+        budget.set_digest_only();
+        channels.digest_all();
+        log.println(
+            "\x1b[1;41m WARNING \x1b[0m \x1b[1;31mBuffered messages exceed the memory limit, checks are now retained as digests: reports lose their text, tolerances and expectations no longer apply\x1b[0m",
+        );
+    }
+}
+
+/// Commands to compare for a group: the recorded traces of the reference directory, if
+/// any, followed by the live commands.
+fn group_commands(
+    group: &TestGroup,
+    options: &RunOptions,
+) -> Result<Vec<(TestCommand, Option<PathBuf>)>, Error> {
+    let live = group.resolve_commands();
+    let mut commands = Vec::new();
+    if let Some(dir) = &options.reference_dir {
+        for command in &live {
+            let path = trace::trace_path(dir, &group.label(), &command.name);
+            if path.exists() {
+                let name = format!("baseline {}", command.name);
+                commands.push((TestCommand::new(&name, ""), Some(path)));
+            }
+        }
+        if commands.is_empty() {
+            return Err(Error::Config(format!(
+                "no trace of group '{}' recorded in {}",
+                group.label(),
+                dir.display()
+            )));
+        }
+    }
+    commands.extend(live.into_iter().map(|command| (command, None)));
+    Ok(commands)
+}
+
+/// Sends the entries of a recorded trace as if a command emitted them.
+fn replay_trace(
+    path: &Path,
+    sender: &Sender<CommandData>,
+    monitor: Option<(Arc<Monitor>, usize)>,
+    peer_index: usize,
+) {
+    let trace = match Trace::load(path) {
+        Ok(trace) => trace,
+        Err(e) => {
+            let _ = sender.send(CommandData::Failed(e));
+            return;
+        }
+    };
+    for entry in trace.entries {
+        if let Some((monitor, group_index)) = &monitor {
+            match &entry {
+                Entry::Check(_) => monitor.record_check(*group_index, peer_index),
+                Entry::Breadcumb(breadcumb) => {
+                    monitor.record_breadcumb(*group_index, peer_index, breadcumb)
+                }
+            }
+        }
+        let data = match entry {
+            Entry::Check(check) => CommandData::Check(check, WriterId::default(), Instant::now()),
+            Entry::Breadcumb(breadcumb) => CommandData::Breadcumb(breadcumb),
+        };
+        if sender.send(data).is_err() {
+            return;
+        }
+    }
+    if let Some((monitor, group_index)) = &monitor {
+        monitor.finish_command(*group_index, peer_index, None);
+    }
+}
+
+/// Records a check for the unstable sites, unless it is ignored.
+fn record_site(
+    sites: &UnstableSites,
+    run: &SiteRun,
+    rules: &SharedRules,
+    command: &str,
+    check: &str,
+) {
+    if !rules.current().is_ignored(check) {
+        sites.record(run, command, check);
+    }
+}
+
+/// Runs a closure in place of a command, sending its messages as if a command printed
+/// them.
+fn run_in_process(
+    closure: &InProcessCommand,
+    sender: &Sender<CommandData>,
+    mut trace: Option<TraceWriter>,
+    mut output: CommandOutput,
+    rules: &SharedRules,
+    monitor: Option<(Arc<Monitor>, usize)>,
+    peer_index: usize,
+) {
+    let (lines, received) = std::sync::mpsc::channel();
+    let runner = {
+        let closure = closure.clone();
+        std::thread::Builder::new()
+            .name(closure.name.clone())
+            .spawn(move || closure.run(lines))
+            .unwrap()
+    };
+    let _ = sender.send(CommandData::Started(None, Instant::now()));
+
+    let mut protocol = StreamProtocol::default();
+    let mut checks = 0;
+    let mut output_sample = Vec::new();
+    for line in received {
+        let data = match protocol.parse(line.trim()) {
+            Ok(Line::Check(check)) => {
+                checks += 1;
+                CommandData::Check(check, protocol.writer(), Instant::now())
+            }
+            Ok(Line::Breadcumb(breadcumb)) => CommandData::Breadcumb(breadcumb),
+            Ok(Line::Output(line)) => {
+                if output_sample.len() < OUTPUT_SAMPLE_LINES {
+                    output_sample.push(line.clone());
+                }
+                if rules.current().verbosity.echo_output {
+                    output.write(&line);
+                }
+                continue;
+            }
+            Ok(Line::Hello | Line::Duplicate(_)) => continue,
+            Err(message) => CommandData::Failed(Error::Protocol {
+                command: closure.name.clone(),
+                message,
+            }),
+        };
+        if let Some(trace) = &mut trace {
+            let written = match &data {
+                CommandData::Check(check, ..) => trace.write(&Entry::Check(check.clone())),
+                CommandData::Breadcumb(breadcumb) => {
+                    trace.write(&Entry::Breadcumb(breadcumb.clone()))
+                }
+                CommandData::Started(..) | CommandData::Failed(_) => Ok(()),
+            };
+            if let Err(e) = written {
+                let _ = sender.send(CommandData::Failed(e));
+            }
+        }
+        if let Some((monitor, group_index)) = &monitor {
+            match &data {
+                CommandData::Check(..) => monitor.record_check(*group_index, peer_index),
+                CommandData::Breadcumb(breadcumb) => {
+                    monitor.record_breadcumb(*group_index, peer_index, breadcumb)
+                }
+                CommandData::Started(..) | CommandData::Failed(_) => {}
+            }
+        }
+        // The comparison is over, the closure sees it stopped
+        if sender.send(data).is_err() {
+            return;
+        }
+    }
+
+    if let Err(panic) = runner.join() {
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        let _ = sender.send(CommandData::Failed(Error::Panicked {
+            command: closure.name.clone(),
+            message,
+        }));
+        return;
+    }
+    if let Some((monitor, group_index)) = &monitor {
+        monitor.finish_command(*group_index, peer_index, None);
+    }
+    if checks == 0 {
+        let _ = sender.send(CommandData::Failed(Error::NoInstrumentation {
+            command: closure.name.clone(),
+            output: output_sample,
+        }));
+    }
+}
+
+/// Compares the files left in the output directories of the commands that declare one.
+fn compare_output_dirs(
+    group: &TestGroup,
+    commands: &[TestCommand],
+    log: &Log,
+) -> Result<(), Error> {
+    let (names, dirs): (Vec<_>, Vec<_>) = commands
+        .iter()
+        .filter_map(|command| Some((command.name.as_str(), command.output_dir.as_deref()?)))
+        .unzip();
+    if dirs.len() < 2 {
+        return Ok(());
+    }
+    let trees = dirs
+        .iter()
+        .map(|dir| filetree::snapshot_tree(Path::new(dir)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut report = String::new();
+    let files = filetree::write_tree_diffs(&mut report, &names, &dirs, &trees)?;
+    if files == 0 {
+        return Ok(());
+    }
+    log.println(report.trim_end());
+    let error = Error::FileTree {
+        group: group.label(),
+        files,
+    };
+    log.eprintln(&error);
+    Err(error)
+}
+
+/// Waits for `child` to exit for up to `timeout`, returning its exit status if it did.
+fn wait_timeout(child: &mut Process, timeout: Duration) -> Option<ExitStatus> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => return Some(status),
+            Ok(None) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(10)),
+            // An error means the status cannot be waited for either
+            _ => return None,
+        }
+    }
+}
+
+/// Synthetic last check of a command comparing its exit status.
+fn exit_status_check(status: ExitStatus) -> String {
+    match (status.code(), status.signal()) {
+        (Some(code), _) => format!(
+            "{} {} code={}",
+            crate::CHECK_PREFIX,
+            crate::EXIT_CHECK,
+            code
+        ),
+        (None, Some(signal)) => format!(
+            "{} {} signal={}",
+            crate::CHECK_PREFIX,
+            crate::EXIT_CHECK,
+            signal
+        ),
+        (None, None) => format!("{} {} unknown", crate::CHECK_PREFIX, crate::EXIT_CHECK),
+    }
+}
+
+/// Fails if `check` is pinned by an `expect` line of the group to another value.
+fn check_expectation(group: &TestGroup, command: &str, check: &str) -> Result<(), Error> {
+    let payload = check.strip_prefix(crate::CHECK_PREFIX).unwrap_or(check);
+    let name = trace::check_name(payload);
+    let Some(expected) = group.expected_value(name) else {
+        return Ok(());
+    };
+    let value = payload.trim_start()[name.len()..].trim();
+    let value = value.strip_prefix('=').unwrap_or(value).trim();
+    if value == expected {
+        return Ok(());
+    }
+    Err(Error::Expectation {
+        command: command.to_string(),
+        check: name.to_string(),
+        expected: expected.to_string(),
+        actual: Some(value.to_string()),
+    })
+}
+
+fn command_names(commands: &[TestCommand]) -> Vec<&str> {
+    commands
+        .iter()
+        .map(|command| command.name.as_str())
+        .collect()
+}
+
+fn check_fingerprints(commands: &[TestCommand], log: &Log) {
+    let names = command_names(commands);
+    let fingerprints: Vec<_> = commands
+        .iter()
+        .map(|command| fingerprint::Fingerprint::collect(&command.command))
+        .collect();
+
+    let warnings = fingerprint::compare_fingerprints(&names, &fingerprints);
+    if warnings.is_empty() {
+        return;
+    }
+
+    let mut report = String::new();
+    let _ = fingerprint::write_fingerprints(&mut report, &names, &fingerprints);
+    for warning in warnings {
+        report.push_str(&format!(
+            "\x1b[1;41m WARNING \x1b[0m \x1b[1;31m{}\x1b[0m\n",
+            warning
+        ));
+    }
+    log.println(report);
+}
+
+/// Writes how many checks each extraction and ignore rule changed during the run of
+/// `group`, with the first ones.
+fn write_normalizations(
+    out: &mut impl Write,
+    group: &TestGroup,
+    normalizations: &Normalizations,
+    options: &RunOptions,
+) -> std::fmt::Result {
+    writeln!(
+        out,
+        "\x1b[1;34mChecks normalized in '{}':\x1b[0m",
+        group.label()
+    )?;
+    for rule in normalizations.rules() {
+        let changed = if rule.section == "ignore" {
+            "ignored"
+        } else {
+            "changed"
+        };
+        writeln!(
+            out,
+            "  {} '{}': \x1b[1;33m{} checks {}\x1b[0m",
+            rule.section, rule.pattern, rule.checks, changed
+        )?;
+        for (check, normalized) in &rule.samples {
+            let shown = |text| truncate::within_budget(text, options.max_payload_bytes, || None);
+            writeln!(out, "    {}", shown(check))?;
+            if let Some(normalized) = normalized {
+                writeln!(out, "    \x1b[1;32m=> {}\x1b[0m", shown(normalized))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes the last checks of `channel` all the commands agreed on, before the mismatch.
+fn write_agreements(
+    out: &mut impl Write,
+    agreements: &Agreements,
+    channel: &str,
+    snapshot_dir: &Path,
+    options: &RunOptions,
+) -> std::fmt::Result {
+    let mut agreed = agreements.last(channel).peekable();
+    if agreed.peek().is_some() {
+        writeln!(out, "\x1b[1;34mLast agreements:\x1b[0m")?;
+    }
+    for (check, agreed) in agreed {
+        let shown = truncate::within_budget(agreed, options.max_payload_bytes, || {
+            truncate::save_full_text(snapshot_dir, agreed)
+        });
+        writeln!(out, "\x1b[1;32mcheck {}\x1b[0m {}", check, shown)?;
+    }
+    Ok(())
+}
+
+fn write_mismatch_report(
+    out: &mut impl Write,
+    commands: &[TestCommand],
+    channel: &str,
+    breadcumbs: &[VecDeque<String>],
+    snapshot_dirs: &[PathBuf],
+    last_checks: &[Option<String>],
+    options: &RunOptions,
+) -> std::fmt::Result {
+    let rules = options.rules.current();
+    if channel == channels::DEFAULT_CHANNEL {
+        writeln!(out, "\x1b[1;31mMismatch detected in runtime checks!\x1b[0m")?;
+    } else {
+        writeln!(
+            out,
+            "\x1b[1;31mMismatch detected in runtime checks of channel '{}'!\x1b[0m",
+            channel
+        )?;
+    }
+    for (i, thread_breadcumbs) in breadcumbs.iter().enumerate() {
+        writeln!(
+            out,
+            "\x1b[1;34mExecutable \x1b[1;37m{}\x1b[1;34m breadcumbs:\x1b[0m",
+            commands[i].name
+        )?;
+        let shown = rules
+            .verbosity
+            .breadcumbs
+            .unwrap_or(thread_breadcumbs.len());
+        for breadcumb in thread_breadcumbs
+            .iter()
+            .skip(thread_breadcumbs.len().saturating_sub(shown))
+        {
+            let shown = truncate::within_budget(breadcumb, options.max_payload_bytes, || {
+                truncate::save_full_text(&snapshot_dirs[i], breadcumb)
+            });
+            writeln!(out, "{}", shown)?;
+        }
+    }
+    let names = command_names(commands);
+    snapshot::write_snapshot_diffs(out, &names, snapshot_dirs, last_checks)?;
+    numeric::write_numeric_diffs(
+        out,
+        &names,
+        snapshot_dirs,
+        last_checks,
+        options.tolerance,
+        &rules.tolerances,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::{inprocess::Emitter, rules::Rules};
+
+    fn options(dir: &Path) -> RunOptions {
+        RunOptions {
+            max_breadcumbs: 8,
+            last_agreements: 0,
+            snapshot_dir: dir.join("snapshots"),
+            fingerprint: false,
+            monitor: None,
+            record_dir: None,
+            diff_tool: None,
+            diff_tools: Vec::new(),
+            analyzers: Vec::new(),
+            tolerance: 0.0,
+            rules: Arc::new(SharedRules::new(Rules::default())),
+            routes: Vec::new(),
+            reference_dir: None,
+            in_process: Vec::new(),
+            log_dir: None,
+            debug_rerun: false,
+            break_at: None,
+            divergence_hook: None,
+            wrapper: None,
+            confirm: false,
+            jitter: None,
+            group_output: false,
+            strict_config: false,
+            max_buffer_mem: None,
+            bench: false,
+            thresholds: Vec::new(),
+            check_deadline: None,
+            fail_on_deadline: false,
+            max_payload_bytes: 0,
+            debug_log: None,
+            decision_log: None,
+            stats: None,
+            unstable_sites: None,
+            resume: None,
+            max_lead: None,
+        }
+    }
+
+    /// Commands diverging at their third check in every run, whose first command tells
+    /// the runs apart.
+    fn diverging(options: &mut RunOptions) -> TestGroup {
+        let runs = Arc::new(AtomicUsize::new(0));
+        options.in_process = vec![
+            InProcessCommand::new("a", move |emitter: &Emitter| {
+                let run = runs.fetch_add(1, Ordering::Relaxed);
+                emitter.check("x 0");
+                emitter.check("x 1");
+                emitter.check(format_args!("x run {}", run));
+            }),
+            InProcessCommand::new("b", |emitter: &Emitter| {
+                emitter.check("x 0");
+                emitter.check("x 1");
+                emitter.check("y");
+            }),
+        ];
+        TestGroup::new(
+            "diverging",
+            vec![TestCommand::new("a", ""), TestCommand::new("b", "")],
+        )
+    }
+
+    /// Runs the diverging commands with the rerun `configure` enables, checking that only
+    /// the first run is recorded.
+    fn assert_first_run_recorded(name: &str, configure: impl FnOnce(&mut RunOptions)) {
+        let dir =
+            std::env::temp_dir().join(format!("runtime-diff-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut options = options(&dir);
+        let group = diverging(&mut options);
+        let stats = Arc::new(Stats::default());
+        configure(&mut options);
+        options.stats = Some(stats.clone());
+        options.decision_log = Some(Arc::new(
+            DecisionLog::create(&dir.join("decisions")).unwrap(),
+        ));
+        options.record_dir = Some(dir.join("traces"));
+
+        let result = run_group(&group, &options, &Log::quiet());
+        assert!(
+            matches!(result, Err(Error::Mismatch { check: 3, .. })),
+            "{:?}",
+            result
+        );
+        let checks: u64 = stats
+            .by_name()
+            .iter()
+            .map(|(_, _, counts)| counts.checks)
+            .sum();
+        assert_eq!(checks, 6);
+        let starts = decisions::load(&dir.join("decisions"))
+            .unwrap()
+            .into_iter()
+            .filter(|decision| matches!(decision, Decision::Start { .. }))
+            .count();
+        assert_eq!(starts, 1);
+        let trace =
+            Trace::load(&trace::trace_path(&dir.join("traces"), &group.label(), "a")).unwrap();
+        assert!(
+            trace
+                .entries
+                .iter()
+                .any(|entry| entry.text().ends_with("x run 0")),
+            "{:?}",
+            trace.entries
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn confirming_keeps_the_records_of_the_first_run() {
+        assert_first_run_recorded("confirm", |options| options.confirm = true);
+    }
+
+    #[test]
+    fn debug_reruns_keep_the_records_of_the_first_run() {
+        assert_first_run_recorded("debug-rerun", |options| options.debug_rerun = true);
+    }
+}
@@ -0,0 +1,59 @@
+use std::{collections::BTreeMap, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use super::{Error, group::Outcome, report::Report};
+
+/// Number of runs of every group kept in the history.
+pub const HISTORY_LENGTH: usize = 20;
+
+/// Outcomes of the latest runs of every group across harness invocations, used to
+/// quarantine the flaky ones.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct History {
+    pub groups: BTreeMap<String, Vec<Outcome>>,
+}
+
+impl History {
+    /// Loads the history at `path`, empty if there is none yet.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match std::fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json)
+                .map_err(|e| Error::Config(format!("invalid history: {}", e))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| Error::Config(format!("cannot serialize history: {}", e)))?;
+        std::fs::write(path, json + "\n")?;
+        Ok(())
+    }
+
+    /// Appends the outcome of every group run in `report`.
+    pub fn record(&mut self, report: &Report) {
+        for group in &report.groups {
+            if group.outcome == Outcome::Skipped {
+                continue;
+            }
+            let runs = self.groups.entry(group.label.clone()).or_default();
+            runs.push(group.outcome);
+            let excess = runs.len().saturating_sub(HISTORY_LENGTH);
+            runs.drain(..excess);
+        }
+    }
+
+    /// Number of recorded runs in which the group with the given label failed, if it also
+    /// passed in others; failures of a group that never passes are not flakiness.
+    pub fn flaky_runs(&self, label: &str) -> usize {
+        let Some(runs) = self.groups.get(label) else {
+            return 0;
+        };
+        if !runs.iter().any(|outcome| outcome.passed()) {
+            return 0;
+        }
+        runs.iter().filter(|outcome| !outcome.passed()).count()
+    }
+}
@@ -0,0 +1,127 @@
+use std::{fmt::Write, path::Path};
+
+use serde::Deserialize;
+
+use super::Error;
+
+/// File the starter test file is written to when no other is given.
+pub const DEFAULT_TEST_FILE: &str = "runtime-diff.run";
+
+#[derive(Deserialize)]
+struct Metadata {
+    packages: Vec<Package>,
+    target_directory: String,
+}
+
+#[derive(Deserialize)]
+struct Package {
+    name: String,
+    targets: Vec<Target>,
+}
+
+#[derive(Deserialize)]
+struct Target {
+    name: String,
+    kind: Vec<String>,
+}
+
+/// Binary target of a workspace package.
+#[derive(Debug, Clone)]
+pub struct Binary {
+    pub name: String,
+    pub package: String,
+}
+
+/// Binaries of the cargo workspace containing `dir`, and the directory they are built in
+/// relative to it when it is inside the workspace.
+pub fn workspace_binaries(dir: &Path) -> Result<(Vec<Binary>, String), Error> {
+    let output = std::process::Command::new("cargo")
+        .args(["metadata", "--format-version", "1", "--no-deps"])
+        .current_dir(dir)
+        .stderr(std::process::Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(Error::CommandFailed {
+            command: "cargo metadata".to_string(),
+            status: output.status,
+        });
+    }
+    let metadata: Metadata = serde_json::from_slice(&output.stdout)
+        .map_err(|e| Error::Config(format!("invalid cargo metadata: {}", e)))?;
+
+    let binaries = metadata
+        .packages
+        .iter()
+        .flat_map(|package| {
+            package
+                .targets
+                .iter()
+                .filter(|target| target.kind.iter().any(|kind| kind == "bin"))
+                .map(|target| Binary {
+                    name: target.name.clone(),
+                    package: package.name.clone(),
+                })
+        })
+        .collect();
+    let target_dir = Path::new(&metadata.target_directory);
+    let target_dir = std::fs::canonicalize(dir)
+        .ok()
+        .and_then(|dir| target_dir.strip_prefix(dir).ok())
+        .map(|relative| format!("./{}", relative.display()))
+        .unwrap_or_else(|| metadata.target_directory.clone());
+    Ok((binaries, target_dir))
+}
+
+/// Starter test file building the `selected` binaries in release mode and diffing their
+/// runs.
+pub fn starter_test_file(
+    binaries: &[Binary],
+    target_dir: &str,
+    selected: &[String],
+) -> Result<String, Error> {
+    if selected.len() < 2 {
+        return Err(Error::Config(
+            "give at least two binaries to compare with --bin".to_string(),
+        ));
+    }
+    let mut chosen = Vec::new();
+    for name in selected {
+        let binary = binaries
+            .iter()
+            .find(|binary| &binary.name == name)
+            .ok_or_else(|| {
+                let available: Vec<_> =
+                    binaries.iter().map(|binary| binary.name.as_str()).collect();
+                Error::Config(format!(
+                    "no binary '{}' in the workspace, it has: {}",
+                    name,
+                    available.join(", ")
+                ))
+            })?;
+        chosen.push(binary);
+    }
+
+    let mut out = String::new();
+    writeln!(out, "build:")?;
+    for binary in &chosen {
+        writeln!(
+            out,
+            "    cargo build --release -p {} --bin {}",
+            binary.package, binary.name
+        )?;
+    }
+    writeln!(out)?;
+    writeln!(
+        out,
+        "# Instrument the binaries with runtime_check!, then add the arguments of the runs"
+    )?;
+    writeln!(out, "test:")?;
+    for binary in &chosen {
+        writeln!(
+            out,
+            "    {}: {}/release/{}",
+            binary.name, target_dir, binary.name
+        )?;
+    }
+    Ok(out)
+}
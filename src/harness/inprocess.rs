@@ -0,0 +1,72 @@
+use std::{
+    cell::Cell,
+    fmt::Display,
+    sync::{Arc, mpsc::Sender},
+};
+
+/// Command run as a closure in the harness process instead of a subprocess, emitting its
+/// protocol messages through a channel, so that comparisons run deterministically and
+/// without spawning anything.
+///
+/// The closure runs once per run of its group, e.g. again when a divergence is confirmed.
+#[derive(Clone)]
+pub struct InProcessCommand {
+    pub name: String,
+    body: Arc<dyn Fn(&Emitter) + Send + Sync>,
+}
+
+impl InProcessCommand {
+    pub fn new(name: &str, body: impl Fn(&Emitter) + Send + Sync + 'static) -> Self {
+        Self {
+            name: name.to_string(),
+            body: Arc::new(body),
+        }
+    }
+
+    /// Runs the closure, sending the lines it emits to `lines`.
+    pub fn run(&self, lines: Sender<String>) {
+        (self.body)(&Emitter {
+            lines,
+            stopped: Cell::new(false),
+        });
+    }
+}
+
+/// Output of an in-process command, taking the place of its stdout.
+pub struct Emitter {
+    lines: Sender<String>,
+    stopped: Cell<bool>,
+}
+
+impl Emitter {
+    pub fn check(&self, check: impl Display) {
+        self.line(format_args!("{} {}", crate::CHECK_PREFIX, check));
+    }
+
+    pub fn breadcumb(&self, breadcumb: impl Display) {
+        self.line(format_args!("{} {}", crate::BREADCUMB_PREFIX, breadcumb));
+    }
+
+    /// Stops the comparison of the following checks until [`Emitter::enable_checks`].
+    pub fn disable_checks(&self) {
+        self.line(crate::DISABLE_PREFIX);
+    }
+
+    pub fn enable_checks(&self) {
+        self.line(crate::ENABLE_PREFIX);
+    }
+
+    /// Writes a line as a command printing it to stdout: a protocol message, or output
+    /// passed through.
+    pub fn line(&self, line: impl Display) {
+        if self.lines.send(line.to_string()).is_err() {
+            self.stopped.set(true);
+        }
+    }
+
+    /// Whether the comparison no longer reads the messages, e.g. once the commands
+    /// diverged; long-running closures should return then.
+    pub fn stopped(&self) -> bool {
+        self.stopped.get()
+    }
+}
@@ -0,0 +1,47 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Random delays before the commands of every group run start, shuffling their start
+/// order and timing to expose divergences that depend on how they interleave at startup.
+///
+/// The delays of a run are drawn from the seed and the label of the group, so that a run
+/// with the same seed starts its commands the same way.
+#[derive(Debug, Clone)]
+pub struct Jitter {
+    max: Duration,
+    seed: u64,
+}
+
+impl Jitter {
+    /// Delays up to `max`, drawn from `seed`, or from the current time if not given.
+    pub fn new(max: Duration, seed: Option<u64>) -> Self {
+        let seed = seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_nanos() as u64)
+        });
+        Self { max, seed }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Delays of the `count` commands of the group run labelled `label`.
+    pub fn delays(&self, label: &str, count: usize) -> Vec<Duration> {
+        let mut state = self.seed ^ crate::digest(label.as_bytes());
+        (0..count)
+            .map(|_| {
+                let fraction = (splitmix64(&mut state) >> 11) as f64 / (1u64 << 53) as f64;
+                self.max.mul_f64(fraction)
+            })
+            .collect()
+    }
+}
+
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
@@ -0,0 +1,216 @@
+use std::{collections::HashSet, fmt, path::Path};
+
+use super::{
+    json, matrix,
+    testfile::{TestFile, is_known_section},
+};
+
+/// Variables the harness substitutes in commands.
+const HARNESS_VARIABLES: &[&str] = &[matrix::INPUT_VARIABLE, matrix::SEED_VARIABLE];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Level::Warning => write!(f, "warning"),
+            Level::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A problem found in a test file.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    /// Line of the test file the problem is at, 0 if it concerns the whole file
+    pub line: usize,
+    pub level: Level,
+    /// Stable identifier of the kind of problem
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn new(line: usize, level: Level, code: &'static str, message: String) -> Self {
+        Self {
+            line,
+            level,
+            code,
+            message,
+        }
+    }
+
+    /// Formats the diagnostic as `file:line: level[code]: message`.
+    pub fn to_text(&self, file: &str) -> String {
+        format!(
+            "{}:{}: {}[{}]: {}",
+            file, self.line, self.level, self.code, self.message
+        )
+    }
+
+    /// Formats the diagnostic as a single-line JSON object.
+    pub fn to_json(&self, file: &str) -> String {
+        format!(
+            "{{\"file\":{},\"line\":{},\"level\":\"{}\",\"code\":\"{}\",\"message\":{}}}",
+            json::string(file),
+            self.line,
+            self.level,
+            self.code,
+            json::string(&self.message)
+        )
+    }
+}
+
+/// Returns the names of the `${NAME}` variables referenced by a command.
+fn referenced_variables(command: &str) -> Vec<&str> {
+    command
+        .split("${")
+        .skip(1)
+        .filter_map(|rest| rest.split_once('}').map(|(name, _)| name))
+        .collect()
+}
+
+/// Checks a test file for mistakes that make the comparison meaningless or unexpected,
+/// resolving the relative paths of its commands against `dir`, the directory of the test
+/// file. Test files include no other files, so no include can be unreachable.
+pub fn lint(test_file: &TestFile, dir: &Path) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    for (line, section) in &test_file.sections {
+        if !is_known_section(section) {
+            diagnostics.push(Diagnostic::new(
+                *line,
+                Level::Warning,
+                "unknown-section",
+                format!("section '{}' is ignored by the harness", section),
+            ));
+        }
+    }
+
+    let all_commands = || test_file.groups.iter().flat_map(|group| &group.commands);
+
+    for group in &test_file.groups {
+        let mut names = HashSet::new();
+        for command in &group.commands {
+            if !names.insert(command.name.as_str()) {
+                diagnostics.push(Diagnostic::new(
+                    command.line,
+                    Level::Error,
+                    "duplicate-name",
+                    format!(
+                        "test command '{}' is declared twice in group '{}'",
+                        command.name, group.name
+                    ),
+                ));
+            }
+        }
+
+        for (first, second) in group.identical_commands() {
+            diagnostics.push(Diagnostic::new(
+                second.line,
+                Level::Warning,
+                "identical-commands",
+                format!(
+                    "'{}' and '{}' run the same command line in group '{}'",
+                    first.name, second.name, group.name
+                ),
+            ));
+        }
+
+        let runs: usize = group.commands.iter().map(|command| command.instances).sum();
+        if runs < 2 && group.expectations.is_empty() {
+            diagnostics.push(Diagnostic::new(
+                group.commands.first().map_or(0, |command| command.line),
+                Level::Warning,
+                "single-command-group",
+                format!(
+                    "group '{}' runs a single command, so there is nothing to compare it against",
+                    group.name
+                ),
+            ));
+        }
+    }
+
+    if !test_file.inputs.is_empty()
+        && !all_commands()
+            .any(|command| referenced_variables(&command.command).contains(&matrix::INPUT_VARIABLE))
+    {
+        let line = test_file
+            .sections
+            .iter()
+            .find(|(_, section)| section == "inputs")
+            .map_or(0, |(line, _)| *line);
+        diagnostics.push(Diagnostic::new(
+            line,
+            Level::Warning,
+            "unused-variable",
+            format!(
+                "inputs are declared but no command uses ${{{}}}, every input runs the same comparison",
+                matrix::INPUT_VARIABLE
+            ),
+        ));
+    }
+
+    for command in all_commands() {
+        for variable in referenced_variables(&command.command) {
+            if !HARNESS_VARIABLES.contains(&variable) && std::env::var_os(variable).is_none() {
+                diagnostics.push(Diagnostic::new(
+                    command.line,
+                    Level::Warning,
+                    "undefined-variable",
+                    format!(
+                        "'{}' uses ${{{}}}, which is neither a harness variable nor set in the environment",
+                        command.name, variable
+                    ),
+                ));
+            }
+        }
+
+        for word in command.command.split_whitespace() {
+            let path = word.trim_matches(|c| c == '\'' || c == '"');
+            if path.contains('/') && !path.contains('$') && !dir.join(path).exists() {
+                diagnostics.push(Diagnostic::new(
+                    command.line,
+                    Level::Warning,
+                    "missing-file",
+                    format!(
+                        "'{}' references '{}', which does not exist unless the build section creates it",
+                        command.name, path
+                    ),
+                ));
+            }
+        }
+    }
+
+    diagnostics.sort_by_key(|diagnostic| diagnostic.line);
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::testfile::load_test_file;
+
+    #[test]
+    fn paths_resolve_against_the_test_file() {
+        let dir = std::env::temp_dir().join(format!("runtime-diff-lint-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("run.sh"), "").unwrap();
+        let path = dir.join("test.run");
+        std::fs::write(&path, "test:\n    a: sh ./run.sh\n    b: sh ./other.sh\n").unwrap();
+
+        let test_file = load_test_file(path.to_str().unwrap()).unwrap();
+        let missing: Vec<_> = lint(&test_file, &dir)
+            .into_iter()
+            .filter(|diagnostic| diagnostic.code == "missing-file")
+            .collect();
+        assert_eq!(missing.len(), 1, "{:?}", missing);
+        assert_eq!(missing[0].line, 3);
+        assert!(missing[0].message.contains("./other.sh"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,123 @@
+use std::{fmt::Display, io::Write, sync::Arc};
+
+use super::monitor::Monitor;
+
+/// Harness output for a comparison group, prefixing every line with the group name
+/// when several groups share the terminal.
+#[derive(Clone)]
+pub struct Log {
+    prefix: String,
+    quiet: bool,
+    /// Monitor capturing the output instead of the terminal
+    monitor: Option<Arc<Monitor>>,
+}
+
+impl Log {
+    pub fn new(group: Option<&str>) -> Self {
+        Self {
+            prefix: group
+                .map(|group| format!("\x1b[1;35m[{}]\x1b[0m ", group))
+                .unwrap_or_default(),
+            quiet: false,
+            monitor: None,
+        }
+    }
+
+    /// Output stored in `monitor`, for a dashboard owning the terminal.
+    pub fn captured(self, monitor: Arc<Monitor>) -> Self {
+        Self {
+            monitor: Some(monitor),
+            ..self
+        }
+    }
+
+    /// Output discarding everything, for runs whose outcome is all that matters.
+    pub fn quiet() -> Self {
+        Self {
+            prefix: String::new(),
+            quiet: true,
+            monitor: None,
+        }
+    }
+
+    pub fn is_quiet(&self) -> bool {
+        self.quiet
+    }
+
+    /// Prints `text` to stdout in one block, so that it is not interleaved with other groups.
+    pub fn println(&self, text: impl Display) {
+        if self.quiet {
+            return;
+        }
+        let text = text.to_string();
+        if let Some(monitor) = &self.monitor {
+            for line in text.lines() {
+                monitor.push_log(format!("{}{}", self.prefix, line));
+            }
+            return;
+        }
+        let mut stdout = std::io::stdout().lock();
+        for line in text.lines() {
+            let _ = writeln!(stdout, "{}{}", self.prefix, line);
+        }
+    }
+
+    pub fn eprintln(&self, text: impl Display) {
+        if self.quiet {
+            return;
+        }
+        let text = text.to_string();
+        if let Some(monitor) = &self.monitor {
+            for line in text.lines() {
+                monitor.push_log(format!("{}{}", self.prefix, line));
+            }
+            return;
+        }
+        let mut stderr = std::io::stderr().lock();
+        for line in text.lines() {
+            let _ = writeln!(stderr, "{}{}", self.prefix, line);
+        }
+    }
+}
+
+/// Output of a command passed through to the terminal, every line prefixed with the name
+/// of the command. Grouped output is held until the command ends and printed in one block.
+pub struct CommandOutput {
+    log: Log,
+    prefix: String,
+    /// Lines held until the command ends, when grouped
+    block: Option<Vec<String>>,
+}
+
+impl CommandOutput {
+    pub fn new(log: Log, command: &str, grouped: bool) -> Self {
+        Self {
+            log,
+            prefix: format!("\x1b[1;36m[{}]\x1b[0m ", command),
+            block: grouped.then(Vec::new),
+        }
+    }
+
+    pub fn write(&mut self, line: &str) {
+        match &mut self.block {
+            Some(block) => block.push(line.to_string()),
+            None => self
+                .log
+                .println(format_args!("{}\x1b[1;37m{}\x1b[0m", self.prefix, line)),
+        }
+    }
+}
+
+impl Drop for CommandOutput {
+    fn drop(&mut self) {
+        if let Some(block) = self.block.take()
+            && !block.is_empty()
+        {
+            let text: String = block
+                .iter()
+                .map(|line| format!("{}\x1b[1;37m{}\x1b[0m\n", self.prefix, line))
+                .collect();
+            self.log.println(text);
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use std::{path::Path, str::FromStr};
+
+use super::{Error, group::Outcome, testfile::TestGroup};
+
+/// Variable bound to the seed of each run in seed-matrix mode.
+pub const SEED_VARIABLE: &str = "SEED";
+
+/// Variable bound to the number of each run, from 1, when every group is repeated.
+pub const REPEAT_VARIABLE: &str = "REPEAT";
+
+/// Variable bound to the input file of each run in input-corpus mode.
+pub const INPUT_VARIABLE: &str = "INPUT";
+
+/// List of seeds given as comma-separated numbers and ranges, e.g. `1..100,512,1000..=1010`.
+pub struct Seeds(pub Vec<u64>);
+
+impl FromStr for Seeds {
+    type Err = Error;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let parse = |value: &str| {
+            value
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| Error::Config(format!("invalid seed '{}'", value.trim())))
+        };
+
+        let mut seeds = Vec::new();
+        for item in spec.split(',') {
+            if let Some((start, end)) = item.split_once("..=") {
+                seeds.extend(parse(start)?..=parse(end)?);
+            } else if let Some((start, end)) = item.split_once("..") {
+                seeds.extend(parse(start)?..parse(end)?);
+            } else {
+                seeds.push(parse(item)?);
+            }
+        }
+        Ok(Self(seeds))
+    }
+}
+
+/// Resolves the entries of an `inputs:` section to the list of input files, replacing
+/// directories with the files they contain.
+pub fn collect_inputs(entries: &[String]) -> Result<Vec<String>, Error> {
+    let mut inputs = Vec::new();
+    for entry in entries {
+        let path = Path::new(entry);
+        if path.is_dir() {
+            let mut files = Vec::new();
+            for file in std::fs::read_dir(path)? {
+                let file = file?.path();
+                if file.is_file() {
+                    files.push(file.display().to_string());
+                }
+            }
+            files.sort();
+            inputs.extend(files);
+        } else if path.is_file() {
+            inputs.push(entry.clone());
+        } else {
+            return Err(Error::Config(format!("input '{}' does not exist", entry)));
+        }
+    }
+    Ok(inputs)
+}
+
+/// Returns a copy of every group for each value of `variable`.
+pub fn expand(groups: Vec<TestGroup>, variable: &str, values: &[String]) -> Vec<TestGroup> {
+    values
+        .iter()
+        .flat_map(|value| {
+            groups
+                .iter()
+                .map(|group| group.with_variable(variable, value))
+        })
+        .collect()
+}
+
+/// Returns the values of `variable` for which at least one group failed, in first-seen order.
+pub fn diverging_values<'a>(
+    groups: &'a [TestGroup],
+    results: &[Outcome],
+    variable: &str,
+) -> Vec<&'a str> {
+    let mut values = Vec::new();
+    for (group, outcome) in groups.iter().zip(results) {
+        if let Some(value) = group.variable(variable)
+            && !outcome.passed()
+            && !values.contains(&value)
+        {
+            values.push(value);
+        }
+    }
+    values
+}
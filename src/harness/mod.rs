@@ -0,0 +1,63 @@
+//! Harness running test commands side by side and comparing their runtime checks.
+
+pub mod agent;
+mod analyzer;
+pub mod baseline;
+mod bench;
+pub mod breakpoint;
+mod budget;
+mod channels;
+pub mod compare;
+#[cfg(feature = "zstd")]
+mod compression;
+pub mod coverage;
+mod deadline;
+pub mod debuglog;
+pub mod decisions;
+mod difftool;
+mod error;
+pub mod events;
+pub mod export;
+mod extract;
+mod filetree;
+mod fingerprint;
+mod group;
+pub mod history;
+pub mod init;
+pub mod inprocess;
+pub mod jitter;
+mod json;
+pub mod lint;
+mod log;
+pub mod matrix;
+pub mod monitor;
+mod numeric;
+mod protocol;
+mod rate;
+mod rawlog;
+pub mod reduce;
+pub mod report;
+pub mod resume;
+pub mod rules;
+pub mod sandbox;
+pub mod session;
+mod sink;
+mod snapshot;
+pub mod stats;
+mod testfile;
+mod throttle;
+pub mod trace;
+pub mod truncate;
+pub mod unstable;
+pub mod wrapper;
+
+pub use error::Error;
+pub use group::{Outcome, RunOptions, run_group};
+pub use inprocess::{Emitter, InProcessCommand};
+pub use log::Log;
+pub use session::{SessionBuilder, SessionOutcome};
+pub use testfile::{
+    COMPARE_EXIT_STATUS_KEY, DEFAULT_GROUP, DEPENDS_ON_KEY, EXPECT_KEY, QUARANTINE_KEY,
+    TestCommand, TestFile, TestGroup, VERIFY_KEY, check_dependencies, load_test_file,
+    parse_duration,
+};
@@ -0,0 +1,242 @@
+use std::{
+    collections::VecDeque,
+    os::unix::process::ExitStatusExt,
+    process::ExitStatus,
+    sync::{Mutex, MutexGuard},
+    time::Instant,
+};
+
+use serde_json::json;
+
+use super::{
+    events::{self, EventLog},
+    group::Outcome,
+};
+
+/// Number of breadcrumbs kept for each command.
+const RECENT_BREADCUMBS: usize = 8;
+
+/// Live state of the running groups, updated by the harness and read by a dashboard or
+/// streamed as events.
+pub struct Monitor {
+    groups: Mutex<Vec<GroupStatus>>,
+    log: Mutex<Vec<String>>,
+    /// Whether a dashboard shows the state, capturing the output of the harness
+    dashboard: bool,
+    events: Option<EventLog>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GroupStatus {
+    pub label: String,
+    pub commands: Vec<CommandStatus>,
+    /// Checks compared so far that matched across the commands
+    pub matched: u64,
+    /// Set once the group has finished
+    pub outcome: Option<Outcome>,
+    /// When the matched checks were last reported as an event
+    progress_reported: Instant,
+}
+
+impl GroupStatus {
+    /// Number of checks the command at `index` is behind the furthest stream of the group.
+    pub fn lag(&self, index: usize) -> u64 {
+        let furthest = self
+            .commands
+            .iter()
+            .map(|command| command.checks)
+            .max()
+            .unwrap_or(0);
+        furthest - self.commands[index].checks
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CommandStatus {
+    pub name: String,
+    pub pid: Option<u32>,
+    pub running: bool,
+    /// Runtime checks emitted so far
+    pub checks: u64,
+    pub breadcumbs: VecDeque<String>,
+}
+
+/// Resources used by a running process.
+#[derive(Debug, Clone, Copy)]
+pub struct Usage {
+    pub cpu_seconds: f64,
+    pub rss_bytes: u64,
+}
+
+impl Usage {
+    /// Reads the usage of process `pid` from procfs, if available.
+    pub fn of(pid: u32) -> Option<Self> {
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+        // The command name may contain spaces, the fields after it are unambiguous
+        let fields: Vec<_> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+        // utime and stime are fields 14 and 15, counted in USER_HZ (always 100 on Linux)
+        let ticks: u64 =
+            fields.get(11)?.parse::<u64>().ok()? + fields.get(12)?.parse::<u64>().ok()?;
+
+        let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+        let rss_kb: u64 = status
+            .lines()
+            .find_map(|line| line.strip_prefix("VmRSS:"))
+            .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())
+            .unwrap_or(0);
+
+        Some(Self {
+            cpu_seconds: ticks as f64 / 100.0,
+            rss_bytes: rss_kb * 1024,
+        })
+    }
+}
+
+impl Monitor {
+    pub fn new(dashboard: bool, events: Option<EventLog>) -> Self {
+        Self {
+            groups: Mutex::default(),
+            log: Mutex::default(),
+            dashboard,
+            events,
+        }
+    }
+
+    pub fn is_dashboard(&self) -> bool {
+        self.dashboard
+    }
+
+    /// Registers a run of a group, returning its index.
+    pub fn start_group(&self, label: &str, names: &[&str]) -> usize {
+        self.emit(
+            "group_started",
+            json!({ "group": label, "commands": names }),
+        );
+        let mut groups = self.groups();
+        groups.push(GroupStatus {
+            label: label.to_string(),
+            commands: names
+                .iter()
+                .map(|name| CommandStatus {
+                    name: name.to_string(),
+                    pid: None,
+                    running: true,
+                    checks: 0,
+                    breadcumbs: VecDeque::new(),
+                })
+                .collect(),
+            matched: 0,
+            outcome: None,
+            progress_reported: Instant::now(),
+        });
+        groups.len() - 1
+    }
+
+    pub fn set_pid(&self, group: usize, command: usize, pid: u32) {
+        let mut groups = self.groups();
+        let (label, status) = (&groups[group].label, &groups[group].commands[command]);
+        self.emit(
+            "process_started",
+            json!({ "group": label, "command": status.name, "pid": pid }),
+        );
+        groups[group].commands[command].pid = Some(pid);
+    }
+
+    pub fn record_check(&self, group: usize, command: usize) {
+        self.groups()[group].commands[command].checks += 1;
+    }
+
+    pub fn record_breadcumb(&self, group: usize, command: usize, breadcumb: &str) {
+        let mut groups = self.groups();
+        let breadcumbs = &mut groups[group].commands[command].breadcumbs;
+        if breadcumbs.len() == RECENT_BREADCUMBS {
+            breadcumbs.pop_front();
+        }
+        breadcumbs.push_back(breadcumb.to_string());
+    }
+
+    /// Records the number of checks matched so far, reported as an event at most once
+    /// per progress period.
+    pub fn record_matched(&self, group: usize, matched: u64) {
+        let mut groups = self.groups();
+        let status = &mut groups[group];
+        status.matched = matched;
+        if status.progress_reported.elapsed() >= events::PROGRESS_PERIOD {
+            status.progress_reported = Instant::now();
+            self.emit(
+                "checks_matched",
+                json!({ "group": status.label, "count": matched }),
+            );
+        }
+    }
+
+    /// Records the first check at which the commands of a group diverged.
+    pub fn record_mismatch(&self, group: usize, check: usize) {
+        let groups = self.groups();
+        self.emit(
+            "mismatch",
+            json!({ "group": groups[group].label, "check": check }),
+        );
+    }
+
+    /// Records that a command ended, with its exit status unless it was stopped by the
+    /// harness or replayed.
+    pub fn finish_command(&self, group: usize, command: usize, status: Option<ExitStatus>) {
+        let mut groups = self.groups();
+        groups[group].commands[command].running = false;
+        let (label, name) = (&groups[group].label, &groups[group].commands[command].name);
+        self.emit(
+            "process_exited",
+            json!({
+                "group": label,
+                "command": name,
+                "code": status.and_then(|status| status.code()),
+                "signal": status.and_then(|status| status.signal()),
+            }),
+        );
+    }
+
+    pub fn finish_group(&self, group: usize, outcome: Outcome) {
+        let mut groups = self.groups();
+        self.emit(
+            "group_finished",
+            json!({
+                "group": groups[group].label,
+                "outcome": outcome,
+                "matched": groups[group].matched,
+            }),
+        );
+        groups[group].outcome = Some(outcome);
+        for command in &mut groups[group].commands {
+            command.running = false;
+        }
+    }
+
+    /// Returns a copy of the state of every group run so far.
+    pub fn groups_status(&self) -> Vec<GroupStatus> {
+        self.groups().clone()
+    }
+
+    /// Stores output of the harness, shown by the dashboard instead of being printed.
+    pub fn push_log(&self, line: String) {
+        self.log
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(line);
+    }
+
+    /// Returns the output of the harness captured so far.
+    pub fn log_lines(&self) -> Vec<String> {
+        self.log.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    fn emit(&self, kind: &str, fields: serde_json::Value) {
+        if let Some(events) = &self.events {
+            events.emit(kind, fields);
+        }
+    }
+
+    fn groups(&self) -> MutexGuard<'_, Vec<GroupStatus>> {
+        self.groups.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,283 @@
+use std::{
+    fmt::{Result, Write},
+    path::{Path, PathBuf},
+};
+
+/// Maximum number of cells of a heat map row.
+const HEAT_MAP_WIDTH: usize = 64;
+/// Maximum number of rows of an image heat map.
+const HEAT_MAP_HEIGHT: usize = 16;
+/// Shades of the heat map cells, from within tolerance to the largest error.
+const HEAT_MAP_SHADES: &[char] = &[' ', '.', ':', '+', '*', '#'];
+
+/// Array or image check, whose data the library wrote to the snapshot directory.
+#[derive(Debug, PartialEq)]
+struct Blob<'a> {
+    id: &'a str,
+    element: &'a str,
+    /// Elements of an array, width, height and channels of an image
+    dims: Vec<usize>,
+}
+
+impl<'a> Blob<'a> {
+    fn parse(check: &'a str) -> Option<Self> {
+        let mut words = check.strip_prefix(crate::CHECK_PREFIX)?.split_whitespace();
+        let kind = words.next()?;
+        let id = words.next()?;
+        let element = words.next()?;
+        let shape = words.next()?;
+        let dims = match kind {
+            k if k == crate::ARRAY_CHECK => vec![shape.strip_prefix("len=")?.parse().ok()?],
+            k if k == crate::IMAGE_CHECK => shape
+                .split('x')
+                .map(|dim| dim.parse().ok())
+                .collect::<Option<Vec<_>>>()
+                .filter(|dims| dims.len() == 3)?,
+            _ => return None,
+        };
+        Some(Self { id, element, dims })
+    }
+
+    /// Name the blob was recorded with, which tolerances are configured for.
+    fn name(&self) -> &str {
+        self.id.rsplit_once('#').map_or(self.id, |(name, _)| name)
+    }
+
+    fn load(&self, dir: &Path) -> Option<Vec<f64>> {
+        let data = std::fs::read(crate::snapshot_path(dir, self.id)).ok()?;
+        let values: Vec<f64> = match self.element {
+            "f32" => data
+                .chunks_exact(4)
+                .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()) as f64)
+                .collect(),
+            "f64" => data
+                .chunks_exact(8)
+                .map(|bytes| f64::from_le_bytes(bytes.try_into().unwrap()))
+                .collect(),
+            "u8" => data.iter().map(|byte| *byte as f64).collect(),
+            _ => return None,
+        };
+        (values.len() == self.dims.iter().product::<usize>()).then_some(values)
+    }
+}
+
+/// Element-wise comparison of two arrays or images of the same shape.
+struct Comparison {
+    errors: Vec<f64>,
+    tolerance: f64,
+    /// Index and value of the largest error
+    max_error: (usize, f64),
+}
+
+impl Comparison {
+    fn new(a: &[f64], b: &[f64], tolerance: f64) -> Self {
+        let errors = a
+            .iter()
+            .zip(b)
+            .map(|(a, b)| match (a.is_nan(), b.is_nan()) {
+                (true, true) => 0.0,
+                (false, false) if a == b => 0.0,
+                _ => (a - b).abs(),
+            })
+            .map(|error| if error.is_nan() { f64::INFINITY } else { error })
+            .collect::<Vec<_>>();
+        let max_error = errors
+            .iter()
+            .copied()
+            .enumerate()
+            .fold(
+                (0, 0.0),
+                |max, (i, error)| if error > max.1 { (i, error) } else { max },
+            );
+        Self {
+            errors,
+            tolerance,
+            max_error,
+        }
+    }
+
+    fn above_tolerance(&self) -> impl Iterator<Item = (usize, f64)> + '_ {
+        self.errors
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|(_, error)| *error > self.tolerance)
+    }
+
+    /// Shade of a cell whose largest error is `error`.
+    fn shade(&self, error: f64) -> char {
+        let max = self.max_error.1;
+        if error <= self.tolerance || max <= self.tolerance {
+            return HEAT_MAP_SHADES[0];
+        }
+        let level = ((error - self.tolerance) / (max - self.tolerance)
+            * (HEAT_MAP_SHADES.len() - 2) as f64)
+            .ceil() as usize;
+        HEAT_MAP_SHADES[level.clamp(1, HEAT_MAP_SHADES.len() - 1)]
+    }
+
+    /// Largest error of the elements in `rows` and `columns` of a `width` wide layout,
+    /// over `channels` interleaved values per element.
+    fn cell_error(
+        &self,
+        rows: std::ops::Range<usize>,
+        columns: std::ops::Range<usize>,
+        width: usize,
+        channels: usize,
+    ) -> f64 {
+        rows.flat_map(|row| columns.clone().map(move |column| row * width + column))
+            .flat_map(|element| element * channels..(element + 1) * channels)
+            .filter_map(|i| self.errors.get(i))
+            .fold(0.0, |max: f64, error| max.max(*error))
+    }
+
+    fn heat_map(&self, width: usize, height: usize, channels: usize) -> Vec<String> {
+        // Elements covered by cell `cell` of `cells` splitting `len` elements
+        let span = |cell: usize, cells: usize, len: usize| {
+            let start = cell * len / cells;
+            start..((cell + 1) * len / cells).max(start + 1)
+        };
+        let cells_x = width.clamp(1, HEAT_MAP_WIDTH);
+        let cells_y = height.clamp(1, HEAT_MAP_HEIGHT);
+        (0..cells_y)
+            .map(|cell_y| {
+                (0..cells_x)
+                    .map(|cell_x| {
+                        self.shade(self.cell_error(
+                            span(cell_y, cells_y, height),
+                            span(cell_x, cells_x, width),
+                            width,
+                            channels,
+                        ))
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+/// Tolerance configured for the blob named `name`.
+fn tolerance_for(name: &str, tolerance: f64, tolerances: &[(String, f64)]) -> f64 {
+    tolerances
+        .iter()
+        .find(|(blob, _)| blob == name)
+        .map_or(tolerance, |(_, tolerance)| *tolerance)
+}
+
+/// Returns true if two checks match, either exactly or, for arrays and images with the
+/// same id and shape, element by element within the tolerance configured for them.
+pub fn checks_match(
+    checks: [&str; 2],
+    dirs: [&Path; 2],
+    tolerance: f64,
+    tolerances: &[(String, f64)],
+) -> bool {
+    if checks[0] == checks[1] {
+        return true;
+    }
+    let (Some(a), Some(b)) = (Blob::parse(checks[0]), Blob::parse(checks[1])) else {
+        return false;
+    };
+    if a.id != b.id || a.dims != b.dims {
+        return false;
+    }
+    let (Some(a_values), Some(b_values)) = (a.load(dirs[0]), b.load(dirs[1])) else {
+        return false;
+    };
+    let tolerance = tolerance_for(a.name(), tolerance, tolerances);
+    Comparison::new(&a_values, &b_values, tolerance)
+        .above_tolerance()
+        .next()
+        .is_none()
+}
+
+/// Writes an error summary and heat map of the arrays and images behind mismatching
+/// checks, using the first check as the reference.
+pub fn write_numeric_diffs(
+    out: &mut impl Write,
+    names: &[&str],
+    dirs: &[PathBuf],
+    checks: &[Option<String>],
+    tolerance: f64,
+    tolerances: &[(String, f64)],
+) -> Result {
+    let blobs: Vec<_> = checks
+        .iter()
+        .enumerate()
+        .filter_map(|(i, check)| Some((i, Blob::parse(check.as_deref()?)?)))
+        .collect();
+    let Some((reference, reference_blob)) = blobs.first() else {
+        return Ok(());
+    };
+    let Some(reference_values) = reference_blob.load(&dirs[*reference]) else {
+        return writeln!(out, "  cannot read the data of {}", reference_blob.id);
+    };
+    let tolerance = tolerance_for(reference_blob.name(), tolerance, tolerances);
+
+    for (i, blob) in &blobs[1..] {
+        if blob.id != reference_blob.id || blob.dims != reference_blob.dims {
+            writeln!(
+                out,
+                "\x1b[1;34m{} of {} has a different shape than {} of {}, not diffing\x1b[0m",
+                blob.id, names[*i], reference_blob.id, names[*reference]
+            )?;
+            continue;
+        }
+        let Some(values) = blob.load(&dirs[*i]) else {
+            writeln!(
+                out,
+                "  cannot read the data of {} of {}",
+                blob.id, names[*i]
+            )?;
+            continue;
+        };
+
+        let comparison = Comparison::new(&reference_values, &values, tolerance);
+        let above: Vec<_> = comparison.above_tolerance().collect();
+        let Some(&(first, _)) = above.first() else {
+            continue;
+        };
+        let (max_index, max_error) = comparison.max_error;
+        let position = |index: usize| match reference_blob.dims[..] {
+            [width, _, channels] => {
+                let pixel = index / channels;
+                format!(
+                    "pixel ({}, {}) channel {}",
+                    pixel % width,
+                    pixel / width,
+                    index % channels
+                )
+            }
+            _ => format!("index {}", index),
+        };
+
+        writeln!(
+            out,
+            "\x1b[1;34m{} differs between {} and {} (tolerance {}):\x1b[0m",
+            blob.id, names[*reference], names[*i], tolerance
+        )?;
+        writeln!(
+            out,
+            "  max error {} at {}, {} of {} elements above tolerance, first at {}",
+            max_error,
+            position(max_index),
+            above.len(),
+            values.len(),
+            position(first)
+        )?;
+        writeln!(
+            out,
+            "    {}: {}, {}: {}",
+            names[*reference], reference_values[first], names[*i], values[first]
+        )?;
+        let heat_map = match reference_blob.dims[..] {
+            [width, height, channels] => comparison.heat_map(width, height, channels),
+            _ => comparison.heat_map(values.len(), 1, 1),
+        };
+        writeln!(out, "  error heat map:")?;
+        for row in heat_map {
+            writeln!(out, "    |{}|", row)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,380 @@
+/// Capabilities the harness makes use of, with the feature they enable.
+const USED_CAPABILITIES: &[(&str, &str)] = &[
+    (
+        crate::SEQUENCE_CAPABILITY,
+        "detection of lost or reordered messages",
+    ),
+    (
+        crate::PID_CAPABILITY,
+        "separate streams for forked processes sharing stdout",
+    ),
+];
+
+/// Lines of a command with the messages of every batch line as lines of their own, in
+/// order.
+pub fn unbatched(
+    lines: impl Iterator<Item = std::io::Result<String>>,
+) -> impl Iterator<Item = std::io::Result<String>> {
+    lines.flat_map(|line| {
+        let messages = match &line {
+            Ok(text) => text.trim().strip_prefix(crate::BATCH_PREFIX).map(|batch| {
+                batch
+                    .trim_start()
+                    .split(crate::BATCH_SEPARATOR)
+                    .map(|message| Ok(message.to_string()))
+                    .collect()
+            }),
+            Err(_) => None,
+        };
+        messages.unwrap_or_else(|| vec![line])
+    })
+}
+
+/// A line of program output, classified according to the protocol.
+pub enum Line {
+    Hello,
+    Check(String),
+    Breadcumb(String),
+    Output(String),
+    /// A message received already, written again by a forked child flushing its copy of
+    /// the buffer of its parent
+    Duplicate(String),
+}
+
+/// Identity of a process writing to the stream of a command, the same across commands
+/// running the same program: the number of messages its parent wrote before forking it,
+/// and its rank among the writers forked at that point, in the order they first write.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriterId {
+    pub forked_at: u64,
+    pub rank: usize,
+}
+
+impl WriterId {
+    /// Returns true for the process the command started, or the only writer of streams
+    /// without PID tags.
+    pub fn is_first(self) -> bool {
+        self == Self::default()
+    }
+}
+
+impl std::fmt::Display for WriterId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "writer forked at message {}", self.forked_at)?;
+        if self.rank > 0 {
+            write!(f, " #{}", self.rank + 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// Process writing to the stream of a command.
+struct Writer {
+    pid: u32,
+    id: WriterId,
+    next_sequence: u64,
+    /// Depth of the nested regions whose checks are not compared
+    disabled: usize,
+}
+
+/// Protocol state of the output stream of a command, negotiated by the hello line the
+/// library writes before its first message.
+#[derive(Default)]
+pub struct StreamProtocol {
+    version: Option<u32>,
+    capabilities: Vec<String>,
+    /// Writers of the stream, a single one unless messages are tagged with their PID
+    writers: Vec<Writer>,
+    /// Index of the writer of the last message parsed
+    writer: usize,
+}
+
+impl StreamProtocol {
+    pub fn version(&self) -> Option<u32> {
+        self.version
+    }
+
+    /// Process that wrote the last message parsed; its checks are compared with those of
+    /// the writers with the same identity in the other commands.
+    pub fn writer(&self) -> WriterId {
+        self.writers
+            .get(self.writer)
+            .map_or(WriterId::default(), |writer| writer.id)
+    }
+
+    fn has(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+
+    /// Describes the capabilities the harness uses that the stream of `name` lacks, once
+    /// its protocol has been negotiated.
+    pub fn describe_downgrade(&self, name: &str) -> Option<String> {
+        let unavailable: Vec<_> = USED_CAPABILITIES
+            .iter()
+            .filter(|(capability, _)| !self.has(capability))
+            .map(|(capability, feature)| format!("{} ({})", capability, feature))
+            .collect();
+        if unavailable.is_empty() {
+            return None;
+        }
+
+        let protocol = match self.version? {
+            1 => "legacy protocol (plain prefixes only)".to_string(),
+            version => format!("protocol version {}", version),
+        };
+        Some(format!(
+            "'{}' speaks the {}, unavailable capabilities: {}",
+            name,
+            protocol,
+            unavailable.join(", ")
+        ))
+    }
+
+    /// Parses a line, returning an error if it breaks the negotiated protocol.
+    pub fn parse(&mut self, line: &str) -> Result<Line, String> {
+        if let Some(hello) = line.strip_prefix(crate::HELLO_PREFIX) {
+            let mut words = hello.split_whitespace();
+            let version = words
+                .next()
+                .and_then(|version| version.parse().ok())
+                .ok_or_else(|| format!("malformed hello line '{}'", line))?;
+            self.version = Some(version);
+            self.capabilities = words.map(str::to_string).collect();
+            return Ok(Line::Hello);
+        }
+
+        let prefixes = [
+            crate::CHECK_PREFIX,
+            crate::BREADCUMB_PREFIX,
+            crate::DISABLE_PREFIX,
+            crate::ENABLE_PREFIX,
+        ];
+        let Some((prefix, payload)) = prefixes
+            .into_iter()
+            .find_map(|prefix| Some((prefix, line.strip_prefix(prefix)?)))
+        else {
+            return Ok(Line::Output(line.to_string()));
+        };
+
+        // Messages before any hello come from a library speaking plain prefixed lines
+        let version = *self.version.get_or_insert(1);
+        let mut payload = payload.trim_start();
+        let tagged = version >= 4 && self.has(crate::PID_CAPABILITY);
+        let mut sequence = None;
+        let mut pid = 0;
+        if version >= 2 && self.has(crate::SEQUENCE_CAPABILITY) {
+            let (frame, rest) = payload
+                .strip_prefix('[')
+                .and_then(|framed| framed.split_once(']'))
+                .ok_or_else(|| format!("unframed message '{}'", line))?;
+            let frame = match frame.split_once('@') {
+                Some((frame, tag)) if tagged => {
+                    pid = tag
+                        .parse()
+                        .map_err(|_| format!("malformed writer tag in '{}'", line))?;
+                    frame
+                }
+                _ => frame,
+            };
+            sequence = Some(
+                frame
+                    .parse::<u64>()
+                    .map_err(|_| format!("unframed message '{}'", line))?,
+            );
+            payload = rest.trim_start();
+        }
+        self.writer = match self.writers.iter().position(|writer| writer.pid == pid) {
+            Some(writer) => writer,
+            None => {
+                // A forked child continues the sequence of its parent
+                let next_sequence = match sequence {
+                    Some(sequence) if !self.writers.is_empty() => sequence,
+                    _ => 0,
+                };
+                let rank = self
+                    .writers
+                    .iter()
+                    .filter(|writer| writer.id.forked_at == next_sequence)
+                    .count();
+                self.writers.push(Writer {
+                    pid,
+                    id: WriterId {
+                        forked_at: next_sequence,
+                        rank,
+                    },
+                    next_sequence,
+                    disabled: 0,
+                });
+                self.writers.len() - 1
+            }
+        };
+        let writer = &mut self.writers[self.writer];
+        if let Some(sequence) = sequence {
+            if tagged && sequence < writer.next_sequence {
+                return Ok(Line::Duplicate(line.to_string()));
+            }
+            if sequence != writer.next_sequence {
+                return Err(format!(
+                    "expected message {}, got message {}",
+                    writer.next_sequence, sequence
+                ));
+            }
+            writer.next_sequence += 1;
+        }
+
+        // Region markers and the checks inside regions are kept as breadcrumbs
+        let message = format!("{} {}", prefix, payload);
+        match prefix {
+            crate::CHECK_PREFIX if writer.disabled == 0 => Ok(Line::Check(message)),
+            crate::CHECK_PREFIX => Ok(Line::Breadcumb(format!(
+                "{} [not compared] {}",
+                crate::BREADCUMB_PREFIX,
+                message
+            ))),
+            crate::DISABLE_PREFIX | crate::ENABLE_PREFIX => {
+                writer.disabled = match prefix {
+                    crate::DISABLE_PREFIX => writer.disabled + 1,
+                    _ => writer.disabled.saturating_sub(1),
+                };
+                Ok(Line::Breadcumb(format!(
+                    "{} {}",
+                    crate::BREADCUMB_PREFIX,
+                    message.trim_end()
+                )))
+            }
+            _ => Ok(Line::Breadcumb(message)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(protocol: &mut StreamProtocol, line: &str) -> String {
+        match protocol.parse(line) {
+            Ok(Line::Check(check)) => check,
+            _ => panic!("'{}' is not a check", line),
+        }
+    }
+
+    #[test]
+    fn streams_without_hello_speak_the_legacy_protocol() {
+        let mut protocol = StreamProtocol::default();
+        assert!(matches!(protocol.parse("hello"), Ok(Line::Output(_))));
+        assert_eq!(protocol.version(), None);
+        assert_eq!(
+            check(&mut protocol, "RUNTIME CHECK: [0] a"),
+            "RUNTIME CHECK: [0] a"
+        );
+        assert_eq!(protocol.version(), Some(1));
+        let downgrade = protocol.describe_downgrade("a").unwrap();
+        assert!(downgrade.contains("legacy protocol"), "{}", downgrade);
+    }
+
+    #[test]
+    fn sequences_must_not_skip_messages() {
+        let mut protocol = StreamProtocol::default();
+        assert!(matches!(
+            protocol.parse("RUNTIME DIFF HELLO: 2 seq"),
+            Ok(Line::Hello)
+        ));
+        assert_eq!(
+            check(&mut protocol, "RUNTIME CHECK: [0] a"),
+            "RUNTIME CHECK: a"
+        );
+        assert!(protocol.parse("RUNTIME CHECK: b").is_err());
+        assert!(protocol.parse("RUNTIME CHECK: [2] b").is_err());
+        assert!(protocol.parse("RUNTIME DIFF HELLO: two").is_err());
+    }
+
+    #[test]
+    fn checks_in_disabled_regions_are_not_compared() {
+        let mut protocol = StreamProtocol::default();
+        protocol.parse("RUNTIME DIFF HELLO: 2 seq").unwrap();
+        let lines = [
+            "RUNTIME DIFF DISABLE [0]",
+            "RUNTIME DIFF DISABLE [1]",
+            "RUNTIME DIFF ENABLE [2]",
+            "RUNTIME CHECK: [3] a",
+            "RUNTIME DIFF ENABLE [4]",
+        ];
+        for line in lines {
+            match protocol.parse(line) {
+                Ok(Line::Breadcumb(breadcrumb)) => {
+                    assert!(breadcrumb.starts_with(crate::BREADCUMB_PREFIX))
+                }
+                _ => panic!("'{}' is compared", line),
+            }
+        }
+        assert_eq!(
+            check(&mut protocol, "RUNTIME CHECK: [5] b"),
+            "RUNTIME CHECK: b"
+        );
+        // Unbalanced enables do not disable later checks
+        protocol.parse("RUNTIME DIFF ENABLE [6]").unwrap();
+        check(&mut protocol, "RUNTIME CHECK: [7] c");
+    }
+
+    #[test]
+    fn forked_writers_continue_the_sequence_of_their_parent() {
+        let mut protocol = StreamProtocol::default();
+        protocol.parse("RUNTIME DIFF HELLO: 4 seq pid").unwrap();
+        check(&mut protocol, "RUNTIME CHECK: [0@100] a");
+        assert!(protocol.writer().is_first());
+        check(&mut protocol, "RUNTIME CHECK: [1@200] b");
+        assert_eq!(
+            protocol.writer(),
+            WriterId {
+                forked_at: 1,
+                rank: 0
+            }
+        );
+        check(&mut protocol, "RUNTIME CHECK: [1@300] b");
+        assert_eq!(
+            protocol.writer(),
+            WriterId {
+                forked_at: 1,
+                rank: 1
+            }
+        );
+        check(&mut protocol, "RUNTIME CHECK: [1@100] b");
+        assert!(protocol.writer().is_first());
+        assert!(protocol.describe_downgrade("a").is_none());
+
+        // A child flushing the buffer it inherited writes messages of its parent again
+        assert!(matches!(
+            protocol.parse("RUNTIME CHECK: [0@100] a"),
+            Ok(Line::Duplicate(_))
+        ));
+        assert!(protocol.parse("RUNTIME CHECK: [1@x] b").is_err());
+    }
+
+    #[test]
+    fn pid_tags_need_version_4() {
+        let mut protocol = StreamProtocol::default();
+        protocol.parse("RUNTIME DIFF HELLO: 3 seq pid").unwrap();
+        assert!(protocol.parse("RUNTIME CHECK: [0@100] a").is_err());
+
+        let mut protocol = StreamProtocol::default();
+        protocol.parse("RUNTIME DIFF HELLO: 3 seq").unwrap();
+        let downgrade = protocol.describe_downgrade("a").unwrap();
+        assert!(downgrade.contains("protocol version 3"), "{}", downgrade);
+        assert!(downgrade.contains(crate::PID_CAPABILITY), "{}", downgrade);
+    }
+
+    #[test]
+    fn batches_are_split_into_lines() {
+        let lines = [
+            "RUNTIME DIFF BATCH: RUNTIME CHECK: [0] a\x1eRUNTIME CHECK: [1] b",
+            "output",
+        ];
+        let unbatched: Vec<_> = unbatched(lines.into_iter().map(|line| Ok(line.to_string())))
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            unbatched,
+            ["RUNTIME CHECK: [0] a", "RUNTIME CHECK: [1] b", "output"]
+        );
+    }
+}
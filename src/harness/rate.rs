@@ -0,0 +1,85 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// Number of checks over which emission rates are compared.
+pub const WINDOW: usize = 64;
+/// How many times slower than the fastest command a command must emit checks to be flagged.
+const COLLAPSE_RATIO: u32 = 100;
+/// Shortest window duration flagged, below which timings are mostly noise.
+const MIN_DURATION: Duration = Duration::from_millis(50);
+
+/// A command whose check rate collapsed compared to the fastest one.
+pub struct Collapse {
+    pub command: usize,
+    pub fastest: usize,
+    /// Time both took to emit the last checks
+    pub durations: (Duration, Duration),
+}
+
+/// Emission times of the last checks of every command, compared round by round.
+pub struct RateMonitor {
+    times: Vec<VecDeque<Instant>>,
+    warned: Vec<bool>,
+}
+
+impl RateMonitor {
+    pub fn new(commands: usize) -> Self {
+        Self {
+            times: vec![VecDeque::new(); commands],
+            warned: vec![false; commands],
+        }
+    }
+
+    /// Records the time every command emitted its check of a comparison round, none for
+    /// commands that ended, returning the commands that just got much slower than the
+    /// fastest one; every command is only reported once.
+    pub fn record(&mut self, round: &[Option<Instant>]) -> Vec<Collapse> {
+        for (times, time) in self.times.iter_mut().zip(round) {
+            match time {
+                Some(time) => {
+                    times.push_back(*time);
+                    if times.len() > WINDOW + 1 {
+                        times.pop_front();
+                    }
+                }
+                None => times.clear(),
+            }
+        }
+
+        let durations: Vec<Option<Duration>> = self
+            .times
+            .iter()
+            .map(|times| {
+                (times.len() > WINDOW).then(|| *times.back().unwrap() - *times.front().unwrap())
+            })
+            .collect();
+        let Some((fastest, fastest_duration)) = durations
+            .iter()
+            .enumerate()
+            .filter_map(|(i, duration)| Some((i, (*duration)?)))
+            .min_by_key(|(_, duration)| *duration)
+        else {
+            return Vec::new();
+        };
+
+        let mut collapses = Vec::new();
+        for (command, duration) in durations.iter().enumerate() {
+            let Some(duration) = *duration else {
+                continue;
+            };
+            if !self.warned[command]
+                && duration > fastest_duration.max(MIN_DURATION / COLLAPSE_RATIO) * COLLAPSE_RATIO
+            {
+                self.warned[command] = true;
+                collapses.push(Collapse {
+                    command,
+                    fastest,
+                    durations: (duration, fastest_duration),
+                });
+            }
+        }
+        collapses
+    }
+}
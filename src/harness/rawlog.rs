@@ -0,0 +1,101 @@
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{BufRead, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use super::{
+    Error,
+    protocol::{Line, StreamProtocol},
+    truncate,
+};
+
+/// Lines of raw output shown before and after the diverging check.
+const CONTEXT_LINES: usize = 10;
+
+/// Path of the raw output log of command `name` of the group labeled `label` inside `dir`.
+pub fn log_path(dir: &Path, label: &str, name: &str) -> PathBuf {
+    dir.join(label.replace('/', "_"))
+        .join(format!("{}.log", name.replace('/', "_")))
+}
+
+/// Writes every line a command prints, protocol messages included, as it prints it.
+pub struct RawLog {
+    writer: BufWriter<File>,
+}
+
+impl RawLog {
+    pub fn create(path: &Path) -> Result<Self, Error> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        Ok(Self {
+            writer: BufWriter::new(File::create(path)?),
+        })
+    }
+
+    /// Appends a line, flushing it if it is a check the comparison may stop at.
+    pub fn write(&mut self, line: &str, is_check: bool) -> Result<(), Error> {
+        writeln!(self.writer, "{}", line)?;
+        if is_check {
+            self.writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the raw output of a command around the check where it diverged: the last
+/// occurrence of `check` among the first `checks` checks of the log.
+pub fn write_log_context(
+    out: &mut impl std::fmt::Write,
+    name: &str,
+    path: &Path,
+    check: &str,
+    checks: usize,
+    max_line_bytes: usize,
+) -> std::fmt::Result {
+    let Ok(file) = File::open(path) else {
+        return writeln!(out, "  cannot read the output log {}", path.display());
+    };
+    let lines: Vec<String> = std::io::BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .collect();
+
+    let mut protocol = StreamProtocol::default();
+    let mut seen = 0;
+    let mut position = None;
+    for (i, line) in lines.iter().enumerate() {
+        if seen == checks {
+            break;
+        }
+        if let Ok(Line::Check(parsed)) = protocol.parse(line.trim()) {
+            seen += 1;
+            if parsed == check || position.is_none() && seen == checks {
+                position = Some(i);
+            }
+        }
+    }
+    let Some(position) = position else {
+        return Ok(());
+    };
+
+    let mut context = String::new();
+    let start = position.saturating_sub(CONTEXT_LINES);
+    let end = (position + CONTEXT_LINES + 1).min(lines.len());
+    for (i, line) in lines.iter().enumerate().take(end).skip(start) {
+        let marker = if i == position { '>' } else { ' ' };
+        let line = truncate::within_budget(line, max_line_bytes, || {
+            Some(format!("{}:{}", path.display(), i + 1))
+        });
+        writeln!(context, "{} {:>6} {}", marker, i + 1, line)?;
+    }
+    writeln!(
+        out,
+        "\x1b[1;34mExecutable \x1b[1;37m{}\x1b[1;34m output around the divergence ({}):\x1b[0m",
+        name,
+        path.display()
+    )?;
+    out.write_str(&context)
+}
@@ -0,0 +1,232 @@
+use std::path::{Path, PathBuf};
+
+use super::{
+    Error,
+    group::{RunOptions, run_group},
+    log::Log,
+    matrix,
+    testfile::TestGroup,
+    wrapper::shell_quote,
+};
+
+/// Number of lines of a minimized text input printed in the report.
+const MAX_PRINTED_LINES: usize = 20;
+
+/// Shrinks the input of a group whose runtime checks diverged, keeping it diverging.
+pub struct Reduction<'a> {
+    group: &'a TestGroup,
+    options: RunOptions,
+    work_dir: PathBuf,
+}
+
+/// Options of the runs of a reduction: how the commands of the main run are run and
+/// compared, without its recordings, logs, statistics and reruns.
+fn reduction_options(options: &RunOptions) -> RunOptions {
+    RunOptions {
+        max_breadcumbs: options.max_breadcumbs,
+        last_agreements: 0,
+        snapshot_dir: options.snapshot_dir.join("reduce"),
+        fingerprint: false,
+        monitor: None,
+        record_dir: None,
+        diff_tool: None,
+        diff_tools: Vec::new(),
+        analyzers: Vec::new(),
+        tolerance: options.tolerance,
+        rules: options.rules.clone(),
+        max_lead: None,
+        reference_dir: options.reference_dir.clone(),
+        in_process: options.in_process.clone(),
+        routes: options.routes.clone(),
+        log_dir: None,
+        debug_rerun: false,
+        break_at: None,
+        divergence_hook: None,
+        wrapper: options.wrapper.clone(),
+        confirm: false,
+        jitter: None,
+        group_output: false,
+        strict_config: false,
+        max_buffer_mem: options.max_buffer_mem,
+        bench: false,
+        thresholds: Vec::new(),
+        check_deadline: None,
+        fail_on_deadline: false,
+        max_payload_bytes: options.max_payload_bytes,
+        debug_log: None,
+        decision_log: None,
+        stats: None,
+        unstable_sites: None,
+        resume: None,
+    }
+}
+
+impl<'a> Reduction<'a> {
+    pub fn new(group: &'a TestGroup, options: &RunOptions, work_dir: &Path) -> Self {
+        Self {
+            group,
+            options: reduction_options(options),
+            work_dir: work_dir.to_path_buf(),
+        }
+    }
+
+    fn input(&self) -> Result<&'a str, Error> {
+        self.group
+            .variable(matrix::INPUT_VARIABLE)
+            .ok_or_else(|| Error::Config(format!("group '{}' has no input", self.group.label())))
+    }
+
+    fn work_path(&self, prefix: &str) -> Result<PathBuf, Error> {
+        let file_name = Path::new(self.input()?)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        Ok(self.work_dir.join(format!("{}{}", prefix, file_name)))
+    }
+
+    fn diverges(&self, input: &Path) -> bool {
+        let mut group = self.group.clone();
+        group.set_variable(matrix::INPUT_VARIABLE, &input.display().to_string());
+        matches!(
+            run_group(&group, &self.options, &Log::quiet()),
+            Err(Error::Mismatch { .. })
+        )
+    }
+
+    /// Minimizes the input with delta debugging over its lines, or over its bytes when it
+    /// is not text, returning the path of the minimized input.
+    pub fn run_builtin(&self) -> Result<PathBuf, Error> {
+        let data = std::fs::read(self.input()?)?;
+        let units: Vec<&[u8]> = if std::str::from_utf8(&data).is_ok() {
+            data.split_inclusive(|byte| *byte == b'\n').collect()
+        } else {
+            data.chunks(1).collect()
+        };
+
+        let candidate_path = self.work_path("candidate-")?;
+        let minimized = ddmin(units, |candidate| {
+            std::fs::write(&candidate_path, candidate.concat()).is_ok()
+                && self.diverges(&candidate_path)
+        });
+        let _ = std::fs::remove_file(&candidate_path);
+
+        let minimized_path = self.work_path("")?;
+        std::fs::write(&minimized_path, minimized.concat())?;
+        Ok(minimized_path)
+    }
+
+    /// Minimizes the input with a user-provided reducer, which is given a working copy of
+    /// the input as `${INPUT}` to shrink in place and an interestingness test script as
+    /// `${TEST}`, succeeding while the comparison still diverges on its argument or on the
+    /// working copy.
+    pub fn run_external(&self, reducer: &str, testfile: &str) -> Result<PathBuf, Error> {
+        let minimized_path = self.work_path("")?;
+        std::fs::copy(self.input()?, &minimized_path)?;
+
+        let minimized_path = std::fs::canonicalize(&minimized_path)?;
+        let test_path = self.work_path("test-")?.with_extension("sh");
+        let executable = std::env::current_exe()?;
+        let seed_args = self
+            .group
+            .variable(matrix::SEED_VARIABLE)
+            .map(|seed| format!(" --seeds {}", shell_quote(seed)))
+            .unwrap_or_default();
+        let script = format!(
+            "#!/bin/sh\ninput={}\n[ $# -gt 0 ] && input=$1\nexec {} {} --group {} --interestingness-test \"$input\" --no-fingerprint{} >/dev/null 2>&1\n",
+            shell_quote(&minimized_path.display().to_string()),
+            shell_quote(&executable.display().to_string()),
+            shell_quote(&std::fs::canonicalize(testfile)?.display().to_string()),
+            shell_quote(&self.group.name),
+            seed_args
+        );
+        std::fs::write(&test_path, script)?;
+        make_executable(&test_path)?;
+
+        let command = reducer
+            .replace(
+                "${INPUT}",
+                &shell_quote(&minimized_path.display().to_string()),
+            )
+            .replace("${TEST}", &shell_quote(&test_path.display().to_string()));
+        let status = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .status()
+            .map_err(|source| Error::Spawn {
+                command: command.clone(),
+                source,
+            })?;
+        if !status.success() {
+            return Err(Error::CommandFailed { command, status });
+        }
+        Ok(minimized_path)
+    }
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+    Ok(std::fs::set_permissions(
+        path,
+        std::fs::Permissions::from_mode(0o755),
+    )?)
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Delta debugging: repeatedly removes chunks of `units` of decreasing size while the
+/// remaining units are still `interesting`.
+fn ddmin<'d>(
+    mut units: Vec<&'d [u8]>,
+    mut interesting: impl FnMut(&[&'d [u8]]) -> bool,
+) -> Vec<&'d [u8]> {
+    let mut granularity = 2;
+    while units.len() >= 2 {
+        let chunk_size = units.len().div_ceil(granularity);
+        let reduced = (0..units.len()).step_by(chunk_size).find_map(|start| {
+            let end = (start + chunk_size).min(units.len());
+            let complement: Vec<_> = units[..start]
+                .iter()
+                .chain(&units[end..])
+                .copied()
+                .collect();
+            interesting(&complement).then_some(complement)
+        });
+
+        match reduced {
+            Some(complement) => {
+                units = complement;
+                granularity = (granularity - 1).max(2);
+            }
+            None if granularity >= units.len() => break,
+            None => granularity = (granularity * 2).min(units.len()),
+        }
+    }
+    units
+}
+
+/// Formats the minimized input for the report.
+pub fn describe(original: &str, minimized: &Path) -> String {
+    let (original_size, minimized_data) = (
+        std::fs::metadata(original).map(|m| m.len()).unwrap_or(0),
+        std::fs::read(minimized).unwrap_or_default(),
+    );
+    let mut description = format!(
+        "\x1b[1;34mMinimized input for \x1b[1;37m{}\x1b[1;34m: {} ({} of {} bytes)\x1b[0m\n",
+        original,
+        minimized.display(),
+        minimized_data.len(),
+        original_size
+    );
+    if let Ok(text) = std::str::from_utf8(&minimized_data)
+        && text.lines().count() <= MAX_PRINTED_LINES
+    {
+        for line in text.lines() {
+            description.push_str(&format!("  {}\n", line));
+        }
+    }
+    description
+}
@@ -0,0 +1,181 @@
+use std::{fmt::Write, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use super::{Error, group::Outcome, testfile::TestGroup};
+
+/// Version of the report format, bumped on incompatible changes.
+pub const REPORT_VERSION: u32 = 1;
+
+/// Outcome of every group of one or more harness invocations, saved as JSON.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Report {
+    pub version: u32,
+    pub groups: Vec<GroupReport>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupReport {
+    pub label: String,
+    pub outcome: Outcome,
+    /// Why the group did not pass
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Outcome of every run of the group in the merged reports
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub runs: Vec<Outcome>,
+    /// The failures of the group do not fail the run
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub quarantined: bool,
+}
+
+impl GroupReport {
+    pub fn new(group: &TestGroup, result: &Result<(), Error>) -> Self {
+        Self {
+            label: group.label(),
+            outcome: Outcome::of(result),
+            error: result.as_ref().err().map(|e| e.to_string()),
+            runs: Vec::new(),
+            quarantined: group.quarantine,
+        }
+    }
+
+    pub fn skipped(group: &TestGroup, reason: String) -> Self {
+        Self {
+            label: group.label(),
+            outcome: Outcome::Skipped,
+            error: Some(reason),
+            runs: Vec::new(),
+            quarantined: group.quarantine,
+        }
+    }
+
+    /// Returns true if the merged runs of the group did not all have the same outcome.
+    pub fn is_flaky(&self) -> bool {
+        self.runs.iter().any(|outcome| *outcome != self.runs[0])
+    }
+
+    /// Returns true if the group failed while quarantined.
+    pub fn is_quarantined_failure(&self) -> bool {
+        self.quarantined && !self.outcome.passed()
+    }
+}
+
+impl Report {
+    pub fn new(groups: Vec<GroupReport>) -> Self {
+        Self {
+            version: REPORT_VERSION,
+            groups,
+        }
+    }
+
+    /// Returns true if every group passed, is quarantined or diverged only once.
+    pub fn passed(&self) -> bool {
+        self.groups.iter().all(|group| {
+            group.outcome.passed() || group.outcome == Outcome::Flaky || group.quarantined
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let report: Report = serde_json::from_str(&std::fs::read_to_string(path)?)
+            .map_err(|e| Error::Config(format!("invalid report: {}", e)))?;
+        if report.version != REPORT_VERSION {
+            return Err(Error::Config(format!(
+                "report version {}, expected {}",
+                report.version, REPORT_VERSION
+            )));
+        }
+        Ok(report)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| Error::Config(format!("cannot serialize report: {}", e)))?;
+        std::fs::write(path, json + "\n")?;
+        Ok(())
+    }
+
+    /// Combines the reports of several invocations, such as sharded CI jobs.
+    ///
+    /// A group run by several of them keeps the outcome of its first failed run and the
+    /// error explaining it, and records the outcome of every run to expose flaky groups.
+    pub fn merge(reports: &[Report]) -> Self {
+        let mut groups: Vec<GroupReport> = Vec::new();
+        for group in reports.iter().flat_map(|report| &report.groups) {
+            let runs = if group.runs.is_empty() {
+                vec![group.outcome]
+            } else {
+                group.runs.clone()
+            };
+            match groups.iter_mut().find(|merged| merged.label == group.label) {
+                Some(merged) => {
+                    merged.runs.extend(runs);
+                    merged.quarantined |= group.quarantined;
+                    if merged.outcome.passed() && !group.outcome.passed() {
+                        merged.outcome = group.outcome;
+                        merged.error = group.error.clone();
+                    }
+                }
+                None => groups.push(GroupReport {
+                    runs,
+                    ..group.clone()
+                }),
+            }
+        }
+        Self::new(groups)
+    }
+}
+
+/// Writes the outcome of every group, with the reason of the failures when `details`.
+pub fn write_summary(out: &mut impl Write, report: &Report, details: bool) -> std::fmt::Result {
+    writeln!(out, "\x1b[1;34mSummary:\x1b[0m")?;
+    for group in &report.groups {
+        match group.outcome {
+            Outcome::Passed => write!(out, "  \x1b[1;32mpassed\x1b[0m {}", group.label)?,
+            Outcome::Skipped => write!(out, "  \x1b[1;33mskipped\x1b[0m {}", group.label)?,
+            Outcome::Flaky => write!(out, "  \x1b[1;33mflaky\x1b[0m {}", group.label)?,
+            Outcome::Sandbox => {
+                write!(out, "  \x1b[1;31msandbox violation\x1b[0m {}", group.label)?
+            }
+            _ => write!(out, "  \x1b[1;31mfailed\x1b[0m {}", group.label)?,
+        }
+        if group.is_quarantined_failure() {
+            write!(out, " \x1b[1;33m(quarantined)\x1b[0m")?;
+        }
+        if group.is_flaky() {
+            let passed = group.runs.iter().filter(|outcome| outcome.passed()).count();
+            write!(
+                out,
+                " \x1b[1;33m(flaky: passed {} of {} runs)\x1b[0m",
+                passed,
+                group.runs.len()
+            )?;
+        }
+        writeln!(out)?;
+        if details && let Some(error) = &group.error {
+            writeln!(out, "    {}", error)?;
+        }
+    }
+    write_quarantined(out, report)
+}
+
+/// Lists the quarantined groups that failed, whose failures do not fail the run.
+pub fn write_quarantined(out: &mut impl Write, report: &Report) -> std::fmt::Result {
+    let failures: Vec<_> = report
+        .groups
+        .iter()
+        .filter(|group| group.is_quarantined_failure())
+        .collect();
+    if failures.is_empty() {
+        return Ok(());
+    }
+    writeln!(
+        out,
+        "\x1b[1;33m{} quarantined group(s) failed without failing the run:\x1b[0m",
+        failures.len()
+    )?;
+    for group in failures {
+        writeln!(out, "  {}", group.label)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,111 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::Error;
+
+/// Time between two saves of the progress of a group.
+pub const SAVE_PERIOD: Duration = Duration::from_secs(5);
+
+/// Last check all the commands of a group agreed on.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResumePoint {
+    /// Checks compared, counting from the start of the run
+    pub checks: usize,
+    /// Rolling digest of the checks of every command, by command name
+    pub digests: BTreeMap<String, u64>,
+}
+
+/// Progress of the groups of a run, saved periodically so that a crashed run can resume
+/// from the last checks all its commands agreed on.
+pub struct ResumeFile {
+    path: PathBuf,
+    /// Points the groups resume from, loaded from the file when resuming
+    resumed: BTreeMap<String, ResumePoint>,
+    current: Mutex<BTreeMap<String, ResumePoint>>,
+}
+
+impl ResumeFile {
+    /// Saves progress to `path`, resuming from what it holds if `resume`.
+    pub fn new(path: &Path, resume: bool) -> Result<Self, Error> {
+        let resumed: BTreeMap<String, ResumePoint> = match std::fs::read_to_string(path) {
+            Ok(json) if resume => serde_json::from_str(&json)
+                .map_err(|e| Error::Config(format!("invalid checkpoint file: {}", e)))?,
+            Err(e) if resume && e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => BTreeMap::new(),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            current: Mutex::new(resumed.clone()),
+            resumed,
+        })
+    }
+
+    /// Point the group with the given label resumes from, if it had one when the harness
+    /// started.
+    pub fn resume_point(&self, label: &str) -> Option<&ResumePoint> {
+        self.resumed.get(label)
+    }
+
+    /// Records the progress of a group.
+    pub fn save(&self, label: &str, point: ResumePoint) -> Result<(), Error> {
+        let mut current = self.current.lock().unwrap_or_else(|e| e.into_inner());
+        current.insert(label.to_string(), point);
+        self.write(&current)
+    }
+
+    /// Forgets the progress of a group that needs no resuming.
+    pub fn remove(&self, label: &str) -> Result<(), Error> {
+        let mut current = self.current.lock().unwrap_or_else(|e| e.into_inner());
+        if current.remove(label).is_some() {
+            self.write(&current)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the file, so that a crash while saving leaves the previous progress.
+    fn write(&self, points: &BTreeMap<String, ResumePoint>) -> Result<(), Error> {
+        let json = serde_json::to_string_pretty(points)
+            .map_err(|e| Error::Config(format!("cannot serialize checkpoints: {}", e)))?;
+        let temporary = self.path.with_extension("tmp");
+        std::fs::write(&temporary, json + "\n")?;
+        std::fs::rename(&temporary, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Checks the check a command emitted once it skipped the checks before `point`, failing
+/// unless the command skipped the same checks as when the progress was saved.
+pub fn check_resumed(command: &str, check: &str, point: &ResumePoint) -> Result<(), Error> {
+    let expected = format!(
+        "{} at={} digest={:016x}",
+        crate::RESUME_CHECK,
+        point.checks,
+        point.digests.get(command).copied().unwrap_or_default()
+    );
+    let payload = check.strip_prefix(crate::CHECK_PREFIX).unwrap_or(check);
+    if payload.trim() == expected {
+        return Ok(());
+    }
+    let message = if payload.trim_start().starts_with(crate::RESUME_CHECK) {
+        format!(
+            "resumed with '{}', expected '{}': it skipped other checks than the saved run",
+            payload.trim(),
+            expected
+        )
+    } else {
+        format!(
+            "did not skip to check {}, its instrumentation may predate resuming",
+            point.checks
+        )
+    };
+    Err(Error::Protocol {
+        command: command.to_string(),
+        message,
+    })
+}
@@ -0,0 +1,182 @@
+use std::{
+    borrow::Cow,
+    sync::{Arc, RwLock},
+};
+
+use regex::Regex;
+
+use super::{
+    Error,
+    extract::{self, Extraction},
+    testfile::{TestFile, Verbosity, load_test_file},
+};
+
+/// Comparison settings of the test file, which can be reloaded during a run.
+#[derive(Debug, Clone, Default)]
+pub struct Rules {
+    /// Per-element tolerance of arrays and images, by the name they are recorded with
+    pub tolerances: Vec<(String, f64)>,
+    pub extractions: Vec<Extraction>,
+    /// Patterns of the checks that are not compared
+    pub ignores: Vec<Regex>,
+    pub verbosity: Verbosity,
+}
+
+impl Rules {
+    pub fn of(test_file: &TestFile) -> Self {
+        Self {
+            tolerances: test_file.tolerances.clone(),
+            extractions: test_file.extractions.clone(),
+            ignores: test_file.ignores.clone(),
+            verbosity: test_file.verbosity.clone(),
+        }
+    }
+
+    /// Returns true if `check` matches an ignore pattern.
+    pub fn is_ignored(&self, check: &str) -> bool {
+        self.ignore_pattern(check).is_some()
+    }
+
+    /// First ignore pattern `check` matches.
+    fn ignore_pattern(&self, check: &str) -> Option<&Regex> {
+        let payload = check.strip_prefix(crate::CHECK_PREFIX).unwrap_or(check);
+        self.ignores
+            .iter()
+            .find(|pattern| pattern.is_match(payload.trim_start()))
+    }
+
+    /// Comparable form of `check`, as extracted by the first rule matching it.
+    pub fn extract<'a>(&self, check: &'a str) -> Cow<'a, str> {
+        extract::extract(&self.extractions, check)
+    }
+
+    /// Comparable form of `check`, recording the change in `normalizations`.
+    pub fn normalize<'a>(
+        &self,
+        check: &'a str,
+        normalizations: &mut Normalizations,
+    ) -> Cow<'a, str> {
+        match extract::extract_with_rule(&self.extractions, check) {
+            Some((rule, extracted)) => {
+                if extracted != check {
+                    let pattern = self.extractions[rule].pattern();
+                    normalizations.record("extract", pattern, check, Some(&extracted));
+                }
+                Cow::Owned(extracted)
+            }
+            None => Cow::Borrowed(check),
+        }
+    }
+
+    /// Rule changing `check` before it is compared, as `section 'pattern'`: the ignore
+    /// pattern it matches if `ignored`, else the extraction rule giving another form.
+    pub fn applied_rule(&self, check: &str, ignored: bool) -> Option<String> {
+        if ignored {
+            return Some(format!("ignore '{}'", self.ignore_pattern(check)?.as_str()));
+        }
+        let (rule, extracted) = extract::extract_with_rule(&self.extractions, check)?;
+        (extracted != check).then(|| format!("extract '{}'", self.extractions[rule].pattern()))
+    }
+
+    /// Records the ignored `check` in `normalizations`.
+    pub fn record_ignored(&self, check: &str, normalizations: &mut Normalizations) {
+        if let Some(pattern) = self.ignore_pattern(check) {
+            normalizations.record("ignore", pattern.as_str(), check, None);
+        }
+    }
+}
+
+/// Checks kept as examples of what each rule changed.
+const NORMALIZATION_SAMPLES: usize = 3;
+
+/// Checks an extraction or ignore rule changed.
+#[derive(Debug)]
+pub struct Normalized {
+    /// Section of the rule
+    pub section: &'static str,
+    pub pattern: String,
+    pub checks: usize,
+    /// First distinct checks changed, with their comparable form unless ignored
+    pub samples: Vec<(String, Option<String>)>,
+}
+
+/// Checks the extraction and ignore rules changed during a group run, by rule, so that an
+/// over-broad pattern masking real divergences can be noticed.
+#[derive(Debug, Default)]
+pub struct Normalizations(Vec<Normalized>);
+
+impl Normalizations {
+    fn record(
+        &mut self,
+        section: &'static str,
+        pattern: &str,
+        check: &str,
+        normalized: Option<&str>,
+    ) {
+        let index = match self
+            .0
+            .iter()
+            .position(|rule| rule.section == section && rule.pattern == pattern)
+        {
+            Some(index) => index,
+            None => {
+                self.0.push(Normalized {
+                    section,
+                    pattern: pattern.to_string(),
+                    checks: 0,
+                    samples: Vec::new(),
+                });
+                self.0.len() - 1
+            }
+        };
+        let rule = &mut self.0[index];
+        rule.checks += 1;
+        if rule.samples.len() < NORMALIZATION_SAMPLES
+            && !rule.samples.iter().any(|(sample, _)| sample == check)
+        {
+            rule.samples
+                .push((check.to_string(), normalized.map(str::to_string)));
+        }
+    }
+
+    /// Rules that changed checks, in the order they first did.
+    pub fn rules(&self) -> &[Normalized] {
+        &self.0
+    }
+}
+
+/// Rules shared by the groups of a run, replaced as a whole on reload.
+#[derive(Default)]
+pub struct SharedRules(RwLock<Arc<Rules>>);
+
+impl SharedRules {
+    pub fn new(rules: Rules) -> Self {
+        Self(RwLock::new(Arc::new(rules)))
+    }
+
+    pub fn current(&self) -> Arc<Rules> {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn replace(&self, rules: Rules) {
+        *self.0.write().unwrap() = Arc::new(rules);
+    }
+}
+
+/// Reloads the rules from the test file at `path` whenever the harness gets SIGHUP, so
+/// that a noisy check can be silenced without restarting a long run.
+pub fn reload_on_sighup(path: String, rules: Arc<SharedRules>) -> Result<(), Error> {
+    let mut signals = signal_hook::iterator::Signals::new([signal_hook::consts::SIGHUP])?;
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            match load_test_file(&path) {
+                Ok(test_file) => {
+                    rules.replace(Rules::of(&test_file));
+                    eprintln!("\x1b[1;33mReloaded the comparison rules of {}\x1b[0m", path);
+                }
+                Err(e) => eprintln!("Cannot reload {}, keeping the current rules: {}", path, e),
+            }
+        }
+    });
+    Ok(())
+}
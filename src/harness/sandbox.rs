@@ -0,0 +1,188 @@
+use std::{os::unix::process::ExitStatusExt, process::ExitStatus, str::FromStr};
+
+use super::Error;
+
+/// Syscalls denied when a profile lists none: debugging other processes, mounting,
+/// loading kernel code and rebooting.
+const DEFAULT_DENIED: &[&str] = &[
+    "ptrace",
+    "process_vm_readv",
+    "process_vm_writev",
+    "mount",
+    "umount2",
+    "pivot_root",
+    "bpf",
+    "init_module",
+    "finit_module",
+    "delete_module",
+    "kexec_load",
+    "reboot",
+];
+
+/// Signal killing a sandboxed command that makes a denied syscall.
+const VIOLATION_SIGNAL: i32 = 31;
+
+/// Whether a sandboxed command exiting with `status` was killed for a denied syscall,
+/// either itself or a process whose status the shell passed on.
+pub fn is_violation(status: ExitStatus) -> bool {
+    status.signal() == Some(VIOLATION_SIGNAL) || status.code() == Some(128 + VIOLATION_SIGNAL)
+}
+
+/// Restrictions of a sandboxed command, given as `read=paths; write=paths; deny=syscalls`
+/// with comma separated lists; every part is optional.
+///
+/// The command may read and execute everything under `read`, `/` by default, and write
+/// under `write` and its snapshot directory only. Denied syscalls kill it with SIGSYS,
+/// reported as a sandbox violation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sandbox {
+    pub read: Vec<String>,
+    pub write: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+impl FromStr for Sandbox {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut sandbox = Self {
+            read: vec!["/".to_string()],
+            write: Vec::new(),
+            deny: DEFAULT_DENIED.iter().map(|name| name.to_string()).collect(),
+        };
+        for part in s.split(';').map(str::trim).filter(|part| !part.is_empty()) {
+            if part == "default" {
+                continue;
+            }
+            let (key, values) = part
+                .split_once('=')
+                .ok_or_else(|| format!("expected 'key=values', got '{}'", part))?;
+            let values: Vec<String> = values
+                .split(',')
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+                .collect();
+            match key.trim() {
+                "read" => sandbox.read = values,
+                "write" => sandbox.write = values,
+                "deny" => {
+                    if let Some(unknown) = values.iter().find(|name| syscall_number(name).is_none())
+                    {
+                        return Err(format!("unknown syscall '{}'", unknown));
+                    }
+                    sandbox.deny = values;
+                }
+                key => {
+                    return Err(format!(
+                        "unknown sandbox setting '{}', expected read, write or deny",
+                        key
+                    ));
+                }
+            }
+        }
+        Ok(sandbox)
+    }
+}
+
+impl std::fmt::Display for Sandbox {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "read={}; write={}; deny={}",
+            self.read.join(","),
+            self.write.join(","),
+            self.deny.join(",")
+        )
+    }
+}
+
+/// Number of the syscall named `name` on this architecture.
+#[cfg(feature = "sandbox")]
+fn syscall_number(name: &str) -> Option<i64> {
+    let number = match name {
+        "ptrace" => libc::SYS_ptrace,
+        "process_vm_readv" => libc::SYS_process_vm_readv,
+        "process_vm_writev" => libc::SYS_process_vm_writev,
+        "mount" => libc::SYS_mount,
+        "umount2" => libc::SYS_umount2,
+        "pivot_root" => libc::SYS_pivot_root,
+        "bpf" => libc::SYS_bpf,
+        "init_module" => libc::SYS_init_module,
+        "finit_module" => libc::SYS_finit_module,
+        "delete_module" => libc::SYS_delete_module,
+        "kexec_load" => libc::SYS_kexec_load,
+        "reboot" => libc::SYS_reboot,
+        "socket" => libc::SYS_socket,
+        "connect" => libc::SYS_connect,
+        "bind" => libc::SYS_bind,
+        "listen" => libc::SYS_listen,
+        "unshare" => libc::SYS_unshare,
+        "setns" => libc::SYS_setns,
+        "chroot" => libc::SYS_chroot,
+        "personality" => libc::SYS_personality,
+        "perf_event_open" => libc::SYS_perf_event_open,
+        "keyctl" => libc::SYS_keyctl,
+        "userfaultfd" => libc::SYS_userfaultfd,
+        _ => return None,
+    };
+    Some(number)
+}
+
+#[cfg(not(feature = "sandbox"))]
+fn syscall_number(_name: &str) -> Option<i64> {
+    None
+}
+
+/// Restricts the current process as `sandbox` describes, the snapshot directory of the
+/// command staying writable, before it executes the command.
+#[cfg(feature = "sandbox")]
+pub fn restrict_self(sandbox: &Sandbox) -> Result<(), Error> {
+    use landlock::{
+        ABI, Access, AccessFs, Ruleset, RulesetAttr, RulesetCreatedAttr, path_beneath_rules,
+    };
+    use seccompiler::{BpfProgram, SeccompAction, SeccompFilter};
+
+    let failed =
+        |what: &str, e: &dyn std::fmt::Display| Error::Config(format!("cannot {}: {}", what, e));
+    let abi = ABI::V2;
+    let mut write = sandbox.write.clone();
+    write.extend(std::env::var(crate::SNAPSHOT_DIR_ENV));
+    write.push("/dev/null".to_string());
+    Ruleset::default()
+        .handle_access(AccessFs::from_all(abi))
+        .and_then(|ruleset| ruleset.create())
+        .and_then(|ruleset| {
+            ruleset.add_rules(path_beneath_rules(&sandbox.read, AccessFs::from_read(abi)))
+        })
+        .and_then(|ruleset| ruleset.add_rules(path_beneath_rules(&write, AccessFs::from_all(abi))))
+        .and_then(|ruleset| ruleset.restrict_self())
+        .map_err(|e| failed("restrict filesystem access", &e))?;
+
+    let rules = sandbox
+        .deny
+        .iter()
+        .filter_map(|name| syscall_number(name))
+        .map(|number| (number, Vec::new()))
+        .collect();
+    let arch = std::env::consts::ARCH
+        .try_into()
+        .map_err(|e| failed("filter syscalls", &e))?;
+    let filter: BpfProgram = SeccompFilter::new(
+        rules,
+        SeccompAction::Allow,
+        SeccompAction::KillProcess,
+        arch,
+    )
+    .map_err(|e| failed("filter syscalls", &e))?
+    .try_into()
+    .map_err(|e| failed("filter syscalls", &e))?;
+    seccompiler::apply_filter(&filter).map_err(|e| failed("filter syscalls", &e))
+}
+
+#[cfg(not(feature = "sandbox"))]
+pub fn restrict_self(_sandbox: &Sandbox) -> Result<(), Error> {
+    Err(Error::Config(
+        "runtime-diff was built without sandbox support".to_string(),
+    ))
+}
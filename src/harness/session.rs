@@ -0,0 +1,428 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use super::{
+    Error,
+    compare::{self, Mode},
+    group::{RunOptions, run_group},
+    inprocess::{Emitter, InProcessCommand},
+    log::Log,
+    rules::{Rules, SharedRules},
+    testfile::{TestCommand, TestGroup},
+    trace::{self, Trace},
+    wrapper,
+};
+
+/// Name of the group of a session, in its traces and reports.
+const SESSION_GROUP: &str = "session";
+
+/// Sessions started by this process, numbering their snapshot directories.
+static SESSIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Result of a session.
+#[derive(Debug)]
+pub enum SessionOutcome {
+    /// Every check of the commands matched
+    Passed,
+    /// The checks diverged; holds the number of the diverging check, when compared in
+    /// lockstep, and the report of the divergence
+    Diverged {
+        check: Option<usize>,
+        report: String,
+    },
+    /// The commands could not be compared, e.g. one of them failed
+    Failed(Error),
+}
+
+impl SessionOutcome {
+    pub fn passed(&self) -> bool {
+        matches!(self, SessionOutcome::Passed)
+    }
+}
+
+/// Comparison of commands run in-process, without a test file, for fuzzing harnesses and
+/// property-test drivers launching many small differential sessions. Commands are shell
+/// commands or closures, which run without spawning anything.
+///
+/// Commands are compared live in lockstep by default. The other modes record the commands
+/// one at a time and compare the first one against each of the others.
+pub struct SessionBuilder {
+    commands: Vec<TestCommand>,
+    closures: Vec<InProcessCommand>,
+    variables: Vec<(String, String)>,
+    mode: Mode,
+    context: usize,
+    tolerance: f64,
+    snapshot_dir: Option<PathBuf>,
+}
+
+impl Default for SessionBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SessionBuilder {
+    pub fn new() -> Self {
+        Self {
+            commands: Vec::new(),
+            closures: Vec::new(),
+            variables: Vec::new(),
+            mode: Mode::Lockstep,
+            context: 32,
+            tolerance: 0.0,
+            snapshot_dir: None,
+        }
+    }
+
+    /// Adds a shell command to compare, named `name` in the reports.
+    pub fn add_command(mut self, name: &str, command: &str) -> Self {
+        self.commands.push(TestCommand::new(name, command));
+        self
+    }
+
+    /// Adds a closure to compare, named `name` in the reports, emitting its messages
+    /// through the emitter it is given.
+    pub fn add_closure(
+        mut self,
+        name: &str,
+        body: impl Fn(&Emitter) + Send + Sync + 'static,
+    ) -> Self {
+        self.commands.push(TestCommand::new(name, ""));
+        self.closures.push(InProcessCommand::new(name, body));
+        self
+    }
+
+    /// Sets an environment variable of the shell command added last; closures share the
+    /// environment of the process.
+    pub fn env(mut self, name: &str, value: &str) -> Self {
+        let closures = &self.closures;
+        if let Some(command) = self
+            .commands
+            .iter_mut()
+            .rev()
+            .find(|command| !closures.iter().any(|closure| closure.name == command.name))
+        {
+            command.command = format!(
+                "export {}={}; {}",
+                name,
+                wrapper::shell_quote(value),
+                command.command
+            );
+        }
+        self
+    }
+
+    /// Binds a variable substituted for `${NAME}` in every command and exported to them.
+    pub fn variable(mut self, name: &str, value: &str) -> Self {
+        self.variables.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// How the checks of the commands are matched, lockstep by default.
+    pub fn comparison(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Entries shown before every difference in the report.
+    pub fn context(mut self, context: usize) -> Self {
+        self.context = context;
+        self
+    }
+
+    /// Largest difference between two elements of matching arrays or images.
+    pub fn tolerance(mut self, tolerance: f64) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Directory the commands write their snapshots to, kept after the session; a
+    /// temporary one removed afterwards by default.
+    pub fn snapshot_dir(mut self, dir: impl AsRef<Path>) -> Self {
+        self.snapshot_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Runs the commands and compares their checks.
+    pub fn run(self) -> SessionOutcome {
+        if self.commands.len() < 2 {
+            return SessionOutcome::Failed(Error::Config(
+                "a session compares at least two commands".to_string(),
+            ));
+        }
+        let temporary = self.snapshot_dir.is_none();
+        let snapshot_dir = self.snapshot_dir.clone().unwrap_or_else(|| {
+            std::env::temp_dir().join(format!(
+                "runtime-diff-session-{}-{}",
+                std::process::id(),
+                SESSIONS.fetch_add(1, Ordering::Relaxed)
+            ))
+        });
+        let outcome = match self.mode {
+            Mode::Lockstep => self.run_lockstep(&snapshot_dir),
+            mode => self.run_recorded(&snapshot_dir, mode),
+        };
+        if temporary {
+            let _ = std::fs::remove_dir_all(&snapshot_dir);
+        }
+        outcome
+    }
+
+    fn group(&self, commands: Vec<TestCommand>) -> TestGroup {
+        let mut group = TestGroup::new(SESSION_GROUP, commands);
+        for (name, value) in &self.variables {
+            group.set_variable(name, value);
+        }
+        group
+    }
+
+    fn options(&self, snapshot_dir: &Path, record_dir: Option<PathBuf>) -> RunOptions {
+        RunOptions {
+            max_breadcumbs: self.context,
+            last_agreements: 0,
+            snapshot_dir: snapshot_dir.to_path_buf(),
+            fingerprint: false,
+            monitor: None,
+            record_dir,
+            diff_tool: None,
+            diff_tools: Vec::new(),
+            analyzers: Vec::new(),
+            tolerance: self.tolerance,
+            rules: Arc::new(SharedRules::new(Rules::default())),
+            routes: Vec::new(),
+            reference_dir: None,
+            in_process: self.closures.clone(),
+            log_dir: None,
+            debug_rerun: false,
+            break_at: None,
+            divergence_hook: None,
+            wrapper: None,
+            confirm: false,
+            jitter: None,
+            group_output: false,
+            strict_config: false,
+            max_buffer_mem: None,
+            bench: false,
+            thresholds: Vec::new(),
+            check_deadline: None,
+            fail_on_deadline: false,
+            max_payload_bytes: 0,
+            debug_log: None,
+            decision_log: None,
+            stats: None,
+            unstable_sites: None,
+            resume: None,
+            max_lead: None,
+        }
+    }
+
+    fn run_lockstep(&self, snapshot_dir: &Path) -> SessionOutcome {
+        let group = self.group(self.commands.clone());
+        let options = self.options(snapshot_dir, None);
+        match run_group(&group, &options, &Log::quiet()) {
+            Ok(()) => SessionOutcome::Passed,
+            Err(Error::Mismatch { check, checks, .. }) => {
+                let report = checks
+                    .iter()
+                    .map(|(name, check)| {
+                        format!("{}: {}\n", name, check.as_deref().unwrap_or("<ended>"))
+                    })
+                    .collect();
+                SessionOutcome::Diverged {
+                    check: Some(check),
+                    report,
+                }
+            }
+            Err(e) => SessionOutcome::Failed(e),
+        }
+    }
+
+    /// Records every command alone, then compares the trace of the first one against the
+    /// traces of the others.
+    fn run_recorded(&self, snapshot_dir: &Path, mode: Mode) -> SessionOutcome {
+        let record_dir = snapshot_dir.join("traces");
+        let options = self.options(snapshot_dir, Some(record_dir.clone()));
+        let mut traces = Vec::new();
+        for command in &self.commands {
+            let group = self.group(vec![command.clone()]);
+            if let Err(e) = run_group(&group, &options, &Log::quiet()) {
+                return SessionOutcome::Failed(e);
+            }
+            let path = trace::trace_path(&record_dir, &group.label(), &command.name);
+            match Trace::load(&path) {
+                Ok(trace) => traces.push(trace),
+                Err(e) => return SessionOutcome::Failed(e),
+            }
+        }
+
+        let mut report = String::new();
+        let mut matched = true;
+        for (command, trace) in self.commands.iter().zip(&traces).skip(1) {
+            let mut differences = String::new();
+            match compare::compare_traces(&mut differences, [&traces[0], trace], mode, self.context)
+            {
+                Ok(true) => {}
+                Ok(false) => {
+                    matched = false;
+                    report.push_str(&format!(
+                        "{} against {}:\n{}",
+                        self.commands[0].name, command.name, differences
+                    ));
+                }
+                Err(e) => return SessionOutcome::Failed(Error::Config(e.to_string())),
+            }
+        }
+        if matched {
+            SessionOutcome::Passed
+        } else {
+            SessionOutcome::Diverged {
+                check: None,
+                report,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counting(checks: usize) -> impl Fn(&Emitter) + Send + Sync + 'static {
+        move |emitter| {
+            for i in 0..checks {
+                emitter.check(format_args!("x {}", i));
+            }
+        }
+    }
+
+    #[test]
+    fn matching_closures_pass() {
+        let outcome = SessionBuilder::new()
+            .add_closure("a", counting(5))
+            .add_closure("b", counting(5))
+            .run();
+        assert!(outcome.passed(), "{:?}", outcome);
+    }
+
+    #[test]
+    fn diverging_closures_report_the_check() {
+        let outcome = SessionBuilder::new()
+            .add_closure("a", counting(5))
+            .add_closure("b", |emitter| {
+                emitter.check("x 0");
+                emitter.check("x 1");
+                emitter.check("x 7");
+            })
+            .run();
+        match outcome {
+            SessionOutcome::Diverged { check, report } => {
+                assert_eq!(check, Some(3));
+                assert!(report.contains("b: RUNTIME CHECK: x 7"), "{}", report);
+            }
+            outcome => panic!("expected a divergence, got {:?}", outcome),
+        }
+    }
+
+    #[test]
+    fn closures_compare_with_shell_commands() {
+        let outcome = SessionBuilder::new()
+            .add_closure("closure", counting(3))
+            .add_command(
+                "shell",
+                "for i in 0 1 2; do echo \"RUNTIME CHECK: x $i\"; done",
+            )
+            .run();
+        assert!(outcome.passed(), "{:?}", outcome);
+    }
+
+    #[test]
+    fn disabled_checks_are_not_compared() {
+        let outcome = SessionBuilder::new()
+            .add_closure("a", |emitter| {
+                emitter.check("x 0");
+                emitter.disable_checks();
+                emitter.check("legacy 1");
+                emitter.enable_checks();
+                emitter.check("x 1");
+            })
+            .add_closure("b", |emitter| {
+                emitter.check("x 0");
+                emitter.disable_checks();
+                emitter.check("legacy 2");
+                emitter.check("legacy 3");
+                emitter.enable_checks();
+                emitter.check("x 1");
+            })
+            .run();
+        assert!(outcome.passed(), "{:?}", outcome);
+    }
+
+    #[test]
+    fn panicking_closures_fail() {
+        let outcome = SessionBuilder::new()
+            .add_closure("a", |emitter| {
+                emitter.check("x 0");
+                panic!("boom");
+            })
+            .add_closure("b", counting(1))
+            .run();
+        match outcome {
+            SessionOutcome::Failed(Error::Panicked { command, message }) => {
+                assert_eq!(command, "a");
+                assert_eq!(message, "boom");
+            }
+            outcome => panic!("expected a panic, got {:?}", outcome),
+        }
+    }
+
+    #[test]
+    fn closures_without_checks_fail() {
+        let outcome = SessionBuilder::new()
+            .add_closure("a", |emitter| emitter.line("no checks here"))
+            .add_closure("b", counting(1))
+            .run();
+        match outcome {
+            SessionOutcome::Failed(Error::NoInstrumentation { command, output }) => {
+                assert_eq!(command, "a");
+                assert_eq!(output, ["no checks here"]);
+            }
+            outcome => panic!("expected missing instrumentation, got {:?}", outcome),
+        }
+    }
+
+    #[test]
+    fn env_skips_closures() {
+        let outcome = SessionBuilder::new()
+            .add_command("shell", "echo \"RUNTIME CHECK: x $VALUE\"")
+            .add_closure("closure", |emitter| emitter.check("x 1"))
+            .env("VALUE", "1")
+            .run();
+        assert!(outcome.passed(), "{:?}", outcome);
+    }
+
+    #[test]
+    fn keyed_comparison_of_closures() {
+        let outcome = SessionBuilder::new()
+            .add_closure("a", |emitter| {
+                emitter.check("y 1");
+                emitter.check("x 1");
+            })
+            .add_closure("b", |emitter| {
+                emitter.check("x 1");
+                emitter.check("y 2");
+            })
+            .comparison(Mode::Keyed)
+            .run();
+        assert!(
+            matches!(outcome, SessionOutcome::Diverged { check: None, .. }),
+            "{:?}",
+            outcome
+        );
+    }
+}
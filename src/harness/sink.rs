@@ -0,0 +1,346 @@
+use std::{
+    borrow::Cow,
+    ffi::CString,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Read, Seek, SeekFrom},
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, SyncSender},
+    },
+    time::Duration,
+};
+
+use super::Error;
+
+/// File name of the named pipe a command can route its messages to, in its snapshot
+/// directory.
+pub const SINK_FILE: &str = "sink";
+
+/// Creates the named pipe at `path`, replacing the one left by an earlier run.
+pub fn create_fifo(path: &Path) -> Result<(), Error> {
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| Error::Config(format!("invalid named pipe path {}", path.display())))?;
+    // SAFETY: the path is a valid NUL-terminated string
+    if unsafe { libc::mkfifo(c_path.as_ptr(), 0o666) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+/// Lines read ahead of the command reader, beyond which the reads stop and the pipes
+/// hold the command back, so that throttling reaches the command.
+const READ_AHEAD: usize = 16;
+
+/// Time between two reads of a watched file.
+const WATCH_PERIOD: Duration = Duration::from_millis(20);
+
+/// Message prefixes whose lines keep their invalid UTF-8 bytes escaped.
+const ESCAPED_PREFIXES: [&str; 3] = [
+    crate::CHECK_PREFIX,
+    crate::BREADCUMB_PREFIX,
+    crate::BATCH_PREFIX,
+];
+
+/// Text of a line read from a command, without its line ending. Invalid UTF-8 is counted
+/// in `invalid_utf8`: messages keep the invalid bytes escaped by [`escape_message`], so
+/// that checks still compare bytewise, and other output has them replaced.
+fn decode_line(mut bytes: Vec<u8>, invalid_utf8: &AtomicUsize) -> String {
+    if bytes.last() == Some(&b'\n') {
+        bytes.pop();
+        if bytes.last() == Some(&b'\r') {
+            bytes.pop();
+        }
+    }
+    let lossy = String::from_utf8_lossy(&bytes);
+    let valid = matches!(lossy, Cow::Borrowed(_));
+    if !valid {
+        invalid_utf8.fetch_add(1, Ordering::Relaxed);
+    }
+    let start = lossy.trim_start();
+    let message = ESCAPED_PREFIXES
+        .iter()
+        .any(|prefix| start.starts_with(prefix));
+    // Only backslashes before `xNN` change in valid messages
+    if !message || (valid && !lossy.contains("\\x")) {
+        return lossy.into_owned();
+    }
+    escape_message(&bytes)
+}
+
+/// Whether `text` starts like an escaped byte, after its backslash.
+fn is_byte_escape(text: &str) -> bool {
+    let bytes = text.as_bytes();
+    bytes.len() >= 3 && bytes[0] == b'x' && bytes[1..3].iter().all(u8::is_ascii_hexdigit)
+}
+
+/// Escapes the invalid UTF-8 bytes of a message as `\xNN`. The backslashes before an
+/// escaped byte or a literal `xNN` are doubled, so that a program printing `\xff` and
+/// one writing the byte 0xff still differ.
+fn escape_message(bytes: &[u8]) -> String {
+    let mut line = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        let mut rest = chunk.valid();
+        while let Some(start) = rest.find('\\') {
+            line.push_str(&rest[..start]);
+            let after = rest[start..].trim_start_matches('\\');
+            let run = rest.len() - start - after.len();
+            let escaped = match after {
+                "" => !chunk.invalid().is_empty(),
+                after => is_byte_escape(after),
+            };
+            line.push_str(&"\\".repeat(if escaped { 2 * run } else { run }));
+            rest = after;
+        }
+        line.push_str(rest);
+        for byte in chunk.invalid() {
+            line.push_str(&format!("\\x{:02x}", byte));
+        }
+    }
+    line
+}
+
+/// Lines of `reader`, ending at the first read error.
+fn decoded_lines(
+    reader: impl Read,
+    invalid_utf8: Arc<AtomicUsize>,
+) -> impl Iterator<Item = std::io::Result<String>> {
+    let mut reader = BufReader::new(reader);
+    let mut failed = false;
+    std::iter::from_fn(move || {
+        if failed {
+            return None;
+        }
+        let mut line = Vec::new();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => None,
+            Ok(_) => Some(Ok(decode_line(line, &invalid_utf8))),
+            Err(e) => {
+                failed = true;
+                Some(Err(e))
+            }
+        }
+    })
+}
+
+/// Lines of `stdout`, of the named pipe at `sink` and of the `watched` files, in the order
+/// they are read, until `stdout` ends and no process holds the pipe open anymore. Lines
+/// holding invalid UTF-8 are counted in `invalid_utf8`.
+///
+/// The reads stop while [`READ_AHEAD`] lines wait to be taken, so that a caller blocking,
+/// e.g. on `--max-lead`, blocks the command writing them too.
+pub fn merged_lines(
+    stdout: impl Read + Send + 'static,
+    sink: PathBuf,
+    watched: Vec<Tail>,
+    invalid_utf8: Arc<AtomicUsize>,
+) -> impl Iterator<Item = std::io::Result<String>> {
+    let (sender, receiver) = mpsc::sync_channel(READ_AHEAD);
+    let done = Arc::new(AtomicBool::new(false));
+
+    for mut tail in watched {
+        let (sender, done) = (sender.clone(), done.clone());
+        let invalid_utf8 = invalid_utf8.clone();
+        std::thread::spawn(move || {
+            // The last read after stdout ended picks up what the command wrote before
+            loop {
+                let finished = done.load(Ordering::Relaxed);
+                if let Err(e) = tail.read_lines(&sender, finished, &invalid_utf8) {
+                    let _ = sender.send(Err(e));
+                    return;
+                }
+                if finished {
+                    return;
+                }
+                std::thread::sleep(WATCH_PERIOD);
+            }
+        });
+    }
+
+    // The harness holds the pipe open for writing until stdout ends, so that opening it
+    // for reading never blocks and the reader only sees its end once the command closed
+    // it too, however often the command opens and closes it meanwhile
+    let pipe = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&sink)
+        .and_then(|writer| Ok((writer, File::open(&sink)?)));
+    let writer = match pipe {
+        Ok((writer, reader)) => {
+            let sender = sender.clone();
+            let invalid_utf8 = invalid_utf8.clone();
+            std::thread::spawn(move || read_sink(reader, &sender, &invalid_utf8));
+            Some(writer)
+        }
+        Err(e) => {
+            let _ = sender.send(Err(e));
+            None
+        }
+    };
+
+    std::thread::spawn(move || {
+        for line in decoded_lines(stdout, invalid_utf8) {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+        done.store(true, Ordering::Relaxed);
+        drop(writer);
+    });
+
+    receiver.into_iter()
+}
+
+/// Forwards the lines written to the pipe, until every writer closed it.
+fn read_sink(
+    reader: File,
+    sender: &SyncSender<std::io::Result<String>>,
+    invalid_utf8: &Arc<AtomicUsize>,
+) {
+    for line in decoded_lines(reader, invalid_utf8.clone()) {
+        if sender.send(line).is_err() {
+            return;
+        }
+    }
+}
+
+/// File appended to by a command, read from where it ended when the command started, or
+/// from its start once it is truncated or replaced.
+pub struct Tail {
+    path: PathBuf,
+    /// Inode of the file and offset read up to
+    position: Option<(u64, u64)>,
+    partial: Vec<u8>,
+}
+
+impl Tail {
+    /// Starts watching the file at `path`, before the command starts.
+    pub fn new(path: PathBuf) -> Self {
+        let position = std::fs::metadata(&path)
+            .ok()
+            .map(|metadata| (metadata.ino(), metadata.len()));
+        Self {
+            path,
+            position,
+            partial: Vec::new(),
+        }
+    }
+
+    /// Sends the complete lines appended since the last read, and the incomplete last one
+    /// if `last`.
+    fn read_lines(
+        &mut self,
+        sender: &SyncSender<std::io::Result<String>>,
+        last: bool,
+        invalid_utf8: &AtomicUsize,
+    ) -> std::io::Result<()> {
+        let mut file = match File::open(&self.path) {
+            Ok(file) => file,
+            // The command may create the file later
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let metadata = file.metadata()?;
+        let offset = match self.position {
+            Some((inode, offset)) if inode == metadata.ino() && offset <= metadata.len() => offset,
+            _ => {
+                self.partial.clear();
+                0
+            }
+        };
+        file.seek(SeekFrom::Start(offset))?;
+        let read = file.read_to_end(&mut self.partial)?;
+        self.position = Some((metadata.ino(), offset + read as u64));
+
+        let complete = match self.partial.iter().rposition(|byte| *byte == b'\n') {
+            Some(end) => end + 1,
+            None if last && !self.partial.is_empty() => self.partial.len(),
+            None => return Ok(()),
+        };
+        let lines: Vec<u8> = self.partial.drain(..complete).collect();
+        for line in lines.split_inclusive(|byte| *byte == b'\n') {
+            let _ = sender.send(Ok(decode_line(line.to_vec(), invalid_utf8)));
+        }
+        if last && !self.partial.is_empty() {
+            let line = std::mem::take(&mut self.partial);
+            let _ = sender.send(Ok(decode_line(line, invalid_utf8)));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(bytes: &[u8]) -> String {
+        decode_line(bytes.to_vec(), &AtomicUsize::new(0))
+    }
+
+    /// Endless output, counting the reads made from it.
+    struct Endless(Arc<AtomicUsize>);
+
+    impl Read for Endless {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            let line = b"RUNTIME CHECK: x\n";
+            let len = line.len().min(buf.len());
+            buf[..len].copy_from_slice(&line[..len]);
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn reads_stop_while_the_lines_are_not_taken() {
+        let dir = std::env::temp_dir().join(format!("runtime-diff-sink-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let sink = dir.join(SINK_FILE);
+        create_fifo(&sink).unwrap();
+
+        let reads = Arc::new(AtomicUsize::new(0));
+        let mut lines = merged_lines(
+            Endless(reads.clone()),
+            sink,
+            Vec::new(),
+            Arc::new(AtomicUsize::new(0)),
+        );
+        assert_eq!(lines.next().unwrap().unwrap(), "RUNTIME CHECK: x");
+        std::thread::sleep(Duration::from_millis(100));
+        let stalled = reads.load(Ordering::Relaxed);
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(reads.load(Ordering::Relaxed), stalled);
+        assert!(stalled <= READ_AHEAD + 2, "{} reads", stalled);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_bytes_are_escaped_in_messages() {
+        assert_eq!(decode(b"RUNTIME CHECK: x \xff\n"), "RUNTIME CHECK: x \\xff");
+        assert_eq!(decode(b"hi \xff"), "hi \u{fffd}");
+    }
+
+    #[test]
+    fn escapes_differ_from_literal_text() {
+        let lines: [&[u8]; 6] = [
+            b"RUNTIME CHECK: x \xff",
+            b"RUNTIME CHECK: x \\xff",
+            b"RUNTIME CHECK: x \\\xff",
+            b"RUNTIME CHECK: x \\\\xff",
+            b"RUNTIME CHECK: x \\\xff\xfe",
+            b"RUNTIME CHECK: x \\\\xff\xfe",
+        ];
+        let decoded: Vec<_> = lines.iter().map(|line| decode(line)).collect();
+        for (i, line) in decoded.iter().enumerate() {
+            assert!(!decoded[i + 1..].contains(line), "{:?}", decoded);
+        }
+        assert_eq!(
+            decode(b"RUNTIME CHECK: {\"a\": \"\\\"\"}"),
+            "RUNTIME CHECK: {\"a\": \"\\\"\"}"
+        );
+    }
+}
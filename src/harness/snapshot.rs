@@ -0,0 +1,131 @@
+use std::{
+    fmt::{Result, Write},
+    path::{Path, PathBuf},
+};
+
+const HEXDUMP_WIDTH: usize = 16;
+const HEXDUMP_CONTEXT_ROWS: usize = 2;
+
+/// Returns the snapshot id if `check` is a snapshot check line.
+pub fn snapshot_id(check: &str) -> Option<&str> {
+    let mut words = check.strip_prefix(crate::CHECK_PREFIX)?.split_whitespace();
+    if words.next()? != crate::SNAPSHOT_CHECK {
+        return None;
+    }
+    words.next()
+}
+
+/// Writes a byte-level diff of the snapshot files behind mismatching snapshot checks,
+/// using the first snapshot check as the reference.
+pub fn write_snapshot_diffs(
+    out: &mut impl Write,
+    names: &[&str],
+    dirs: &[PathBuf],
+    checks: &[Option<String>],
+) -> Result {
+    let snapshots: Vec<_> = checks
+        .iter()
+        .enumerate()
+        .filter_map(|(i, check)| Some((i, check.as_deref()?, snapshot_id(check.as_deref()?)?)))
+        .collect();
+
+    let Some(&(reference, reference_check, reference_id)) = snapshots.first() else {
+        return Ok(());
+    };
+
+    for &(i, check, id) in &snapshots[1..] {
+        if check == reference_check {
+            continue;
+        }
+        if id != reference_id {
+            writeln!(
+                out,
+                "\x1b[1;34mSnapshot \x1b[1;37m{}\x1b[1;34m of {} differs from snapshot \x1b[1;37m{}\x1b[1;34m of {}, not diffing\x1b[0m",
+                id, names[i], reference_id, names[reference]
+            )?;
+            continue;
+        }
+
+        writeln!(
+            out,
+            "\x1b[1;34mSnapshot \x1b[1;37m{}\x1b[1;34m differs between {} and {}:\x1b[0m",
+            id, names[reference], names[i]
+        )?;
+        diff_files(
+            out,
+            (
+                names[reference],
+                &crate::snapshot_path(&dirs[reference], id),
+            ),
+            (names[i], &crate::snapshot_path(&dirs[i], id)),
+        )?;
+    }
+    Ok(())
+}
+
+fn diff_files(
+    out: &mut impl Write,
+    (a_name, a_path): (&str, &Path),
+    (b_name, b_path): (&str, &Path),
+) -> Result {
+    let (a, b) = match (std::fs::read(a_path), std::fs::read(b_path)) {
+        (Ok(a), Ok(b)) => (a, b),
+        (Err(e), _) | (_, Err(e)) => {
+            return writeln!(out, "  cannot read snapshot files: {}", e);
+        }
+    };
+
+    writeln!(
+        out,
+        "  {}: {} bytes ({})",
+        a_name,
+        a.len(),
+        a_path.display()
+    )?;
+    writeln!(
+        out,
+        "  {}: {} bytes ({})",
+        b_name,
+        b.len(),
+        b_path.display()
+    )?;
+
+    let Some(offset) = (0..a.len().max(b.len())).find(|&i| a.get(i) != b.get(i)) else {
+        return writeln!(out, "  snapshot contents are identical");
+    };
+    writeln!(out, "  first difference at offset {:#x}", offset)?;
+
+    let start = (offset / HEXDUMP_WIDTH).saturating_sub(HEXDUMP_CONTEXT_ROWS) * HEXDUMP_WIDTH;
+    let end = (offset / HEXDUMP_WIDTH + HEXDUMP_CONTEXT_ROWS + 1) * HEXDUMP_WIDTH;
+    for (name, data, other) in [(a_name, &a, &b), (b_name, &b, &a)] {
+        writeln!(out, "  {}:", name)?;
+        for row in (start..end.min(data.len())).step_by(HEXDUMP_WIDTH) {
+            writeln!(out, "    {}", hexdump_row(data, other, row))?;
+        }
+    }
+    Ok(())
+}
+
+fn hexdump_row(data: &[u8], other: &[u8], row: usize) -> String {
+    let mut hex = String::new();
+    let mut ascii = String::new();
+    for i in row..row + HEXDUMP_WIDTH {
+        let Some(&byte) = data.get(i) else {
+            hex.push_str("   ");
+            continue;
+        };
+        let printable = if byte.is_ascii_graphic() || byte == b' ' {
+            byte as char
+        } else {
+            '.'
+        };
+        if other.get(i) != Some(&byte) {
+            hex.push_str(&format!("\x1b[1;31m{:02x}\x1b[0m ", byte));
+            ascii.push_str(&format!("\x1b[1;31m{}\x1b[0m", printable));
+        } else {
+            hex.push_str(&format!("{:02x} ", byte));
+            ascii.push(printable);
+        }
+    }
+    format!("{:08x}  {} |{}|", row, hex, ascii)
+}
@@ -0,0 +1,123 @@
+use std::{collections::BTreeMap, fmt::Write, sync::Mutex};
+
+use super::{channels, trace};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Counts {
+    /// Checks received from all the commands
+    pub checks: u64,
+    pub bytes: u64,
+    /// Compared checks that diverged
+    pub mismatches: u64,
+}
+
+/// Checks received over a run by channel and check name, to find which subsystems
+/// produce the most noise.
+#[derive(Default)]
+pub struct Stats(Mutex<BTreeMap<(String, String), Counts>>);
+
+impl Stats {
+    fn update(&self, channel: &str, name: &str, update: impl FnOnce(&mut Counts)) {
+        let mut stats = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        update(
+            stats
+                .entry((channel.to_string(), name.to_string()))
+                .or_default(),
+        );
+    }
+
+    pub fn record(&self, channel: &str, check: &str) {
+        self.update(channel, check_name(check), |counts| {
+            counts.checks += 1;
+            counts.bytes += check.len() as u64;
+        });
+    }
+
+    /// Records a divergence of the aligned `checks`, once for each of their names.
+    pub fn record_mismatch(&self, channel: &str, checks: &[Option<String>]) {
+        let mut names: Vec<_> = checks
+            .iter()
+            .flatten()
+            .map(|check| check_name(check))
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+        for name in names {
+            self.update(channel, name, |counts| counts.mismatches += 1);
+        }
+    }
+
+    /// Counts by channel and check name, the most frequent names first.
+    pub fn by_name(&self) -> Vec<(String, String, Counts)> {
+        let stats = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let mut by_name: Vec<_> = stats
+            .iter()
+            .map(|((channel, name), counts)| (channel.clone(), name.clone(), *counts))
+            .collect();
+        by_name.sort_by_key(|(_, _, counts)| std::cmp::Reverse(counts.checks));
+        by_name
+    }
+}
+
+fn check_name(check: &str) -> &str {
+    let payload = check.strip_prefix(crate::CHECK_PREFIX).unwrap_or(check);
+    trace::check_name(payload.trim_start())
+}
+
+fn channel_label(channel: &str) -> &str {
+    if channel == channels::DEFAULT_CHANNEL {
+        "(default)"
+    } else {
+        channel
+    }
+}
+
+fn write_row(out: &mut impl Write, label: &str, counts: &Counts) -> std::fmt::Result {
+    let mismatches = if counts.mismatches > 0 {
+        format!("\x1b[1;31m{:>10}\x1b[0m", counts.mismatches)
+    } else {
+        format!("{:>10}", counts.mismatches)
+    };
+    writeln!(
+        out,
+        "  {:<32} {:>10} {:>12} {}",
+        label, counts.checks, counts.bytes, mismatches
+    )
+}
+
+/// Writes the counts of the run by channel, then by check name.
+pub fn write_stats(out: &mut impl Write, stats: &Stats) -> std::fmt::Result {
+    let by_name = stats.by_name();
+    let mut by_channel: BTreeMap<&str, Counts> = BTreeMap::new();
+    for (channel, _, counts) in &by_name {
+        let total = by_channel.entry(channel.as_str()).or_default();
+        total.checks += counts.checks;
+        total.bytes += counts.bytes;
+        total.mismatches += counts.mismatches;
+    }
+
+    writeln!(out, "\x1b[1;34mChecks by channel:\x1b[0m")?;
+    writeln!(
+        out,
+        "  {:<32} {:>10} {:>12} {:>10}",
+        "channel", "checks", "bytes", "mismatches"
+    )?;
+    for (channel, counts) in &by_channel {
+        write_row(out, channel_label(channel), counts)?;
+    }
+    writeln!(out, "\x1b[1;34mChecks by name:\x1b[0m")?;
+    writeln!(
+        out,
+        "  {:<32} {:>10} {:>12} {:>10}",
+        "name", "checks", "bytes", "mismatches"
+    )?;
+    for (channel, name, counts) in &by_name {
+        let label = if by_channel.len() > 1 {
+            format!("{} ({})", name, channel_label(channel))
+        } else {
+            name.clone()
+        };
+        write_row(out, &label, counts)?;
+    }
+    Ok(())
+}
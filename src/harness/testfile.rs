@@ -0,0 +1,910 @@
+use std::time::Duration;
+
+use regex::Regex;
+
+use super::{Error, extract::Extraction, sandbox::Sandbox};
+
+/// Name of the group holding the commands of a plain `test:` section.
+pub const DEFAULT_GROUP: &str = "test";
+/// Key of the group line listing the groups that must pass before the group runs.
+pub const DEPENDS_ON_KEY: &str = "depends_on";
+/// Key of the group line making the exit status of the commands a last compared check.
+pub const COMPARE_EXIT_STATUS_KEY: &str = "compare_exit_status";
+/// Key of the group lines pinning the value of a named check, as `expect: name = value`.
+pub const EXPECT_KEY: &str = "expect";
+/// Key of the group line giving a command run with the path of the group report once its
+/// commands exit, failing the group if it fails.
+pub const VERIFY_KEY: &str = "verify";
+/// Key of the group line reporting the divergences of the group without failing the run,
+/// while its flakiness is investigated.
+pub const QUARANTINE_KEY: &str = "quarantine";
+/// Placeholder of a command template replaced by the arguments of each variant, which are
+/// appended to the command without it.
+pub const VARIANT_ARGS: &str = "{args}";
+
+#[derive(Debug, Clone)]
+pub struct TestCommand {
+    pub name: String,
+    pub command: String,
+    /// Number of times the command is run in its group
+    pub instances: usize,
+    /// Line of the test file declaring the command, 0 if it was not read from a file
+    pub line: usize,
+    /// Directory the command writes its output files to, compared across the group once
+    /// the commands exit
+    pub output_dir: Option<String>,
+    /// Have the command zstd compress its messages, for slow transports such as SSH
+    pub compress: bool,
+    /// User the command runs as, through sudo
+    pub user: Option<String>,
+    /// File mode creation mask of the command
+    pub umask: Option<u32>,
+    /// Run the command in a network namespace of its own, so that it is offline; not with
+    /// `user`, as sudo cannot switch users in that namespace
+    pub isolate_network: bool,
+    /// Address of the agent running the command, on a machine of its own
+    pub host: Option<String>,
+    /// Files the command writes messages to besides stdout, such as an application log,
+    /// tailed during the run
+    pub watch: Vec<String>,
+    /// The command is a wasm module and its arguments, run under wasmtime, which must be
+    /// on the PATH. Modules are built for `wasm32-wasip1` with the library's default
+    /// features disabled, and emit their messages on stdout
+    pub wasm: bool,
+    /// Filesystem paths and syscalls the command is restricted to, for untrusted or
+    /// experimental implementations
+    pub sandbox: Option<Sandbox>,
+    /// Time the harness waits before starting the command
+    pub start_delay: Option<Duration>,
+    /// Argument sets by variant name, making the command a template expanded into one
+    /// command `name-variant` per variant
+    pub variants: Vec<(String, String)>,
+}
+
+impl TestCommand {
+    pub fn new(name: &str, command: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            command: command.to_string(),
+            instances: 1,
+            line: 0,
+            output_dir: None,
+            compress: false,
+            user: None,
+            umask: None,
+            isolate_network: false,
+            host: None,
+            watch: Vec::new(),
+            wasm: false,
+            sandbox: None,
+            start_delay: None,
+            variants: Vec::new(),
+        }
+    }
+
+    /// Sets an option given on an indented line below the command.
+    fn set_option(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "instances" => {
+                self.instances = value
+                    .parse()
+                    .ok()
+                    .filter(|instances| *instances > 0)
+                    .ok_or_else(|| format!("invalid instance count '{}'", value))?;
+            }
+            "output_dir" => self.output_dir = Some(value.to_string()),
+            "compress" => {
+                self.compress = match value {
+                    "zstd" if cfg!(feature = "zstd") => true,
+                    "zstd" => return Err("runtime-diff was built without zstd support".to_string()),
+                    "none" => false,
+                    _ => return Err(format!("unknown compression '{}'", value)),
+                };
+            }
+            "user" => {
+                let valid = !value.is_empty()
+                    && value
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+                if !valid {
+                    return Err(format!("invalid user name '{}'", value));
+                }
+                self.user = Some(value.to_string());
+            }
+            "umask" => {
+                self.umask = Some(
+                    u32::from_str_radix(value, 8)
+                        .ok()
+                        .filter(|umask| *umask <= 0o777)
+                        .ok_or_else(|| format!("invalid umask '{}'", value))?,
+                );
+            }
+            "isolate_network" => {
+                self.isolate_network = value
+                    .parse()
+                    .map_err(|_| format!("expected true or false, got '{}'", value))?;
+            }
+            "wasm" => {
+                self.wasm = value
+                    .parse()
+                    .map_err(|_| format!("expected true or false, got '{}'", value))?;
+            }
+            "sandbox" => {
+                if !cfg!(feature = "sandbox") {
+                    return Err("runtime-diff was built without sandbox support".to_string());
+                }
+                self.sandbox = Some(value.parse()?);
+            }
+            "start_delay" => {
+                self.start_delay = Some(parse_delay(value).map_err(|e| e.to_string())?)
+            }
+            "variants" => {
+                for variant in value
+                    .split(';')
+                    .filter(|variant| !variant.trim().is_empty())
+                {
+                    let (name, args) = variant
+                        .split_once('=')
+                        .map(|(name, args)| (name.trim(), args.trim()))
+                        .filter(|(name, _)| !name.is_empty())
+                        .ok_or_else(|| {
+                            format!("expected 'name = arguments', got '{}'", variant.trim())
+                        })?;
+                    if self.variants.iter().any(|(other, _)| other == name) {
+                        return Err(format!("variant '{}' is given twice", name));
+                    }
+                    self.variants.push((name.to_string(), args.to_string()));
+                }
+            }
+            "watch" => self.watch.extend(
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|path| !path.is_empty())
+                    .map(str::to_string),
+            ),
+            "host" => {
+                if !value.contains(':') {
+                    return Err(format!("expected 'host:port', got '{}'", value));
+                }
+                self.host = Some(value.to_string());
+            }
+            _ => return Err(format!("unknown command option '{}'", key)),
+        }
+        if self.isolate_network && self.user.is_some() {
+            // sudo cannot switch users in the user namespace isolating the network
+            return Err("user and isolate_network cannot be combined".to_string());
+        }
+        Ok(())
+    }
+
+    /// The commands a template expands into, one per variant with its arguments in place of
+    /// the `{args}` placeholder, or the command itself if it has no variants.
+    fn expand_variants(self) -> Vec<TestCommand> {
+        if self.variants.is_empty() {
+            return vec![self];
+        }
+        self.variants
+            .iter()
+            .map(|(variant, args)| {
+                let command = if self.command.contains(VARIANT_ARGS) {
+                    self.command.replace(VARIANT_ARGS, args)
+                } else {
+                    format!("{} {}", self.command, args)
+                };
+                TestCommand {
+                    name: format!("{}-{}", self.name, variant),
+                    command: command.trim().to_string(),
+                    variants: Vec::new(),
+                    ..self.clone()
+                }
+            })
+            .collect()
+    }
+
+    /// Shell script running the command with its umask, if set.
+    pub fn script(&self) -> String {
+        let command = if self.wasm {
+            // The module sees the protocol variables and writes its snapshots as a native
+            // command would
+            format!(
+                "wasmtime run -S inherit-env --dir . --dir \"${}\" {}",
+                crate::SNAPSHOT_DIR_ENV,
+                self.command
+            )
+        } else {
+            self.command.clone()
+        };
+        match self.umask {
+            Some(umask) => format!("umask {:03o}; {}", umask, command),
+            None => command,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TestGroup {
+    pub name: String,
+    pub commands: Vec<TestCommand>,
+    /// Values substituted for `${NAME}` in the commands and exported to their environment
+    pub variables: Vec<(String, String)>,
+    /// Names of the groups that must pass before this one runs
+    pub depends_on: Vec<String>,
+    /// Values every command must give the checks of these names
+    pub expectations: Vec<(String, String)>,
+    /// Compare the exit statuses of the commands as a last check instead of failing on
+    /// unsuccessful ones
+    pub compare_exit_status: bool,
+    /// Command validating the results of the group, given the path of its report
+    pub verify: Option<String>,
+    /// Report the failures of the group without affecting the exit status
+    pub quarantine: bool,
+}
+
+impl TestGroup {
+    pub fn new(name: &str, commands: Vec<TestCommand>) -> Self {
+        Self {
+            name: name.to_string(),
+            commands,
+            variables: Vec::new(),
+            depends_on: Vec::new(),
+            expectations: Vec::new(),
+            compare_exit_status: false,
+            verify: None,
+            quarantine: false,
+        }
+    }
+
+    /// Returns a copy of the group binding `variable` to `value`.
+    pub fn with_variable(&self, variable: &str, value: &str) -> Self {
+        let mut group = self.clone();
+        group.set_variable(variable, value);
+        group
+    }
+
+    pub fn set_variable(&mut self, variable: &str, value: &str) {
+        match self.variables.iter_mut().find(|(name, _)| name == variable) {
+            Some((_, current)) => *current = value.to_string(),
+            None => self
+                .variables
+                .push((variable.to_string(), value.to_string())),
+        }
+    }
+
+    /// Name of the group followed by the variables bound for this run of it.
+    pub fn label(&self) -> String {
+        let mut label = self.name.clone();
+        for (variable, value) in &self.variables {
+            label.push_str(&format!(" {}={}", variable, value));
+        }
+        label
+    }
+
+    pub fn variable(&self, variable: &str) -> Option<&str> {
+        self.variables
+            .iter()
+            .find(|(name, _)| name == variable)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns true if this group waits for `other`, a run of a group it depends on
+    /// binding the variables they share to the same values.
+    pub fn depends_on_group(&self, other: &TestGroup) -> bool {
+        self.depends_on.contains(&other.name)
+            && other.variables.iter().all(|(variable, value)| {
+                self.variable(variable)
+                    .is_none_or(|own_value| own_value == value)
+            })
+    }
+
+    /// Value pinned by an `expect` line for the check `name`, if any.
+    pub fn expected_value(&self, name: &str) -> Option<&str> {
+        self.expectations
+            .iter()
+            .find(|(check, _)| check == name)
+            .map(|(_, expected)| expected.as_str())
+    }
+
+    /// Returns `command` with the variables of the group substituted.
+    pub fn substitute(&self, command: &str) -> String {
+        let mut command = command.to_string();
+        for (variable, value) in &self.variables {
+            command = command.replace(&format!("${{{}}}", variable), value);
+        }
+        command
+    }
+
+    /// Pairs of declared commands running the same command line, which is usually a
+    /// copy-paste mistake making the comparison meaningless.
+    pub fn identical_commands(&self) -> Vec<(&TestCommand, &TestCommand)> {
+        let mut pairs = Vec::new();
+        for (i, first) in self.commands.iter().enumerate() {
+            for second in &self.commands[i + 1..] {
+                if self.substitute(&first.command).trim() == self.substitute(&second.command).trim()
+                    && first.user == second.user
+                    && first.umask == second.umask
+                    && first.isolate_network == second.isolate_network
+                    && first.host == second.host
+                    && first.wasm == second.wasm
+                    && first.sandbox == second.sandbox
+                {
+                    pairs.push((first, second));
+                }
+            }
+        }
+        pairs
+    }
+
+    /// Returns the commands to run, with variables substituted and a copy named `name#i`
+    /// for each instance of commands declaring more than one.
+    pub fn resolve_commands(&self) -> Vec<TestCommand> {
+        let mut commands = Vec::new();
+        for command in &self.commands {
+            let mut command = command.clone();
+            command.command = self.substitute(&command.command);
+            command.output_dir = command.output_dir.map(|dir| self.substitute(&dir));
+            command.watch = command
+                .watch
+                .iter()
+                .map(|path| self.substitute(path))
+                .collect();
+
+            if command.instances == 1 {
+                commands.push(command);
+                continue;
+            }
+            for instance in 1..=command.instances {
+                commands.push(TestCommand {
+                    name: format!("{}#{}", command.name, instance),
+                    instances: 1,
+                    ..command.clone()
+                });
+            }
+        }
+        commands
+    }
+}
+
+/// Settings of the `verbosity` section.
+#[derive(Debug, Clone)]
+pub struct Verbosity {
+    /// Breadcrumbs shown before a mismatch, at most as many as kept by the harness
+    pub breadcumbs: Option<usize>,
+    /// Print what the commands write besides their messages
+    pub echo_output: bool,
+}
+
+impl Default for Verbosity {
+    fn default() -> Self {
+        Self {
+            breadcumbs: None,
+            echo_output: true,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TestFile {
+    pub build_commands: Vec<String>,
+    pub groups: Vec<TestGroup>,
+    /// Input files and directories of the corpus the groups are run over
+    pub inputs: Vec<String>,
+    /// Commands rendering the payloads of diverging checks, by check name
+    pub diff_tools: Vec<(String, String)>,
+    /// Commands given the matched checks and divergences as JSON lines, printing verdicts
+    /// back, by name
+    pub analyzers: Vec<(String, String)>,
+    /// Per-element tolerance of arrays and images, by the name they are recorded with
+    pub tolerances: Vec<(String, f64)>,
+    /// Largest time ratio to the first command allowed before every checkpoint, by check
+    /// name, `*` applying to all others
+    pub thresholds: Vec<(String, f64)>,
+    /// Rules routing the checks matching a pattern to a channel, in order of precedence
+    pub routes: Vec<(String, Regex)>,
+    /// Rules extracting the compared portion of the checks matching a pattern, in order
+    /// of precedence
+    pub extractions: Vec<Extraction>,
+    /// Patterns of the checks that are not compared
+    pub ignores: Vec<Regex>,
+    pub verbosity: Verbosity,
+    /// Every section header with its line, including sections the harness ignores
+    pub sections: Vec<(usize, String)>,
+}
+
+impl TestFile {
+    /// Runs the commands of the `build` section as a single bash script.
+    pub fn build(&self) -> Result<(), Error> {
+        let script = self.build_commands.join("\n");
+        let status = std::process::Command::new("bash")
+            .arg("-c")
+            .arg(&script)
+            .status()
+            .map_err(|source| Error::Spawn {
+                command: "build".to_string(),
+                source,
+            })?;
+        if !status.success() {
+            return Err(Error::CommandFailed {
+                command: "build".to_string(),
+                status,
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns the first test command named `name` in any group.
+    pub fn find_command(&self, name: &str) -> Option<&TestCommand> {
+        self.groups
+            .iter()
+            .flat_map(|group| &group.commands)
+            .find(|command| command.name == name)
+    }
+
+    fn group_mut(&mut self, name: &str) -> &mut TestGroup {
+        match self.groups.iter().position(|group| group.name == name) {
+            Some(index) => &mut self.groups[index],
+            None => {
+                self.groups.push(TestGroup::new(name, Vec::new()));
+                self.groups.last_mut().unwrap()
+            }
+        }
+    }
+}
+
+/// Checks that every dependency names a declared group and that no group depends on itself,
+/// directly or through other groups.
+pub fn check_dependencies(groups: &[TestGroup]) -> Result<(), Error> {
+    fn visit<'a>(
+        group: &'a TestGroup,
+        groups: &'a [TestGroup],
+        path: &mut Vec<&'a str>,
+        done: &mut Vec<&'a str>,
+    ) -> Result<(), Error> {
+        if done.contains(&group.name.as_str()) {
+            return Ok(());
+        }
+        if let Some(start) = path.iter().position(|name| *name == group.name) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(&group.name);
+            return Err(Error::Config(format!(
+                "groups depend on each other: {}",
+                cycle.join(" -> ")
+            )));
+        }
+
+        path.push(&group.name);
+        for dependency in &group.depends_on {
+            let Some(dependency) = groups.iter().find(|other| &other.name == dependency) else {
+                return Err(Error::Config(format!(
+                    "group '{}' depends on unknown group '{}'",
+                    group.name, dependency
+                )));
+            };
+            visit(dependency, groups, path, done)?;
+        }
+        path.pop();
+        done.push(&group.name);
+        Ok(())
+    }
+
+    let mut done = Vec::new();
+    for group in groups {
+        visit(group, groups, &mut Vec::new(), &mut done)?;
+    }
+    Ok(())
+}
+
+/// Returns the comparison group declared by a section header, either `test` or `test <name>`.
+fn group_name(section: &str) -> Option<&str> {
+    match section.split_once(char::is_whitespace) {
+        None if section == DEFAULT_GROUP => Some(DEFAULT_GROUP),
+        Some((DEFAULT_GROUP, name)) => Some(name.trim()),
+        _ => None,
+    }
+}
+
+/// Parses a duration given in seconds, with an optional ms, s or m suffix.
+pub fn parse_duration(s: &str) -> Result<Duration, Error> {
+    parse_seconds(s, false)
+}
+
+/// Parses a delay, a duration as [`parse_duration`] takes it or 0 for none, e.g. to cancel
+/// the delay a variant inherits.
+fn parse_delay(s: &str) -> Result<Duration, Error> {
+    parse_seconds(s, true)
+}
+
+/// Parses a duration given in seconds, with an optional ms, s or m suffix, accepting zero
+/// if `zero`.
+fn parse_seconds(s: &str, zero: bool) -> Result<Duration, Error> {
+    let s = s.trim();
+    let (number, unit) = if let Some(number) = s.strip_suffix("ms") {
+        (number, 0.001)
+    } else if let Some(number) = s.strip_suffix('s') {
+        (number, 1.0)
+    } else if let Some(number) = s.strip_suffix('m') {
+        (number, 60.0)
+    } else {
+        (s, 1.0)
+    };
+    number
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|number| number.is_finite() && (*number > 0.0 || zero && *number == 0.0))
+        .map(|number| Duration::from_secs_f64(number * unit))
+        .ok_or_else(|| Error::Config(format!("invalid duration '{}'", s)))
+}
+
+/// Returns true for the sections the harness reads.
+pub fn is_known_section(section: &str) -> bool {
+    matches!(
+        section,
+        "build"
+            | "inputs"
+            | "diff_tools"
+            | "analyzers"
+            | "tolerances"
+            | "routes"
+            | "thresholds"
+            | "extract"
+            | "ignore"
+            | "verbosity"
+    ) || group_name(section).is_some()
+}
+
+fn indentation(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+pub fn load_test_file(filename: &str) -> Result<TestFile, Error> {
+    let content = std::fs::read_to_string(filename)?;
+
+    let mut test_file = TestFile {
+        build_commands: Vec::new(),
+        groups: Vec::new(),
+        inputs: Vec::new(),
+        diff_tools: Vec::new(),
+        analyzers: Vec::new(),
+        tolerances: Vec::new(),
+        routes: Vec::new(),
+        thresholds: Vec::new(),
+        extractions: Vec::new(),
+        ignores: Vec::new(),
+        verbosity: Verbosity::default(),
+        sections: Vec::new(),
+    };
+
+    let mut current_section = "";
+    // Indentation of the last test command, lines indented deeper are its options
+    let mut command_indentation = None;
+
+    for (line_number, line) in content.lines().enumerate() {
+        let trimmed = line.trim();
+
+        // Skip empty lines and comments
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        // Check for section headers
+        if trimmed.ends_with(':') {
+            current_section = trimmed.trim_end_matches(':');
+            command_indentation = None;
+            test_file
+                .sections
+                .push((line_number + 1, current_section.to_string()));
+            continue;
+        }
+
+        // Parse content based on current section
+        if current_section == "build" {
+            test_file.build_commands.push(trimmed.to_string());
+        } else if current_section == "inputs" {
+            test_file.inputs.push(trimmed.to_string());
+        } else if current_section == "diff_tools" {
+            let (name, tool) = trimmed.split_once(':').ok_or_else(|| Error::Parse {
+                line: line_number + 1,
+                message: format!("expected 'check name: command', got '{}'", trimmed),
+            })?;
+            test_file
+                .diff_tools
+                .push((name.trim().to_string(), tool.trim().to_string()));
+        } else if current_section == "analyzers" {
+            let (name, command) = trimmed.split_once(':').ok_or_else(|| Error::Parse {
+                line: line_number + 1,
+                message: format!("expected 'name: command', got '{}'", trimmed),
+            })?;
+            test_file
+                .analyzers
+                .push((name.trim().to_string(), command.trim().to_string()));
+        } else if current_section == "tolerances" {
+            let tolerance = trimmed
+                .split_once(':')
+                .and_then(|(name, tolerance)| Some((name.trim(), tolerance.trim().parse().ok()?)))
+                .filter(|(_, tolerance): &(_, f64)| *tolerance >= 0.0)
+                .ok_or_else(|| Error::Parse {
+                    line: line_number + 1,
+                    message: format!("expected 'name: tolerance', got '{}'", trimmed),
+                })?;
+            test_file
+                .tolerances
+                .push((tolerance.0.to_string(), tolerance.1));
+        } else if current_section == "thresholds" {
+            let threshold = trimmed
+                .split_once(':')
+                .and_then(|(name, ratio)| Some((name.trim(), ratio.trim().parse().ok()?)))
+                .filter(|(_, ratio): &(_, f64)| *ratio > 0.0)
+                .ok_or_else(|| Error::Parse {
+                    line: line_number + 1,
+                    message: format!("expected 'check name: ratio', got '{}'", trimmed),
+                })?;
+            test_file
+                .thresholds
+                .push((threshold.0.to_string(), threshold.1));
+        } else if current_section == "extract" {
+            let extraction = trimmed.parse().map_err(|message| Error::Parse {
+                line: line_number + 1,
+                message,
+            })?;
+            test_file.extractions.push(extraction);
+        } else if current_section == "ignore" {
+            let pattern = Regex::new(trimmed).map_err(|e| Error::Parse {
+                line: line_number + 1,
+                message: format!("invalid ignore pattern: {}", e),
+            })?;
+            test_file.ignores.push(pattern);
+        } else if current_section == "verbosity" {
+            let (key, value) = trimmed
+                .split_once(':')
+                .map(|(key, value)| (key.trim(), value.trim()))
+                .unwrap_or((trimmed, ""));
+            let invalid = |expected: &str| Error::Parse {
+                line: line_number + 1,
+                message: format!("expected {} for '{}', got '{}'", expected, key, value),
+            };
+            match key {
+                "breadcumbs" => {
+                    test_file.verbosity.breadcumbs =
+                        Some(value.parse().map_err(|_| invalid("a count"))?);
+                }
+                "echo_output" => {
+                    test_file.verbosity.echo_output =
+                        value.parse().map_err(|_| invalid("true or false"))?;
+                }
+                _ => {
+                    return Err(Error::Parse {
+                        line: line_number + 1,
+                        message: format!("unknown verbosity setting '{}'", key),
+                    });
+                }
+            }
+        } else if current_section == "routes" {
+            let route = trimmed
+                .split_once(':')
+                .map(|(channel, pattern)| (channel.trim(), pattern.trim()))
+                .filter(|(channel, _)| !channel.is_empty())
+                .ok_or_else(|| format!("expected 'channel: pattern', got '{}'", trimmed))
+                .and_then(|(channel, pattern)| {
+                    let pattern = Regex::new(pattern)
+                        .map_err(|e| format!("invalid pattern for channel '{}': {}", channel, e))?;
+                    Ok((channel.to_string(), pattern))
+                })
+                .map_err(|message| Error::Parse {
+                    line: line_number + 1,
+                    message,
+                })?;
+            test_file.routes.push(route);
+        } else if let Some(group) = group_name(current_section)
+            && let Some((key, value)) = trimmed.split_once(':')
+        {
+            let (key, value) = (key.trim(), value.trim());
+            let group = test_file.group_mut(group);
+            match command_indentation {
+                Some(indentation_level) if indentation(line) > indentation_level => {
+                    let command = group.commands.last_mut().unwrap();
+                    command
+                        .set_option(key, value)
+                        .map_err(|message| Error::Parse {
+                            line: line_number + 1,
+                            message,
+                        })?;
+                }
+                _ if key == DEPENDS_ON_KEY => {
+                    group.depends_on.extend(
+                        value
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|name| !name.is_empty())
+                            .map(str::to_string),
+                    );
+                }
+                _ if key == COMPARE_EXIT_STATUS_KEY => {
+                    group.compare_exit_status = value.parse().map_err(|_| Error::Parse {
+                        line: line_number + 1,
+                        message: format!("expected true or false, got '{}'", value),
+                    })?;
+                }
+                _ if key == VERIFY_KEY => group.verify = Some(value.to_string()),
+                _ if key == QUARANTINE_KEY => {
+                    group.quarantine = value.parse().map_err(|_| Error::Parse {
+                        line: line_number + 1,
+                        message: format!("expected true or false, got '{}'", value),
+                    })?;
+                }
+                _ if key == EXPECT_KEY => {
+                    let (name, expected) = value
+                        .split_once('=')
+                        .map(|(name, expected)| (name.trim(), expected.trim()))
+                        .filter(|(name, _)| !name.is_empty())
+                        .ok_or_else(|| Error::Parse {
+                            line: line_number + 1,
+                            message: format!("expected 'expect: name = value', got '{}'", trimmed),
+                        })?;
+                    group
+                        .expectations
+                        .push((name.to_string(), expected.to_string()));
+                }
+                _ => {
+                    group.commands.push(TestCommand {
+                        line: line_number + 1,
+                        ..TestCommand::new(key, value)
+                    });
+                    command_indentation = Some(indentation(line));
+                }
+            }
+        }
+        // Ignore unknown sections
+    }
+
+    for group in &mut test_file.groups {
+        let commands = std::mem::take(&mut group.commands);
+        group.commands = commands
+            .into_iter()
+            .flat_map(TestCommand::expand_variants)
+            .collect();
+    }
+    check_dependencies(&test_file.groups)?;
+    Ok(test_file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(name: &str, content: &str) -> Result<TestFile, Error> {
+        let path = std::env::temp_dir().join(format!(
+            "runtime-diff-testfile-{}-{}.run",
+            name,
+            std::process::id()
+        ));
+        std::fs::write(&path, content).unwrap();
+        let test_file = load_test_file(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        test_file
+    }
+
+    #[test]
+    fn commands_take_the_options_indented_below_them() {
+        let test_file = load(
+            "options",
+            "build:
+    cargo build
+# a comment
+test fast:
+    depends_on: test
+    expect: total = 3
+    a: ./a ${N}
+        instances: 2
+        umask: 027
+    b: ./b
+test:
+    c: ./c {args} --quiet
+        variants: small = -n 1; large = -n 9
+unknown:
+    ignored: true
+",
+        )
+        .unwrap();
+        assert_eq!(test_file.build_commands, ["cargo build"]);
+        let names: Vec<_> = test_file.groups.iter().map(|group| &group.name).collect();
+        assert_eq!(names, ["fast", DEFAULT_GROUP]);
+
+        let fast = &test_file.groups[0];
+        assert_eq!(fast.depends_on, [DEFAULT_GROUP]);
+        assert_eq!(fast.expected_value("total"), Some("3"));
+        let a = test_file.find_command("a").unwrap();
+        assert_eq!((a.line, a.instances, a.umask), (7, 2, Some(0o027)));
+        assert_eq!(test_file.find_command("b").unwrap().umask, None);
+        let resolved: Vec<_> = fast
+            .with_variable("N", "5")
+            .resolve_commands()
+            .into_iter()
+            .map(|command| (command.name, command.command))
+            .collect();
+        assert_eq!(
+            resolved,
+            [
+                ("a#1".to_string(), "./a 5".to_string()),
+                ("a#2".to_string(), "./a 5".to_string()),
+                ("b".to_string(), "./b".to_string()),
+            ]
+        );
+
+        let variants: Vec<_> = test_file.groups[1]
+            .commands
+            .iter()
+            .map(|command| (command.name.as_str(), command.command.as_str()))
+            .collect();
+        assert_eq!(
+            variants,
+            [
+                ("c-small", "./c -n 1 --quiet"),
+                ("c-large", "./c -n 9 --quiet")
+            ]
+        );
+        assert_eq!(test_file.sections.len(), 4);
+    }
+
+    #[test]
+    fn parse_errors_give_their_line() {
+        let cases = [
+            ("test:\n    a: ./a\n        instances: 0\n", 3),
+            ("test:\n    a: ./a\n        colour: blue\n", 3),
+            ("test:\n    expect: = 3\n", 2),
+            ("tolerances:\n    weights: -1\n", 2),
+            ("thresholds:\n    *: 0\n", 2),
+            ("routes:\n    net: (\n", 2),
+            ("extract:\n    value\n", 2),
+            ("verbosity:\n    breadcumbs: many\n", 2),
+        ];
+        for (content, expected) in cases {
+            match load("errors", content) {
+                Err(Error::Parse { line, .. }) => assert_eq!(line, expected, "{}", content),
+                _ => panic!("'{}' is not a parse error", content),
+            }
+        }
+    }
+
+    #[test]
+    fn dependencies_must_not_form_cycles() {
+        let cycle = "test a:\n    depends_on: b\ntest b:\n    depends_on: a\n";
+        let error = load("cycle", cycle).unwrap_err().to_string();
+        assert!(error.contains("a -> b -> a"), "{}", error);
+        let unknown = "test a:\n    depends_on: c\n";
+        assert!(load("unknown", unknown).is_err());
+    }
+
+    #[test]
+    fn durations_take_a_unit() {
+        assert_eq!(parse_duration("1.5").unwrap(), Duration::from_millis(1500));
+        assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+        assert_eq!(parse_duration(" 2 s ").unwrap(), Duration::from_secs(2));
+        assert_eq!(parse_duration("1m").unwrap(), Duration::from_secs(60));
+        for invalid in ["", "0", "-1", "inf", "1h", "ms"] {
+            assert!(parse_duration(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn start_delays_may_be_zero() {
+        let mut command = TestCommand::new("a", "true");
+        command.set_option("start_delay", "0").unwrap();
+        assert_eq!(command.start_delay, Some(Duration::ZERO));
+        command.set_option("start_delay", "0ms").unwrap();
+        assert_eq!(command.start_delay, Some(Duration::ZERO));
+        assert!(command.set_option("start_delay", "-1").is_err());
+    }
+
+    #[test]
+    fn network_isolation_excludes_switching_users() {
+        let mut command = TestCommand::new("a", "true");
+        command.set_option("user", "nobody").unwrap();
+        assert!(command.set_option("isolate_network", "true").is_err());
+
+        let mut command = TestCommand::new("a", "true");
+        command.set_option("isolate_network", "true").unwrap();
+        assert!(command.set_option("user", "nobody").is_err());
+        assert!(command.set_option("isolate_network", "false").is_ok());
+    }
+}
@@ -0,0 +1,58 @@
+use std::sync::{Arc, Condvar, Mutex};
+
+/// Bounds how many checks a command reader may queue ahead of the comparison, so that a
+/// fast command blocks on its pipe instead of buffering its whole output.
+pub struct Throttle {
+    max_lead: isize,
+    /// Checks sent but not yet compared, and whether the comparison is over; the
+    /// comparison may take a check before its reader counts it, going negative
+    state: Mutex<(isize, bool)>,
+    changed: Condvar,
+}
+
+impl Throttle {
+    pub fn new(max_lead: usize) -> Self {
+        Self {
+            max_lead: max_lead.try_into().unwrap_or(isize::MAX),
+            state: Mutex::new((0, false)),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Called by the reader after sending a check, blocks while it is too far ahead.
+    /// Returns true if it blocked.
+    pub fn sent(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.0 += 1;
+        let blocked = state.0 > self.max_lead && !state.1;
+        while state.0 > self.max_lead && !state.1 {
+            state = self.changed.wait(state).unwrap();
+        }
+        blocked
+    }
+
+    /// Called by the comparison after taking a check from the reader.
+    pub fn compared(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.0 -= 1;
+        self.changed.notify_all();
+    }
+
+    /// Releases the reader for good, once the comparison no longer consumes its checks.
+    pub fn close(&self) {
+        self.state.lock().unwrap().1 = true;
+        self.changed.notify_all();
+    }
+}
+
+/// Throttles of the readers of a comparison, closed when it returns, however it does.
+#[derive(Default)]
+pub struct Throttles(pub Vec<Arc<Throttle>>);
+
+impl Drop for Throttles {
+    fn drop(&mut self) {
+        for throttle in &self.0 {
+            throttle.close();
+        }
+    }
+}
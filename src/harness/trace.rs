@@ -0,0 +1,252 @@
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{BufRead, BufWriter, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use regex::Regex;
+
+use super::{
+    Error,
+    protocol::{Line, StreamProtocol},
+};
+
+/// First line of a trace file, followed by the name of the recorded command.
+const TRACE_HEADER: &str = "# runtime-diff trace:";
+
+/// A message of a recorded stream, holding the line as the harness compares it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Entry {
+    Check(String),
+    Breadcumb(String),
+}
+
+impl Entry {
+    pub fn text(&self) -> &str {
+        match self {
+            Entry::Check(text) | Entry::Breadcumb(text) => text,
+        }
+    }
+
+    pub fn is_check(&self) -> bool {
+        matches!(self, Entry::Check(_))
+    }
+
+    /// Text after the prefix.
+    pub fn payload(&self) -> &str {
+        let prefix = match self {
+            Entry::Check(_) => crate::CHECK_PREFIX,
+            Entry::Breadcumb(_) => crate::BREADCUMB_PREFIX,
+        };
+        let text = self.text();
+        text.strip_prefix(prefix).unwrap_or(text).trim_start()
+    }
+
+    /// Name of a check, none for breadcrumbs.
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Entry::Check(_) => Some(check_name(self.payload())),
+            Entry::Breadcumb(_) => None,
+        }
+    }
+}
+
+/// Name of a check given its payload: the first word of it.
+pub fn check_name(payload: &str) -> &str {
+    payload.split_whitespace().next().unwrap_or("")
+}
+
+/// The messages of a command, recorded to be examined or compared after the run.
+#[derive(Debug, Clone)]
+pub struct Trace {
+    pub name: String,
+    pub entries: Vec<Entry>,
+    /// Time since the start of the command at which every entry was received, if recorded
+    pub timestamps: Vec<Option<Duration>>,
+}
+
+impl Trace {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let reader = std::io::BufReader::new(File::open(path)?);
+        let mut trace = Trace {
+            name: path.display().to_string(),
+            entries: Vec::new(),
+            timestamps: Vec::new(),
+        };
+        // Traces hold the canonical lines, which parse as the legacy protocol
+        let mut protocol = StreamProtocol::default();
+        for (line_number, line) in reader.lines().enumerate() {
+            let line = line?;
+            if let Some(name) = line.strip_prefix(TRACE_HEADER) {
+                trace.name = name.trim().to_string();
+                continue;
+            }
+            let (timestamp, line) = match line
+                .strip_prefix('+')
+                .and_then(|line| line.split_once(' '))
+                .and_then(|(micros, line)| Some((micros.parse().ok()?, line)))
+            {
+                Some((micros, line)) => (Some(Duration::from_micros(micros)), line),
+                None => (None, line.as_str()),
+            };
+            trace.timestamps.push(timestamp);
+            match protocol.parse(line) {
+                Ok(Line::Check(check)) => trace.entries.push(Entry::Check(check)),
+                Ok(Line::Breadcumb(breadcumb)) => trace.entries.push(Entry::Breadcumb(breadcumb)),
+                _ => {
+                    return Err(Error::Parse {
+                        line: line_number + 1,
+                        message: format!("not a trace entry: '{}'", line),
+                    });
+                }
+            }
+        }
+        Ok(trace)
+    }
+
+    /// Position in the entries of the check numbered `check`, counting from 1.
+    pub fn check_position(&self, check: usize) -> Option<usize> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.is_check())
+            .nth(check.checked_sub(1)?)
+            .map(|(position, _)| position)
+    }
+}
+
+/// Writes the messages of a command to a trace file as they arrive, each line preceded
+/// by `+` and the microseconds elapsed since the writer was created.
+pub struct TraceWriter {
+    writer: BufWriter<File>,
+    start: Instant,
+}
+
+impl TraceWriter {
+    pub fn create(path: &Path, name: &str) -> Result<Self, Error> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "{} {}", TRACE_HEADER, name)?;
+        Ok(Self {
+            writer,
+            start: Instant::now(),
+        })
+    }
+
+    pub fn write(&mut self, entry: &Entry) -> Result<(), Error> {
+        writeln!(
+            self.writer,
+            "+{} {}",
+            self.start.elapsed().as_micros(),
+            entry.text()
+        )?;
+        // The harness may exit right after comparing a check, before the writer is dropped
+        if entry.is_check() {
+            self.writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Path of the trace of command `name` of the group labeled `label` inside `dir`.
+pub fn trace_path(dir: &Path, label: &str, name: &str) -> PathBuf {
+    dir.join(label.replace('/', "_"))
+        .join(format!("{}.trace", name.replace('/', "_")))
+}
+
+/// Selection of the entries of a trace to print.
+pub struct Query {
+    /// Only entries matching the pattern, with their context
+    pub grep: Option<Regex>,
+    /// Only entries around the check with the given number
+    pub around_check: Option<usize>,
+    /// Entries printed before and after every selected one
+    pub context: usize,
+}
+
+impl Query {
+    /// Returns the disjoint, ordered ranges of entries selected in `trace`.
+    pub fn regions(&self, trace: &Trace) -> Result<Vec<Range<usize>>, Error> {
+        let len = trace.entries.len();
+        let around = |position: usize| {
+            position.saturating_sub(self.context)..(position + self.context + 1).min(len)
+        };
+
+        let window = match self.around_check {
+            Some(check) => around(trace.check_position(check).ok_or_else(|| {
+                Error::Config(format!("the trace holds fewer than {} checks", check))
+            })?),
+            None => 0..len,
+        };
+
+        let mut regions: Vec<Range<usize>> = Vec::new();
+        let selected: Vec<Range<usize>> = match &self.grep {
+            Some(grep) => window
+                .clone()
+                .filter(|position| grep.is_match(trace.entries[*position].text()))
+                .map(around)
+                .collect(),
+            None => vec![window.clone()],
+        };
+        for region in selected {
+            let region = region.start.max(window.start)..region.end.min(window.end);
+            match regions.last_mut() {
+                Some(last) if region.start <= last.end => last.end = last.end.max(region.end),
+                _ => regions.push(region),
+            }
+        }
+        Ok(regions)
+    }
+}
+
+/// Writes the selected regions of `trace`, numbering entries and checks and highlighting
+/// the entries matching the pattern.
+pub fn write_regions(
+    out: &mut impl std::fmt::Write,
+    trace: &Trace,
+    query: &Query,
+    regions: &[Range<usize>],
+) -> std::fmt::Result {
+    let mut check_numbers = Vec::with_capacity(trace.entries.len());
+    let mut checks = 0;
+    for entry in &trace.entries {
+        if entry.is_check() {
+            checks += 1;
+        }
+        check_numbers.push(checks);
+    }
+
+    for (i, region) in regions.iter().enumerate() {
+        if i > 0 {
+            writeln!(out, "\x1b[1;34m--\x1b[0m")?;
+        }
+        for position in region.clone() {
+            let entry = &trace.entries[position];
+            let mut line = String::new();
+            write!(line, "\x1b[1;34m{:>8}\x1b[0m ", position + 1)?;
+            if entry.is_check() {
+                write!(line, "\x1b[1;33m#{:<7}\x1b[0m ", check_numbers[position])?;
+            } else {
+                write!(line, "{:8} ", "")?;
+            }
+            let highlighted = query
+                .grep
+                .as_ref()
+                .is_some_and(|grep| grep.is_match(entry.text()))
+                || query
+                    .around_check
+                    .is_some_and(|check| entry.is_check() && check_numbers[position] == check);
+            if highlighted {
+                writeln!(out, "{}\x1b[1;37m{}\x1b[0m", line, entry.text())?;
+            } else {
+                writeln!(out, "{}{}", line, entry.text())?;
+            }
+        }
+    }
+    Ok(())
+}
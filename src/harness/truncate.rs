@@ -0,0 +1,38 @@
+use std::{borrow::Cow, path::Path};
+
+/// Bytes of a message shown in human-readable output by default.
+pub const DEFAULT_MAX_PAYLOAD_BYTES: &str = "4096";
+
+/// `text` cut to `budget` bytes if it is longer, followed by the digest of the whole text
+/// and where it can be read in full, as given by `location`. A budget of 0 disables
+/// truncation.
+pub fn within_budget<'a>(
+    text: &'a str,
+    budget: usize,
+    location: impl FnOnce() -> Option<String>,
+) -> Cow<'a, str> {
+    if budget == 0 || text.len() <= budget {
+        return Cow::Borrowed(text);
+    }
+    let mut end = budget;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    Cow::Owned(format!(
+        "{}… \x1b[1;33m[{} bytes, digest {:016x}, full text in {}]\x1b[0m",
+        &text[..end],
+        text.len(),
+        crate::digest(text.as_bytes()),
+        location().unwrap_or_else(|| "no saved file".to_string())
+    ))
+}
+
+/// Saves the full `text` of a truncated message in `dir`, returning its path.
+pub fn save_full_text(dir: &Path, text: &str) -> Option<String> {
+    let path = dir.join(format!(
+        "message-{:016x}.txt",
+        crate::digest(text.as_bytes())
+    ));
+    std::fs::write(&path, format!("{}\n", text)).ok()?;
+    Some(path.display().to_string())
+}
@@ -0,0 +1,188 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Write,
+    sync::Mutex,
+};
+
+use super::{matrix, testfile::TestGroup, trace};
+
+/// Check site of a command in a group: the group label without the repeat, the command
+/// and the check name.
+type Site = (String, String, String);
+
+/// Site whose checks differed between two repeats of the same command.
+#[derive(Debug, Clone)]
+pub struct UnstableSite {
+    /// Occurrence of the check at the site, counting from 1
+    pub occurrence: usize,
+    /// Check of the first repeat reaching it, then the one differing from it
+    pub values: (String, String),
+}
+
+#[derive(Default)]
+struct Sites {
+    /// Checks of the first repeat reaching every occurrence of a site
+    reference: HashMap<Site, Vec<String>>,
+    /// Occurrences of every site seen so far in the current run of each group, by its
+    /// full label
+    occurrences: HashMap<(String, Site), usize>,
+    unstable: BTreeMap<Site, UnstableSite>,
+    /// Sites the commands of a group diverged at, by group label without the repeat
+    divergences: BTreeSet<(String, String)>,
+}
+
+/// Check sites of every command compared across `--repeat` runs of its group, telling the
+/// sites whose values change between runs of the same command apart from divergences
+/// between implementations. Holds the checks of the first repeat.
+#[derive(Default)]
+pub struct UnstableSites(Mutex<Sites>);
+
+/// Run of a group the checks of its commands are recorded for.
+#[derive(Debug, Clone)]
+pub struct SiteRun {
+    label: String,
+    /// Label without the repeat variable
+    repeated: String,
+}
+
+/// Label of `group` without the repeat variable, the same for all its repeats.
+fn repeated_label(group: &TestGroup) -> String {
+    let mut label = group.name.clone();
+    for (variable, value) in &group.variables {
+        if variable != matrix::REPEAT_VARIABLE {
+            label.push_str(&format!(" {}={}", variable, value));
+        }
+    }
+    label
+}
+
+fn check_name(check: &str) -> &str {
+    let payload = check.strip_prefix(crate::CHECK_PREFIX).unwrap_or(check);
+    trace::check_name(payload.trim_start())
+}
+
+impl UnstableSites {
+    /// Starts a run of `group`, counting the occurrences of its sites from the start,
+    /// e.g. again when a divergence is confirmed.
+    pub fn start(&self, group: &TestGroup) -> SiteRun {
+        let run = SiteRun {
+            label: group.label(),
+            repeated: repeated_label(group),
+        };
+        let mut sites = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        sites
+            .occurrences
+            .retain(|(label, _), _| *label != run.label);
+        run
+    }
+
+    /// Records a `check` emitted by a command in a run of its group, compared or not.
+    pub fn record(&self, run: &SiteRun, command: &str, check: &str) {
+        let site = (
+            run.repeated.clone(),
+            command.to_string(),
+            check_name(check).to_string(),
+        );
+        let mut sites = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let occurrence = sites
+            .occurrences
+            .entry((run.label.clone(), site.clone()))
+            .or_default();
+        *occurrence += 1;
+        let occurrence = *occurrence;
+
+        let reference = sites.reference.entry(site.clone()).or_default();
+        if reference.len() < occurrence {
+            reference.push(check.to_string());
+            return;
+        }
+        if reference[occurrence - 1] != check {
+            let values = (reference[occurrence - 1].clone(), check.to_string());
+            sites
+                .unstable
+                .entry(site)
+                .or_insert(UnstableSite { occurrence, values });
+        }
+    }
+
+    /// Records that the commands of `group` diverged at the aligned `checks`.
+    pub fn record_divergence(&self, group: &TestGroup, checks: &[Option<String>]) {
+        let mut sites = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        for check in checks.iter().flatten() {
+            sites
+                .divergences
+                .insert((repeated_label(group), check_name(check).to_string()));
+        }
+    }
+}
+
+/// Ignore rule silencing the checks named `name`.
+fn ignore_rule(name: &str) -> String {
+    format!("^{}(\\s|$)", regex::escape(name))
+}
+
+/// Writes the unstable sites with the ignore rules silencing them, then the divergences
+/// between implementations, telling which happened at unstable sites.
+pub fn write_unstable(out: &mut impl Write, sites: &UnstableSites) -> std::fmt::Result {
+    let sites = sites.0.lock().unwrap_or_else(|e| e.into_inner());
+    if sites.unstable.is_empty() {
+        writeln!(
+            out,
+            "\x1b[1;32mNo check site changed between repeats of the same command\x1b[0m"
+        )?;
+    } else {
+        writeln!(
+            out,
+            "\x1b[1;34mUnstable sites, changing between repeats of the same command:\x1b[0m"
+        )?;
+        for ((group, command, name), site) in &sites.unstable {
+            writeln!(
+                out,
+                "  \x1b[1;33m{}\x1b[0m of '{}' in {}, occurrence {}:",
+                name, command, group, site.occurrence
+            )?;
+            writeln!(out, "    {}", site.values.0)?;
+            writeln!(out, "    {}", site.values.1)?;
+        }
+        let rules: BTreeSet<_> = sites
+            .unstable
+            .keys()
+            .map(|(_, _, name)| ignore_rule(name))
+            .collect();
+        writeln!(out, "\x1b[1;34mSuggested ignore rules:\x1b[0m")?;
+        writeln!(out, "ignore:")?;
+        for rule in rules {
+            writeln!(out, "    {}", rule)?;
+        }
+    }
+
+    if sites.divergences.is_empty() {
+        return Ok(());
+    }
+    writeln!(
+        out,
+        "\x1b[1;34mSites the implementations diverged at:\x1b[0m"
+    )?;
+    for (group, name) in &sites.divergences {
+        let unstable = sites
+            .unstable
+            .keys()
+            .any(|(unstable_group, _, unstable_name)| {
+                unstable_group == group && unstable_name == name
+            });
+        if unstable {
+            writeln!(
+                out,
+                "  {} in {} \x1b[1;33m(unstable within a command, likely nondeterminism)\x1b[0m",
+                name, group
+            )?;
+        } else {
+            writeln!(
+                out,
+                "  {} in {} \x1b[1;31m(stable across repeats)\x1b[0m",
+                name, group
+            )?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,100 @@
+use std::{process::Command, str::FromStr};
+
+use super::{Error, sandbox::Sandbox};
+
+/// Environment limiting the common thread pools to a single thread.
+const SINGLE_THREAD_ENV: &[(&str, &str)] = &[
+    ("RAYON_NUM_THREADS", "1"),
+    ("OMP_NUM_THREADS", "1"),
+    ("TOKIO_WORKER_THREADS", "1"),
+];
+
+/// Tool every test command runs under, to rule out nondeterminism caused by scheduling;
+/// either a preset or a command prefix.
+#[derive(Debug, Clone)]
+pub struct Wrapper {
+    prefix: String,
+    env: Vec<(String, String)>,
+}
+
+impl FromStr for Wrapper {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (prefix, env) = match s.trim() {
+            "" => return Err(Error::Config("empty wrapper".to_string())),
+            // Records the run, scheduling every thread on a single core
+            "rr" => ("rr record -h", &[][..]),
+            // Records the run with randomized scheduling, to shake out races
+            "rr-chaos" => ("rr record --chaos", &[][..]),
+            "single-core" => ("taskset -c 0", SINGLE_THREAD_ENV),
+            prefix => (prefix, &[][..]),
+        };
+        Ok(Self {
+            prefix: prefix.to_string(),
+            env: env
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        })
+    }
+}
+
+/// Command switching to the user given after it.
+const USER_SWITCH: &str = "sudo -n --preserve-env -u";
+/// Command running the command after it in new user and network namespaces, with no
+/// network interface up.
+const NETWORK_ISOLATION: &str = "unshare --map-root-user --net --";
+
+/// Hidden subcommand of the harness restricting itself to a sandbox profile, then
+/// executing the command after it.
+pub const SANDBOX_EXEC: &str = "sandbox-exec";
+
+/// Shell command running `script`, as `user`, under `wrapper` and in `sandbox` if any,
+/// offline if `isolate_network`.
+pub fn shell_command(
+    script: &str,
+    user: Option<&str>,
+    isolate_network: bool,
+    sandbox: Option<&Sandbox>,
+    wrapper: Option<&Wrapper>,
+) -> Result<Command, Error> {
+    let mut command = Command::new("sh");
+    let mut prefixes = Vec::new();
+    if let Some(wrapper) = wrapper {
+        prefixes.push(wrapper.prefix.clone());
+        command.envs(wrapper.env.iter().map(|(name, value)| (name, value)));
+    }
+    if isolate_network {
+        prefixes.push(NETWORK_ISOLATION.to_string());
+    }
+    if let Some(user) = user {
+        prefixes.push(format!("{} {} --", USER_SWITCH, user));
+    }
+    if let Some(sandbox) = sandbox {
+        // Innermost, so that nothing the command starts escapes the restrictions
+        let harness = std::env::current_exe()?;
+        prefixes.push(format!(
+            "{} {} --profile {} --",
+            shell_quote(&harness.to_string_lossy()),
+            SANDBOX_EXEC,
+            shell_quote(&sandbox.to_string())
+        ));
+    }
+
+    if prefixes.is_empty() {
+        command.arg("-c").arg(script);
+    } else {
+        command
+            .arg("-c")
+            .arg(format!("{} sh -c \"$1\"", prefixes.join(" ")))
+            .arg("sh")
+            .arg(script);
+    }
+    Ok(command)
+}
+
+/// `text` quoted as a single shell word.
+pub fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
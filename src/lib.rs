@@ -1,15 +1,319 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+mod emit;
+#[cfg(feature = "harness")]
+pub mod harness;
+pub mod sink;
+
+#[doc(hidden)]
+pub use emit::__emit;
+pub use emit::{Batching, batching, flush, init_from_env, set_sink, transaction};
+pub use sink::Sink;
+
+/// Prefix marking a breadcrumb line in the program output.
+pub const BREADCUMB_PREFIX: &str = "BREADCUMB:";
+/// Prefix marking a runtime check line in the program output.
+pub const CHECK_PREFIX: &str = "RUNTIME CHECK:";
+/// Marker opening a region whose checks the harness records as breadcrumbs only.
+pub const DISABLE_PREFIX: &str = "RUNTIME DIFF DISABLE";
+/// Marker closing a region opened by [`DISABLE_PREFIX`].
+pub const ENABLE_PREFIX: &str = "RUNTIME DIFF ENABLE";
+/// Prefix of a line carrying a batch of messages, separated by [`BATCH_SEPARATOR`].
+pub const BATCH_PREFIX: &str = "RUNTIME DIFF BATCH:";
+/// Separator of the messages of a batch line.
+pub const BATCH_SEPARATOR: char = '\x1e';
+/// Prefix of the line announcing the protocol version and capabilities of the library.
+pub const HELLO_PREFIX: &str = "RUNTIME DIFF HELLO:";
+
+/// Version of the protocol spoken by this library; version 1 is plain prefixed lines,
+/// version 3 adds batches, version 4 tags every message with the process writing it.
+pub const PROTOCOL_VERSION: u32 = 4;
+/// Capabilities announced in the hello line.
+pub const CAPABILITIES: &[&str] = &[SEQUENCE_CAPABILITY, PID_CAPABILITY];
+/// Capability of numbering every message, letting the harness detect lost or reordered ones.
+pub const SEQUENCE_CAPABILITY: &str = "seq";
+/// Capability of tagging every message with the PID of its writer, letting the harness
+/// split the messages of forked processes sharing stdout into streams of their own.
+pub const PID_CAPABILITY: &str = "pid";
+/// Capability of sending the messages after the hello line zstd compressed, once the
+/// harness accepts it.
+pub const ZSTD_CAPABILITY: &str = "zstd";
+/// Prefix of a line carrying a base64 encoded chunk of the zstd stream of the messages.
+pub const ZSTD_PREFIX: &str = "RUNTIME DIFF ZSTD:";
+
+/// Environment variable holding the highest protocol version the harness understands.
+pub const PROTOCOL_ENV: &str = "RUNTIME_DIFF_PROTOCOL";
+/// Environment variable holding the name of the test command this process runs as.
+pub const ROLE_ENV: &str = "RUNTIME_DIFF_ROLE";
+/// Environment variable holding the index of this process among its peers.
+pub const PEER_INDEX_ENV: &str = "RUNTIME_DIFF_PEER_INDEX";
+/// Environment variable holding the number of processes being compared.
+pub const PEER_COUNT_ENV: &str = "RUNTIME_DIFF_PEER_COUNT";
+/// Environment variable holding the directory where snapshots of this process are written.
+pub const SNAPSHOT_DIR_ENV: &str = "RUNTIME_DIFF_SNAPSHOT_DIR";
+/// Environment variable holding the compression the harness accepts, [`ZSTD_CAPABILITY`].
+pub const COMPRESSION_ENV: &str = "RUNTIME_DIFF_COMPRESSION";
+/// Environment variable holding the named pipe [`init_from_env`] routes messages to.
+pub const SINK_ENV: &str = "RUNTIME_DIFF_SINK";
+/// Environment variable holding the number of checks a process resumed from a checkpoint
+/// skips, emitting a [`RESUME_CHECK`] in place of the last one.
+pub const RESUME_ENV: &str = "RUNTIME_DIFF_RESUME_AT";
+/// Environment variable holding the instrumentation level, `debug` enabling debug breadcrumbs.
+pub const LEVEL_ENV: &str = "RUNTIME_DIFF_LEVEL";
+/// Value of the level environment variable enabling debug breadcrumbs.
+pub const DEBUG_LEVEL: &str = "debug";
+/// Leading word of the check emitted for a binary snapshot.
+pub const SNAPSHOT_CHECK: &str = "SNAPSHOT";
+/// Leading word of the check emitted for an array of numbers.
+pub const ARRAY_CHECK: &str = "ARRAY";
+/// Leading word of the check the harness synthesizes from the exit status of a command.
+pub const EXIT_CHECK: &str = "EXIT";
+/// Leading word of the check emitted for an image.
+pub const IMAGE_CHECK: &str = "IMAGE";
+/// Leading word of the check a resumed process emits once it skipped the checks before its
+/// checkpoint, with their number and rolling digest.
+pub const RESUME_CHECK: &str = "RESUMED";
+
+/// Name of the test command this process was launched as, if running under the harness.
+pub fn role() -> Option<&'static str> {
+    static ROLE: OnceLock<Option<String>> = OnceLock::new();
+    ROLE.get_or_init(|| std::env::var(ROLE_ENV).ok()).as_deref()
+}
+
+/// Position of this process among the commands being compared, if running under the harness.
+pub fn peer_index() -> Option<usize> {
+    static INDEX: OnceLock<Option<usize>> = OnceLock::new();
+    *INDEX.get_or_init(|| std::env::var(PEER_INDEX_ENV).ok()?.parse().ok())
+}
+
+/// Number of commands being compared, if running under the harness.
+pub fn peer_count() -> Option<usize> {
+    static COUNT: OnceLock<Option<usize>> = OnceLock::new();
+    *COUNT.get_or_init(|| std::env::var(PEER_COUNT_ENV).ok()?.parse().ok())
+}
+
+/// Returns true if debug breadcrumbs are enabled, the harness setting the level when it
+/// re-runs a diverging group.
+pub fn debug_enabled() -> bool {
+    static DEBUG: OnceLock<bool> = OnceLock::new();
+    *DEBUG.get_or_init(|| std::env::var(LEVEL_ENV).is_ok_and(|level| level == DEBUG_LEVEL))
+}
+
+/// Returns true if this process was launched as the test command named `name`.
+pub fn is_role(name: &str) -> bool {
+    role() == Some(name)
+}
+
+/// 64-bit FNV-1a digest, stable across builds and platforms.
+pub fn digest(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Digest of a sequence of checks, given the digest of the previous ones, 0 for none, and
+/// the payload of the next one.
+pub fn rolling_digest(previous: u64, payload: &str) -> u64 {
+    let mut bytes = previous.to_le_bytes().to_vec();
+    bytes.extend_from_slice(payload.trim_start().as_bytes());
+    digest(&bytes)
+}
+
+/// Path of the file holding the snapshot with the given id inside `dir`.
+pub fn snapshot_path(dir: &Path, id: &str) -> PathBuf {
+    let file_name: String = id
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '_',
+        })
+        .collect();
+    dir.join(format!("{}.bin", file_name))
+}
+
+/// Emits a check carrying the digest of `data`, identified by `name` and the number
+/// of snapshots with that name taken so far.
+///
+/// When running under the harness the full snapshot is also written to this process'
+/// snapshot directory, so that a mismatch can be reported with a byte-level diff.
+pub fn snapshot(name: &str, data: &[u8]) {
+    record_blob(SNAPSHOT_CHECK, name, &format!("len={}", data.len()), data);
+}
+
+/// Emits a check for an array of floats, which the harness compares element by element
+/// within the tolerance configured for `name`.
+pub fn array(name: &str, values: &[f32]) {
+    let data: Vec<u8> = values
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect();
+    record_blob(
+        ARRAY_CHECK,
+        name,
+        &format!("f32 len={}", values.len()),
+        &data,
+    );
+}
+
+/// Like [`array`], for double precision values.
+pub fn array_f64(name: &str, values: &[f64]) {
+    let data: Vec<u8> = values
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect();
+    record_blob(
+        ARRAY_CHECK,
+        name,
+        &format!("f64 len={}", values.len()),
+        &data,
+    );
+}
+
+/// Emits a check for an image of `width` by `height` pixels of `channels` bytes each,
+/// stored row by row, which the harness compares pixel by pixel within a tolerance.
+pub fn image(name: &str, width: usize, height: usize, channels: usize, pixels: &[u8]) {
+    record_blob(
+        IMAGE_CHECK,
+        name,
+        &format!("u8 {}x{}x{}", width, height, channels),
+        pixels,
+    );
+}
+
+/// Writes `data` to the snapshot directory, if running under the harness, and emits a
+/// `kind` check describing it with `shape` and its digest.
+///
+/// Inside a [`transaction`] both only happen once it commits, so that snapshots keep the
+/// same numbering whether speculative work was rolled back or never done.
+fn record_blob(kind: &'static str, name: &str, shape: &str, data: &[u8]) {
+    let (name, shape, data) = (name.to_string(), shape.to_string(), data.to_vec());
+    emit::emit_deferred(CHECK_PREFIX, move || write_blob(kind, &name, &shape, &data));
+}
+
+/// Numbers and writes the snapshot of `data`, returning the payload of its check.
+fn write_blob(kind: &str, name: &str, shape: &str, data: &[u8]) -> String {
+    static COUNTERS: Mutex<Option<HashMap<String, usize>>> = Mutex::new(None);
+
+    let index = {
+        let mut counters = COUNTERS.lock().unwrap_or_else(|e| e.into_inner());
+        let counter = counters
+            .get_or_insert_with(HashMap::new)
+            .entry(name.to_string())
+            .or_insert(0);
+        *counter += 1;
+        *counter
+    };
+    let id = format!("{}#{}", name, index);
+
+    if let Some(dir) = std::env::var_os(SNAPSHOT_DIR_ENV) {
+        let path = snapshot_path(Path::new(&dir), &id);
+        if let Err(e) = std::fs::write(&path, data) {
+            eprintln!(
+                "runtime-diff: cannot write snapshot {}: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+
+    format!("{} {} {} digest={:016x}", kind, id, shape, digest(data))
+}
+
 #[macro_export]
 macro_rules! breadcumb {
     ($($arg:tt)*) => {
-        println!("BREADCUMB: {}", format_args!($($arg)*));
+        $crate::__emit($crate::BREADCUMB_PREFIX, format_args!($($arg)*));
+    };
+}
+
+/// Emits a breadcrumb only when debug breadcrumbs are enabled.
+#[macro_export]
+macro_rules! debug_breadcumb {
+    ($($arg:tt)*) => {
+        if $crate::debug_enabled() {
+            $crate::__emit($crate::BREADCUMB_PREFIX, format_args!($($arg)*));
+        }
+    };
+}
+
+/// Stops the comparison of the following checks, e.g. in a legacy code path known to
+/// diverge, until [`enable_checks!`]; the harness still records them as breadcrumbs.
+/// Regions nest.
+#[macro_export]
+macro_rules! disable_checks {
+    () => {
+        $crate::__emit($crate::DISABLE_PREFIX, format_args!(""));
+    };
+    ($($arg:tt)+) => {
+        $crate::__emit($crate::DISABLE_PREFIX, format_args!($($arg)+));
+    };
+}
+
+/// Closes the region opened by the last [`disable_checks!`].
+#[macro_export]
+macro_rules! enable_checks {
+    () => {
+        $crate::__emit($crate::ENABLE_PREFIX, format_args!(""));
+    };
+    ($($arg:tt)+) => {
+        $crate::__emit($crate::ENABLE_PREFIX, format_args!($($arg)+));
     };
 }
 
 #[macro_export]
 macro_rules! runtime_check {
     ($($arg:tt)*) => {
-        println!("RUNTIME CHECK: {}", format_args!($($arg)*));
+        $crate::__emit($crate::CHECK_PREFIX, format_args!($($arg)*));
     };
     () => {
     };
 }
+
+/// Emits the value of an expression as a check named after the expression, and with the
+/// `assertions` feature panics unless it equals the expected value, so that the same
+/// instrumentation serves as an assertion in unit tests and as a check under the harness.
+///
+/// Only the actual value is emitted: under the harness the implementations are compared
+/// with each other, not with the expected value.
+#[macro_export]
+macro_rules! runtime_check_eq {
+    ($actual:expr, $expected:expr $(,)?) => {
+        $crate::__check_eq(stringify!($actual), &$actual, &$expected, None)
+    };
+    ($actual:expr, $expected:expr, $($arg:tt)+) => {
+        $crate::__check_eq(
+            stringify!($actual),
+            &$actual,
+            &$expected,
+            Some(format_args!($($arg)+)),
+        )
+    };
+}
+
+#[doc(hidden)]
+#[track_caller]
+pub fn __check_eq<T, U>(
+    expression: &str,
+    actual: &T,
+    expected: &U,
+    message: Option<std::fmt::Arguments>,
+) where
+    T: std::fmt::Debug + PartialEq<U> + ?Sized,
+    U: std::fmt::Debug + ?Sized,
+{
+    __emit(CHECK_PREFIX, format_args!("{} = {:?}", expression, actual));
+    if cfg!(feature = "assertions") && actual != expected {
+        match message {
+            Some(message) => panic!(
+                "runtime check `{}` failed: {}\n  actual: {:?}\nexpected: {:?}",
+                expression, message, actual, expected
+            ),
+            None => panic!(
+                "runtime check `{}` failed\n  actual: {:?}\nexpected: {:?}",
+                expression, actual, expected
+            ),
+        }
+    }
+}
@@ -0,0 +1,141 @@
+//! Transports carrying the protocol lines of a process to the harness.
+//!
+//! Named pipes and sockets are Unix only; wasm modules built for `wasm32-wasip1` write
+//! to stdout, or to an inherited file descriptor.
+
+#[cfg(any(unix, target_os = "wasi"))]
+use std::os::fd::OwnedFd;
+use std::{
+    fs::File,
+    io::Write,
+    sync::{Arc, Mutex},
+};
+#[cfg(unix)]
+use std::{
+    fs::OpenOptions,
+    os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+    path::{Path, PathBuf},
+};
+
+/// Destination of the messages, selected with [`set_sink`](crate::set_sink) before
+/// anything is emitted.
+///
+/// Every call writes one or more complete protocol lines, framed and compressed as
+/// negotiated with the harness, so any transport reaching it stays protocol compatible.
+/// When a write fails the messages go to stdout from then on.
+pub trait Sink: Send {
+    fn write(&mut self, text: &str) -> std::io::Result<()>;
+}
+
+/// Standard output, the default transport.
+pub struct StdoutSink;
+
+impl Sink for StdoutSink {
+    fn write(&mut self, text: &str) -> std::io::Result<()> {
+        std::io::stdout().lock().write_all(text.as_bytes())
+    }
+}
+
+/// Named pipe created by the harness, given in the `RUNTIME_DIFF_SINK` environment
+/// variable.
+#[cfg(unix)]
+pub struct FifoSink {
+    path: PathBuf,
+    file: File,
+}
+
+#[cfg(unix)]
+impl FifoSink {
+    /// Opens the pipe, failing instead of blocking if the harness does not hold it open
+    /// for reading, e.g. because it is gone.
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&path)?;
+        // Writes block again, for the harness to keep up
+        let fd = file.as_raw_fd();
+        // SAFETY: fcntl on a descriptor owned by `file`
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+        if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_NONBLOCK) } < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Self { path, file })
+    }
+}
+
+#[cfg(unix)]
+impl Sink for FifoSink {
+    /// Writes `text`, reopening the pipe once if the harness reconnected and writing the
+    /// rest of the text to it.
+    fn write(&mut self, text: &str) -> std::io::Result<()> {
+        let mut rest = text.as_bytes();
+        let mut reopened = false;
+        while !rest.is_empty() {
+            match self.file.write(rest) {
+                Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+                Ok(written) => rest = &rest[written..],
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(_) if !reopened => {
+                    reopened = true;
+                    *self = Self::open(&self.path)?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Any writer, such as an inherited file descriptor or a socket.
+pub struct WriterSink<W>(pub W);
+
+#[cfg(any(unix, target_os = "wasi"))]
+impl WriterSink<File> {
+    /// Writes to a file descriptor, such as one the harness keeps open for the process.
+    pub fn from_fd(fd: OwnedFd) -> Self {
+        Self(File::from(fd))
+    }
+}
+
+#[cfg(unix)]
+impl WriterSink<std::os::unix::net::UnixStream> {
+    /// Connects to a Unix socket.
+    pub fn connect(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        std::os::unix::net::UnixStream::connect(path).map(Self)
+    }
+}
+
+impl<W: Write + Send> Sink for WriterSink<W> {
+    fn write(&mut self, text: &str) -> std::io::Result<()> {
+        self.0.write_all(text.as_bytes())?;
+        self.0.flush()
+    }
+}
+
+/// Keeps the lines in memory, for tests of the instrumentation itself; clones share
+/// the same buffer.
+#[derive(Clone, Default)]
+pub struct MemorySink(Arc<Mutex<String>>);
+
+impl MemorySink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Text written so far.
+    pub fn contents(&self) -> String {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+impl Sink for MemorySink {
+    fn write(&mut self, text: &str) -> std::io::Result<()> {
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push_str(text);
+        Ok(())
+    }
+}